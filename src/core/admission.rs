@@ -0,0 +1,226 @@
+use super::{ChainState, MemPoolError, StateTransitionError, Transaction};
+
+/// What an admission policy can look at besides the transaction itself.
+pub struct AdmissionContext<'a> {
+    pub state: &'a ChainState,
+    /// Pending transactions of the same sender by increasing sequence,
+    /// without the one the new transaction would replace.
+    pub sender_pending: Vec<&'a Transaction>,
+}
+
+/// Decides whether the mempool accepts a transaction that passed the
+/// structural checks (id, chain, expiry and size).
+pub trait AdmissionPolicy: Send {
+    fn check(&self, tx: &Transaction, context: &AdmissionContext) -> Result<(), MemPoolError>;
+
+    /// Fee a transaction of `size` bytes must pay at least, regardless of
+    /// how full the mempool is.
+    fn min_relay_fee(&self, _size: usize) -> u64 {
+        0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StandardAdmissionPolicy {
+    pub verify_signatures: bool,
+    /// Requires the sequence to follow the chain state and the pending
+    /// transactions of the sender, and the balance to cover all of them.
+    pub check_chain_state: bool,
+    /// Minimum fee per 1000 bytes, rounded up.
+    pub min_relay_fee_per_kb: u64,
+    pub max_txs_per_sender: usize,
+}
+
+impl Default for StandardAdmissionPolicy {
+    fn default() -> Self {
+        StandardAdmissionPolicy {
+            verify_signatures: true,
+            check_chain_state: true,
+            min_relay_fee_per_kb: 1,
+            max_txs_per_sender: 25,
+        }
+    }
+}
+
+impl StandardAdmissionPolicy {
+    fn check_against_state(&self, tx: &Transaction, context: &AdmissionContext) -> Result<(), StateTransitionError> {
+        let mut expected_sequence = context.state.get_next_sequence(&tx.from);
+        let mut pending_spent: u64 = 0;
+        for pending in &context.sender_pending {
+            if pending.sequence != expected_sequence || pending.sequence >= tx.sequence {
+                continue;
+            }
+            expected_sequence += 1;
+            pending_spent = pending_spent.saturating_add(pending.amount.saturating_add(pending.fee));
+        }
+
+        if tx.sequence != expected_sequence {
+            return Err(StateTransitionError::BadSequence {
+                address: tx.from.clone(),
+                expected: expected_sequence,
+                found: tx.sequence,
+            });
+        }
+
+        let balance = context.state.get_balance(&tx.from).saturating_sub(pending_spent);
+        let required = tx.amount.saturating_add(tx.fee);
+        if balance < required {
+            return Err(StateTransitionError::InsufficientBalance {
+                address: tx.from.clone(),
+                balance,
+                required,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl AdmissionPolicy for StandardAdmissionPolicy {
+    fn min_relay_fee(&self, size: usize) -> u64 {
+        (size as u64).saturating_mul(self.min_relay_fee_per_kb).div_ceil(1000)
+    }
+
+    fn check(&self, tx: &Transaction, context: &AdmissionContext) -> Result<(), MemPoolError> {
+        if tx.is_coinbase() {
+            return Err(MemPoolError::Coinbase { id: tx.id.clone() });
+        }
+
+        if self.verify_signatures {
+            tx.verify()?;
+        }
+
+        let required_fee = self.min_relay_fee(tx.size());
+        if tx.fee < required_fee {
+            return Err(MemPoolError::FeeTooLow {
+                required_fee,
+                found_fee: tx.fee,
+            });
+        }
+
+        if context.sender_pending.len() >= self.max_txs_per_sender {
+            return Err(MemPoolError::TooManyFromSender {
+                address: tx.from.clone(),
+                max: self.max_txs_per_sender,
+            });
+        }
+
+        if self.check_chain_state {
+            self.check_against_state(tx, context)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod admission_test {
+    use crate::core::{
+        AdmissionPolicy, ChainState, MemPoolError, StandardAdmissionPolicy, StateTransitionError,
+        Transaction, TransactionValidationError, WalletKeyPair,
+    };
+
+    use super::AdmissionContext;
+
+    fn signed_tx(sender: &WalletKeyPair, amount: u64, fee: u64, sequence: u64) -> Transaction {
+        let mut tx = Transaction::new(sender.address(), "to_address".to_string(), amount, fee, sequence, 1);
+        tx.sign(&sender.secret_key);
+        tx
+    }
+
+    #[test]
+    fn unsigned_tx_is_rejected() {
+        let policy = StandardAdmissionPolicy::default();
+        let sender = WalletKeyPair::new();
+        let tx = Transaction::new(sender.address(), "to_address".to_string(), 10, 10, 0, 1);
+        let state = ChainState::new();
+        let context = AdmissionContext {
+            state: &state,
+            sender_pending: Vec::new(),
+        };
+
+        assert!(matches!(
+            policy.check(&tx, &context),
+            Err(MemPoolError::InvalidTransaction(TransactionValidationError::MissingSignature { .. }))
+        ));
+    }
+
+    #[test]
+    fn coinbase_tx_is_rejected_even_without_other_checks() {
+        let policy = StandardAdmissionPolicy {
+            verify_signatures: false,
+            check_chain_state: false,
+            min_relay_fee_per_kb: 0,
+            ..StandardAdmissionPolicy::default()
+        };
+        let coinbase = Transaction::coinbase("miner_address".to_string(), 50, 1, 1);
+        let state = ChainState::new();
+        let context = AdmissionContext {
+            state: &state,
+            sender_pending: Vec::new(),
+        };
+
+        assert!(matches!(
+            policy.check(&coinbase, &context),
+            Err(MemPoolError::Coinbase { id }) if id == coinbase.id
+        ));
+    }
+
+    #[test]
+    fn fee_below_min_relay_fee_is_rejected() {
+        let policy = StandardAdmissionPolicy {
+            verify_signatures: false,
+            check_chain_state: false,
+            min_relay_fee_per_kb: 1000,
+            ..StandardAdmissionPolicy::default()
+        };
+        let tx = Transaction::new("from_address".to_string(), "to_address".to_string(), 10, 10, 0, 1);
+        let state = ChainState::new();
+        let context = AdmissionContext {
+            state: &state,
+            sender_pending: Vec::new(),
+        };
+
+        assert!(matches!(
+            policy.check(&tx, &context),
+            Err(MemPoolError::FeeTooLow { required_fee, found_fee: 10 }) if required_fee == tx.size() as u64
+        ));
+    }
+
+    #[test]
+    fn pending_txs_of_the_sender_count_against_sequence_balance_and_cap() {
+        let sender = WalletKeyPair::new();
+        let state = ChainState::new();
+
+        let first = signed_tx(&sender, 10, 1, 0);
+        let context = AdmissionContext {
+            state: &state,
+            sender_pending: Vec::new(),
+        };
+        assert!(matches!(
+            StandardAdmissionPolicy::default().check(&first, &context),
+            Err(MemPoolError::InvalidForState(StateTransitionError::InsufficientBalance { .. }))
+        ));
+
+        let policy = StandardAdmissionPolicy {
+            check_chain_state: false,
+            max_txs_per_sender: 1,
+            ..StandardAdmissionPolicy::default()
+        };
+        let second = signed_tx(&sender, 10, 1, 1);
+        let context = AdmissionContext {
+            state: &state,
+            sender_pending: vec![&first],
+        };
+        assert!(matches!(
+            policy.check(&second, &context),
+            Err(MemPoolError::TooManyFromSender { max: 1, .. })
+        ));
+
+        let gap = signed_tx(&sender, 10, 1, 2);
+        assert!(matches!(
+            StandardAdmissionPolicy::default().check(&gap, &context),
+            Err(MemPoolError::InvalidForState(StateTransitionError::BadSequence { expected: 1, found: 2, .. }))
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+use chrono::Utc;
+
+/// Source of the current time, in seconds since the unix epoch.
+pub trait Clock: Send {
+    fn now(&self) -> i64;
+    fn clone_dyn(&self) -> Box<dyn Clock>;
+}
+
+impl Clone for Box<dyn Clock> {
+    fn clone(&self) -> Self {
+        self.clone_dyn()
+    }
+}
+
+#[derive(Clone)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Clock> {
+        Box::new(self.clone())
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time, so a test
+/// can keep a handle and move the time of the `History` that owns the other one.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> ManualClock {
+        ManualClock {
+            now: Arc::new(AtomicI64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Clock> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod clock_test {
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock_clones_share_the_same_time() {
+        let clock = ManualClock::new(100);
+        let boxed: Box<dyn Clock> = Box::new(clock.clone());
+
+        clock.advance(20);
+        assert_eq!(120, boxed.now());
+
+        clock.set(5);
+        assert_eq!(5, boxed.clone().now());
+    }
+}
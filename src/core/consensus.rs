@@ -0,0 +1,216 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::ConsensusParamsError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RewardSchedule {
+    pub initial_reward: u64,
+    pub halving_interval: u64,
+}
+
+/// Consensus rules of a chain. Every node of the same network must run with
+/// the same values, otherwise they will disagree on which blocks are valid.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// Identifier of the network, part of every signed transaction.
+    pub chain_id: u32,
+    /// Expected time between two blocks, in seconds.
+    pub target_block_time: i64,
+    /// Number of blocks after which the difficulty is recomputed.
+    pub retarget_interval: u64,
+    /// Difficulty of the genesis block, as leading zero bits of the hash.
+    pub initial_difficulty: u32,
+    pub min_difficulty: u32,
+    /// Maximum size of a serialized block, in bytes.
+    pub max_block_size: usize,
+    pub reward_schedule: RewardSchedule,
+    /// How far in the future, in seconds, a block timestamp is allowed to be.
+    pub max_future_drift: i64,
+    /// Number of previous blocks whose median timestamp a new block must exceed.
+    pub median_time_span: usize,
+    pub genesis_timestamp: i64,
+    /// Number of blocks to wait before a block reward can be spent.
+    pub coinbase_maturity: u64,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        ConsensusParams {
+            chain_id: 1,
+            target_block_time: 60,
+            retarget_interval: 10,
+            initial_difficulty: 16,
+            min_difficulty: 1,
+            max_block_size: 1_000_000,
+            reward_schedule: RewardSchedule {
+                initial_reward: 50,
+                halving_interval: 210_000,
+            },
+            max_future_drift: 2 * 60 * 60,
+            median_time_span: 11,
+            genesis_timestamp: 1_700_870_400,
+            coinbase_maturity: 100,
+        }
+    }
+}
+
+/// Everything a chain is shipped with: its consensus rules, and the nodes
+/// to ask for peers, which are not part of them. A spec file holds them in
+/// two sections:
+///
+/// `{"params": {"chain_id": 1, ...}, "seed_nodes": ["seed.example.org:8333"]}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSpec {
+    pub params: ConsensusParams,
+    /// Nodes, as `host:port`, asked for peers when a node starts.
+    pub seed_nodes: Vec<String>,
+}
+
+impl ChainSpec {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ChainSpec, ConsensusParamsError> {
+        let content = fs::read_to_string(path)?;
+        ChainSpec::from_json(&content)
+    }
+
+    pub fn from_json(json: &str) -> Result<ChainSpec, ConsensusParamsError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+impl ConsensusParams {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ConsensusParams, ConsensusParamsError> {
+        let content = fs::read_to_string(path)?;
+        ConsensusParams::from_json(&content)
+    }
+
+    pub fn from_json(json: &str) -> Result<ConsensusParams, ConsensusParamsError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn block_reward(&self, height: u64) -> u64 {
+        let schedule = &self.reward_schedule;
+        if schedule.halving_interval == 0 {
+            return schedule.initial_reward;
+        }
+
+        let halvings = height / schedule.halving_interval;
+        if halvings >= u64::BITS as u64 {
+            return 0;
+        }
+        schedule.initial_reward >> halvings
+    }
+
+    /// Expected duration, in seconds, of a whole retarget interval.
+    pub fn target_timespan(&self) -> i64 {
+        self.target_block_time * self.retarget_interval as i64
+    }
+
+    /// Computes the difficulty of the next retarget period given the actual
+    /// time it took to mine the last one. Difficulty is expressed in leading
+    /// zero bits, so every step doubles or halves the expected work.
+    pub fn retarget(&self, current_difficulty: u32, actual_timespan: i64) -> u32 {
+        let target = self.target_timespan();
+
+        let next = if actual_timespan < target / 2 {
+            current_difficulty + 1
+        } else if actual_timespan > target * 2 {
+            current_difficulty.saturating_sub(1)
+        } else {
+            current_difficulty
+        };
+
+        next.max(self.min_difficulty)
+    }
+
+    /// Median timestamp of the last `median_time_span` blocks of the chain
+    /// ending at `height`, where `timestamp_at` gives the timestamp of the
+    /// block of that chain at a height.
+    pub fn median_time_past<F: Fn(u64) -> i64>(&self, height: u64, timestamp_at: F) -> i64 {
+        let start = (height + 1).saturating_sub(self.median_time_span.max(1) as u64);
+        let mut timestamps: Vec<i64> = (start..=height).map(timestamp_at).collect();
+        timestamps.sort_unstable();
+
+        timestamps[timestamps.len() / 2]
+    }
+
+    /// Difficulty of a block built on a parent at `parent_height` mined with
+    /// `parent_difficulty`. It only changes at the start of a retarget
+    /// interval, based on how long the previous interval took. The duration
+    /// is measured between median times past rather than raw timestamps,
+    /// since only those are guaranteed to move forward.
+    pub fn next_difficulty<F: Fn(u64) -> i64>(
+        &self,
+        parent_height: u64,
+        parent_difficulty: u32,
+        timestamp_at: F,
+    ) -> u32 {
+        let next_height = parent_height + 1;
+        let interval = self.retarget_interval;
+        if interval == 0 || !next_height.is_multiple_of(interval) || next_height < interval {
+            return parent_difficulty;
+        }
+
+        let first_height = next_height - interval;
+        let actual_timespan =
+            self.median_time_past(parent_height, &timestamp_at) - self.median_time_past(first_height, &timestamp_at);
+
+        self.retarget(parent_difficulty, actual_timespan)
+    }
+}
+
+#[cfg(test)]
+mod consensus_test {
+    use super::{ChainSpec, ConsensusParams};
+
+    #[test]
+    fn missing_fields_in_json_fall_back_to_defaults() {
+        let params = ConsensusParams::from_json(r#"{ "initial_difficulty": 4 }"#).unwrap();
+
+        assert_eq!(4, params.initial_difficulty);
+        assert_eq!(ConsensusParams::default().max_block_size, params.max_block_size);
+    }
+
+    #[test]
+    fn chain_spec_keeps_seed_nodes_apart_from_the_params() {
+        let json = r#"{ "params": { "initial_difficulty": 4 }, "seed_nodes": ["127.0.0.1:8333"] }"#;
+        let spec = ChainSpec::from_json(json).unwrap();
+
+        assert_eq!(4, spec.params.initial_difficulty);
+        assert_eq!(vec!["127.0.0.1:8333".to_string()], spec.seed_nodes);
+        // Params written at the top level would otherwise be silently ignored
+        assert!(ChainSpec::from_json(r#"{ "initial_difficulty": 4 }"#).is_err());
+    }
+
+    #[test]
+    fn malformed_json_returns_error() {
+        assert!(ConsensusParams::from_json("{ not json").is_err());
+    }
+
+    #[test]
+    fn block_reward_halves_every_interval() {
+        let mut params = ConsensusParams::default();
+        params.reward_schedule.initial_reward = 100;
+        params.reward_schedule.halving_interval = 10;
+
+        assert_eq!(100, params.block_reward(0));
+        assert_eq!(100, params.block_reward(9));
+        assert_eq!(50, params.block_reward(10));
+        assert_eq!(25, params.block_reward(25));
+        assert_eq!(0, params.block_reward(10 * 64));
+    }
+
+    #[test]
+    fn retarget_moves_difficulty_towards_target_block_time() {
+        let params = ConsensusParams::default();
+        let target = params.target_timespan();
+
+        assert_eq!(11, params.retarget(10, target / 4));
+        assert_eq!(10, params.retarget(10, target));
+        assert_eq!(9, params.retarget(10, target * 4));
+        assert_eq!(params.min_difficulty, params.retarget(params.min_difficulty, target * 4));
+    }
+}
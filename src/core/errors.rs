@@ -1,51 +1,417 @@
 use hex::FromHexError;
-use std::fmt;
+
+use super::LockTime;
+use std::{error::Error, fmt};
 
 #[derive(Debug, Clone)]
-pub struct AppendToHistoryError;
+pub enum AppendToHistoryError {
+    EmptyHistory,
+    InvalidBlock(BlockValidationError),
+    /// A chain to switch to does not start with our genesis block.
+    UnknownGenesis { expected: String, found: String },
+}
 
 impl fmt::Display for AppendToHistoryError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cannot append block to history")
+        match self {
+            AppendToHistoryError::EmptyHistory => {
+                write!(f, "Cannot append block to history: history has no tail block")
+            }
+            AppendToHistoryError::InvalidBlock(err) => {
+                write!(f, "Cannot append block to history: {}", err)
+            }
+            AppendToHistoryError::UnknownGenesis { expected, found } => {
+                write!(f, "Cannot switch to a chain starting at {} instead of genesis {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Error for AppendToHistoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppendToHistoryError::EmptyHistory => None,
+            AppendToHistoryError::InvalidBlock(err) => Some(err),
+            AppendToHistoryError::UnknownGenesis { .. } => None,
+        }
     }
 }
 
-impl From<FromHexError> for AppendToHistoryError {
-    fn from(err: FromHexError) -> AppendToHistoryError {
-        AppendToHistoryError {}
+impl From<BlockValidationError> for AppendToHistoryError {
+    fn from(err: BlockValidationError) -> AppendToHistoryError {
+        AppendToHistoryError::InvalidBlock(err)
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct TransactionValidationError;
+pub enum BlockValidationError {
+    BadParent { expected: String, found: String },
+    BadHeight { expected: u64, found: u64 },
+    BadDifficulty { expected: u32, found: u32 },
+    InsufficientWork { required: u32, found: u32 },
+    MalformedHash(FromHexError),
+    HashMismatch { declared: String, computed: String },
+    BadMerkleRoot { declared: String, computed: String },
+    MisplacedCoinbase { index: usize },
+    TooLarge { size: usize, max: usize },
+    TimestampTooOld { timestamp: i64, median_time_past: i64 },
+    TimestampTooFarInFuture { timestamp: i64, max_allowed: i64 },
+    InvalidTransaction { id: String, source: TransactionValidationError },
+    BadStateTransition { id: String, source: StateTransitionError },
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockValidationError::BadParent { expected, found } => {
+                write!(f, "previous hash {} does not match parent {}", found, expected)
+            }
+            BlockValidationError::BadHeight { expected, found } => {
+                write!(f, "height {} does not follow parent, expected {}", found, expected)
+            }
+            BlockValidationError::BadDifficulty { expected, found } => {
+                write!(f, "difficulty {} differs from the expected {}", found, expected)
+            }
+            BlockValidationError::InsufficientWork { required, found } => write!(
+                f,
+                "hash has {} leading zero bits, {} required",
+                found, required
+            ),
+            BlockValidationError::MalformedHash(_) => write!(f, "hash is not valid hex"),
+            BlockValidationError::HashMismatch { declared, computed } => write!(
+                f,
+                "declared hash {} differs from computed hash {}",
+                declared, computed
+            ),
+            BlockValidationError::BadMerkleRoot { declared, computed } => write!(
+                f,
+                "declared merkle root {} differs from computed root {}",
+                declared, computed
+            ),
+            BlockValidationError::MisplacedCoinbase { index } => write!(
+                f,
+                "coinbase transaction found at index {}, only the first can be a coinbase",
+                index
+            ),
+            BlockValidationError::TooLarge { size, max } => {
+                write!(f, "block size {} exceeds the maximum of {} bytes", size, max)
+            }
+            BlockValidationError::TimestampTooOld {
+                timestamp,
+                median_time_past,
+            } => write!(
+                f,
+                "timestamp {} is not after median time past {}",
+                timestamp, median_time_past
+            ),
+            BlockValidationError::TimestampTooFarInFuture {
+                timestamp,
+                max_allowed,
+            } => write!(
+                f,
+                "timestamp {} is after the maximum allowed {}",
+                timestamp, max_allowed
+            ),
+            BlockValidationError::InvalidTransaction { id, .. } => {
+                write!(f, "transaction {} is invalid", id)
+            }
+            BlockValidationError::BadStateTransition { id, .. } => {
+                write!(f, "transaction {} cannot be applied to the chain state", id)
+            }
+        }
+    }
+}
+
+impl Error for BlockValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlockValidationError::MalformedHash(err) => Some(err),
+            BlockValidationError::InvalidTransaction { source, .. } => Some(source),
+            BlockValidationError::BadStateTransition { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<FromHexError> for BlockValidationError {
+    fn from(err: FromHexError) -> BlockValidationError {
+        BlockValidationError::MalformedHash(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TransactionValidationError {
+    IdMismatch { expected: String, found: String },
+    WrongChain { expected: u32, found: u32 },
+    NotYetValid { valid_after: LockTime },
+    Expired { expires_at: LockTime },
+    MissingSignature { id: String },
+    MalformedSender(secp256k1::Error),
+    BadSignature(secp256k1::Error),
+    TooLarge { size: usize, max: usize },
+}
 
 impl fmt::Display for TransactionValidationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Transaction failed to validate")
+        match self {
+            TransactionValidationError::IdMismatch { expected, found } => write!(
+                f,
+                "Transaction id {} does not match its content, expected {}",
+                found, expected
+            ),
+            TransactionValidationError::WrongChain { expected, found } => write!(
+                f,
+                "Transaction is for chain {}, expected chain {}",
+                found, expected
+            ),
+            TransactionValidationError::NotYetValid { valid_after } => {
+                write!(f, "Transaction is only valid after {}", valid_after)
+            }
+            TransactionValidationError::Expired { expires_at } => {
+                write!(f, "Transaction expired at {}", expires_at)
+            }
+            TransactionValidationError::MissingSignature { id } => {
+                write!(f, "Transaction {} has an empty signature", id)
+            }
+            TransactionValidationError::MalformedSender(_) => {
+                write!(f, "Transaction sender is not a valid public key")
+            }
+            TransactionValidationError::BadSignature(_) => {
+                write!(f, "Transaction signature is invalid")
+            }
+            TransactionValidationError::TooLarge { size, max } => write!(
+                f,
+                "Transaction size {} exceeds the maximum of {} bytes",
+                size, max
+            ),
+        }
+    }
+}
+
+impl Error for TransactionValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransactionValidationError::MalformedSender(err) => Some(err),
+            TransactionValidationError::BadSignature(err) => Some(err),
+            _ => None,
+        }
     }
 }
 
-impl From<FromHexError> for TransactionValidationError {
-    fn from(err: FromHexError) -> TransactionValidationError {
-        TransactionValidationError {}
+impl From<secp256k1::Error> for TransactionValidationError {
+    fn from(err: secp256k1::Error) -> TransactionValidationError {
+        TransactionValidationError::BadSignature(err)
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct EmptySignatureError {
-    msg: String,
+pub enum StateTransitionError {
+    InsufficientBalance { address: String, balance: u64, required: u64 },
+    BadSequence { address: String, expected: u64, found: u64 },
+    ExcessiveCoinbase { amount: u64, max: u64 },
 }
 
-impl EmptySignatureError {
-    pub fn new(error_msg: String) -> EmptySignatureError {
-        EmptySignatureError { msg: error_msg }
+impl fmt::Display for StateTransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateTransitionError::InsufficientBalance {
+                address,
+                balance,
+                required,
+            } => write!(
+                f,
+                "Address {} has a balance of {}, {} required",
+                address, balance, required
+            ),
+            StateTransitionError::BadSequence {
+                address,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Sequence {} of address {} is not the next one, expected {}",
+                found, address, expected
+            ),
+            StateTransitionError::ExcessiveCoinbase { amount, max } => write!(
+                f,
+                "Coinbase pays {} but at most {} can be claimed",
+                amount, max
+            ),
+        }
     }
 }
 
-impl fmt::Display for EmptySignatureError {
+impl Error for StateTransitionError {}
+
+#[derive(Debug, Clone)]
+pub enum MemPoolError {
+    InvalidTransaction(TransactionValidationError),
+    /// Coinbase transactions only exist as the first one of a block.
+    Coinbase {
+        id: String,
+    },
+    InsufficientReplacementFee {
+        replaced_id: String,
+        required_fee: u64,
+        found_fee: u64,
+    },
+    InvalidForState(StateTransitionError),
+    FeeTooLow {
+        required_fee: u64,
+        found_fee: u64,
+    },
+    TooManyFromSender {
+        address: String,
+        max: usize,
+    },
+    MemPoolFull {
+        id: String,
+    },
+}
+
+impl fmt::Display for MemPoolError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match self {
+            MemPoolError::InvalidTransaction(err) => {
+                write!(f, "Transaction rejected by the mempool: {}", err)
+            }
+            MemPoolError::Coinbase { id } => write!(
+                f,
+                "Transaction {} is a coinbase, which can only be included by the miner of a block",
+                id
+            ),
+            MemPoolError::InsufficientReplacementFee {
+                replaced_id,
+                required_fee,
+                found_fee,
+            } => write!(
+                f,
+                "Transaction pays a fee of {} but at least {} is required to replace {}",
+                found_fee, required_fee, replaced_id
+            ),
+            MemPoolError::InvalidForState(err) => {
+                write!(f, "Transaction cannot be applied to the chain state: {}", err)
+            }
+            MemPoolError::FeeTooLow {
+                required_fee,
+                found_fee,
+            } => write!(
+                f,
+                "Transaction pays a fee of {} but the minimum relay fee is {}",
+                found_fee, required_fee
+            ),
+            MemPoolError::TooManyFromSender { address, max } => write!(
+                f,
+                "Sender {} already has the maximum of {} pending transactions",
+                address, max
+            ),
+            MemPoolError::MemPoolFull { id } => write!(
+                f,
+                "Mempool is full and transaction {} does not pay enough to evict any pending one",
+                id
+            ),
+        }
+    }
+}
+
+impl Error for MemPoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MemPoolError::InvalidTransaction(err) => Some(err),
+            MemPoolError::InvalidForState(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransactionValidationError> for MemPoolError {
+    fn from(err: TransactionValidationError) -> MemPoolError {
+        MemPoolError::InvalidTransaction(err)
     }
 }
 
-impl std::error::Error for EmptySignatureError {}
\ No newline at end of file
+impl From<StateTransitionError> for MemPoolError {
+    fn from(err: StateTransitionError) -> MemPoolError {
+        MemPoolError::InvalidForState(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConsensusParamsError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for ConsensusParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsensusParamsError::Io(err) => write!(f, "Cannot read consensus params: {}", err),
+            ConsensusParamsError::Parse(err) => write!(f, "Cannot parse consensus params: {}", err),
+        }
+    }
+}
+
+impl Error for ConsensusParamsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConsensusParamsError::Io(err) => Some(err),
+            ConsensusParamsError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConsensusParamsError {
+    fn from(err: std::io::Error) -> ConsensusParamsError {
+        ConsensusParamsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ConsensusParamsError {
+    fn from(err: serde_json::Error) -> ConsensusParamsError {
+        ConsensusParamsError::Parse(err)
+    }
+}
+
+/// Failure to save or load the chain or the mempool.
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    UnsupportedVersion { found: u64, supported: u64 },
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistenceError::Io(err) => write!(f, "Cannot access file: {}", err),
+            PersistenceError::Parse(err) => write!(f, "Cannot parse file: {}", err),
+            PersistenceError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "File has version {} but only version {} is supported",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl Error for PersistenceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PersistenceError::Io(err) => Some(err),
+            PersistenceError::Parse(err) => Some(err),
+            PersistenceError::UnsupportedVersion { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PersistenceError {
+    fn from(err: std::io::Error) -> PersistenceError {
+        PersistenceError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PersistenceError {
+    fn from(err: serde_json::Error) -> PersistenceError {
+        PersistenceError::Parse(err)
+    }
+}
@@ -0,0 +1,25 @@
+use super::{Block, Transaction};
+
+/// Change of the chain or of the mempool of a `Node`, sent to its
+/// subscribers, see `Node::subscribe`.
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// A block joined the chain, extending it or during a reorganization.
+    BlockConnected(Block),
+    /// A block left the chain during a reorganization, tip first.
+    BlockDisconnected(Block),
+    /// The chain switched to another branch, sent once its blocks were
+    /// disconnected and connected.
+    Reorganized {
+        fork_height: u64,
+        old_tip: String,
+        new_tip: String,
+        /// Hashes of the blocks that left the chain, in chain order.
+        disconnected: Vec<String>,
+        /// Hashes of the blocks that joined it, in chain order.
+        connected: Vec<String>,
+    },
+    /// A transaction entered the mempool, submitted or given back by a
+    /// disconnected block.
+    TxAccepted(Transaction),
+}
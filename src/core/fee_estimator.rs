@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use super::{Block, Transaction};
+
+#[derive(Debug, Clone)]
+pub struct FeeEstimatorConfig {
+    /// Longest confirmation target, in blocks, the estimator keeps data for.
+    pub max_target: u64,
+    /// Weight kept by past observations at every new block, so that the
+    /// estimates follow recent fee market conditions.
+    pub decay: f64,
+    /// Observations a group of fee rate buckets needs before its success
+    /// rate is trusted.
+    pub min_samples: f64,
+    /// Ratio between the lower bounds of two consecutive buckets.
+    pub bucket_spacing: f64,
+    pub max_fee_per_kb: u64,
+}
+
+impl Default for FeeEstimatorConfig {
+    fn default() -> Self {
+        FeeEstimatorConfig {
+            max_target: 48,
+            decay: 0.998,
+            min_samples: 4.0,
+            bucket_spacing: 1.2,
+            max_fee_per_kb: 10_000_000,
+        }
+    }
+}
+
+/// Fee rate expected to get a transaction confirmed within `target` blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeEstimate {
+    pub fee_per_kb: u64,
+    pub target: u64,
+}
+
+impl FeeEstimate {
+    /// Fee a transaction of `size` bytes should pay, rounded up.
+    pub fn fee_for(&self, size: usize) -> u64 {
+        (size as u64).saturating_mul(self.fee_per_kb).div_ceil(1000)
+    }
+}
+
+struct FeeBucket {
+    min_fee_per_kb: u64,
+    /// Decayed count of transactions confirmed within `index + 1` blocks.
+    confirmed_within: Vec<f64>,
+    /// Decayed count of transactions that confirmed, however long it took.
+    confirmed: f64,
+}
+
+struct TrackedTx {
+    bucket: usize,
+    entry_height: u64,
+}
+
+/// Learns how long transactions take to confirm depending on the fee rate
+/// they pay, from the moment they enter the mempool to their inclusion in
+/// the chain.
+pub struct FeeEstimator {
+    buckets: Vec<FeeBucket>,
+    tracked_txs: HashMap<String, TrackedTx>,
+    tip_height: u64,
+    config: FeeEstimatorConfig,
+}
+
+impl FeeEstimator {
+    pub fn new() -> FeeEstimator {
+        FeeEstimator::with_config(FeeEstimatorConfig::default())
+    }
+
+    pub fn with_config(config: FeeEstimatorConfig) -> FeeEstimator {
+        let mut bounds = vec![0, 1];
+        while let Some(&last) = bounds.last() {
+            let next = ((last as f64) * config.bucket_spacing).ceil() as u64;
+            if next > config.max_fee_per_kb {
+                break;
+            }
+            bounds.push(next.max(last + 1));
+        }
+
+        let buckets = bounds
+            .into_iter()
+            .map(|min_fee_per_kb| FeeBucket {
+                min_fee_per_kb,
+                confirmed_within: vec![0.0; config.max_target as usize],
+                confirmed: 0.0,
+            })
+            .collect();
+
+        FeeEstimator {
+            buckets,
+            tracked_txs: HashMap::new(),
+            tip_height: 0,
+            config,
+        }
+    }
+
+    /// Starts tracking a transaction accepted in the mempool while the chain
+    /// tip is at `tip_height`.
+    pub fn on_tx_accepted(&mut self, tx: &Transaction, tip_height: u64) {
+        let bucket = self.bucket_index(tx.fee_per_kb());
+        self.tip_height = self.tip_height.max(tip_height);
+        self.tracked_txs.insert(
+            tx.id.clone(),
+            TrackedTx {
+                bucket,
+                entry_height: tip_height,
+            },
+        );
+    }
+
+    /// Records how many blocks the tracked transactions included in `block`
+    /// waited for.
+    pub fn on_block_connected(&mut self, block: &Block) {
+        for bucket in &mut self.buckets {
+            bucket.confirmed *= self.config.decay;
+            for count in &mut bucket.confirmed_within {
+                *count *= self.config.decay;
+            }
+        }
+        self.tip_height = block.height;
+
+        for tx in &block.txs {
+            let tracked = match self.tracked_txs.remove(&tx.id) {
+                Some(tracked) => tracked,
+                None => continue,
+            };
+
+            let blocks_waited = block.height.saturating_sub(tracked.entry_height).max(1);
+            let bucket = &mut self.buckets[tracked.bucket];
+            bucket.confirmed += 1.0;
+            for count in bucket
+                .confirmed_within
+                .iter_mut()
+                .skip(blocks_waited as usize - 1)
+            {
+                *count += 1.0;
+            }
+        }
+    }
+
+    /// Stops tracking the transactions that left the mempool without being
+    /// mined, since they say nothing about confirmation times.
+    pub fn retain_tracked<F: Fn(&str) -> bool>(&mut self, is_pending: F) {
+        self.tracked_txs.retain(|id, _| is_pending(id));
+    }
+
+    /// Lowest fee rate such that transactions paying at least that much were
+    /// confirmed within `target` blocks with a success rate of at least
+    /// `confidence`, between 0 and 1. Transactions still pending after
+    /// `target` blocks count as failures.
+    pub fn estimate_fee(&self, target: u64, confidence: f64) -> Option<u64> {
+        if target == 0 || target > self.config.max_target {
+            return None;
+        }
+
+        let mut pending_too_long = vec![0.0; self.buckets.len()];
+        for tracked in self.tracked_txs.values() {
+            if self.tip_height.saturating_sub(tracked.entry_height) >= target {
+                pending_too_long[tracked.bucket] += 1.0;
+            }
+        }
+
+        // Buckets are grouped from the highest fee rate down until a group
+        // has enough samples, the estimate is the lower bound of the last
+        // group passing the confidence threshold
+        let mut estimate = None;
+        let (mut group_successes, mut group_total) = (0.0, 0.0);
+        for (index, bucket) in self.buckets.iter().enumerate().rev() {
+            group_successes += bucket.confirmed_within[target as usize - 1];
+            group_total += bucket.confirmed + pending_too_long[index];
+            if group_total < self.config.min_samples {
+                continue;
+            }
+
+            if group_successes / group_total < confidence {
+                break;
+            }
+            estimate = Some(bucket.min_fee_per_kb);
+            group_successes = 0.0;
+            group_total = 0.0;
+        }
+
+        estimate
+    }
+
+    /// Like `estimate_fee`, falling back to longer targets when there is
+    /// not enough data for `target`.
+    pub fn estimate_smart_fee(&self, target: u64, confidence: f64) -> Option<FeeEstimate> {
+        (target.max(1)..=self.config.max_target).find_map(|target| {
+            self.estimate_fee(target, confidence)
+                .map(|fee_per_kb| FeeEstimate { fee_per_kb, target })
+        })
+    }
+
+    pub fn tracked_count(&self) -> usize {
+        self.tracked_txs.len()
+    }
+
+    fn bucket_index(&self, fee_per_kb: u64) -> usize {
+        self.buckets
+            .partition_point(|bucket| bucket.min_fee_per_kb <= fee_per_kb)
+            .saturating_sub(1)
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        FeeEstimator::new()
+    }
+}
+
+#[cfg(test)]
+mod fee_estimator_test {
+    use crate::core::{test_utils::block_at, FeeEstimatorConfig, Transaction};
+
+    use super::FeeEstimator;
+
+    fn tx_with_fee(sender: usize, fee: u64) -> Transaction {
+        Transaction::new(format!("sender_{}", sender), "to_address".to_string(), 10, fee, 0, 1)
+    }
+
+    /// Every block confirms one high fee transaction sent just before it
+    /// and one low fee transaction sent five blocks earlier.
+    fn estimator_with_history() -> (FeeEstimator, u64, u64) {
+        let mut estimator = FeeEstimator::with_config(FeeEstimatorConfig {
+            max_target: 10,
+            ..FeeEstimatorConfig::default()
+        });
+        let high_fee_per_kb = tx_with_fee(0, 10_000).fee_per_kb();
+        let low_fee_per_kb = tx_with_fee(0, 100).fee_per_kb();
+
+        let mut low_fee_txs = Vec::new();
+        for height in 1..=30u64 {
+            let high_fee_tx = tx_with_fee(height as usize, 10_000);
+            let low_fee_tx = tx_with_fee(1000 + height as usize, 100);
+            estimator.on_tx_accepted(&high_fee_tx, height - 1);
+            estimator.on_tx_accepted(&low_fee_tx, height - 1);
+            low_fee_txs.push(low_fee_tx);
+
+            let mut txs = vec![high_fee_tx];
+            if height > 5 {
+                txs.push(low_fee_txs[height as usize - 6].clone());
+            }
+            estimator.on_block_connected(&block_at(height, txs));
+        }
+
+        (estimator, high_fee_per_kb, low_fee_per_kb)
+    }
+
+    #[test]
+    fn short_target_requires_the_fee_rate_confirming_fast() {
+        let (estimator, high_fee_per_kb, _) = estimator_with_history();
+
+        let estimate = estimator.estimate_fee(1, 0.95).unwrap();
+        assert!(estimate <= high_fee_per_kb);
+        assert!(estimate > high_fee_per_kb * 5 / 6);
+    }
+
+    #[test]
+    fn longer_target_accepts_a_lower_fee_rate() {
+        let (estimator, _, low_fee_per_kb) = estimator_with_history();
+
+        let estimate = estimator.estimate_fee(6, 0.95).unwrap();
+        assert!(estimate <= low_fee_per_kb);
+        assert!(estimate > low_fee_per_kb * 5 / 6);
+    }
+
+    #[test]
+    fn smart_estimate_falls_back_to_a_longer_target() {
+        let mut estimator = FeeEstimator::new();
+        assert!(estimator.estimate_smart_fee(1, 0.5).is_none());
+
+        for height in 1..=10u64 {
+            let tx = tx_with_fee(height as usize, 1000);
+            estimator.on_tx_accepted(&tx, height - 1);
+            estimator.on_block_connected(&block_at(height + 2, vec![tx]));
+        }
+
+        let estimate = estimator.estimate_smart_fee(1, 0.9).unwrap();
+        assert_eq!(3, estimate.target);
+        assert!(estimate.fee_per_kb <= tx_with_fee(0, 1000).fee_per_kb());
+        assert_eq!(estimate.fee_per_kb.div_ceil(2), estimate.fee_for(500));
+    }
+
+    #[test]
+    fn txs_that_left_the_mempool_are_no_longer_tracked() {
+        let mut estimator = FeeEstimator::new();
+        let kept = tx_with_fee(0, 1000);
+        let evicted = tx_with_fee(1, 1000);
+        estimator.on_tx_accepted(&kept, 0);
+        estimator.on_tx_accepted(&evicted, 0);
+
+        estimator.retain_tracked(|id| id == kept.id);
+
+        assert_eq!(1, estimator.tracked_count());
+    }
+}
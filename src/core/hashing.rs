@@ -11,6 +11,29 @@ pub fn calculate_hash(data: &Value) -> [u8; 32] {
         .expect("Failed to convert hash to array")
 }
 
+/// Merkle root of the given leaves. When a level has an odd number of nodes
+/// the last one is paired with itself. An empty tree has an all zero root.
+pub fn calculate_merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                let mut hasher = Sha256::new();
+                hasher.update(pair[0]);
+                hasher.update(right);
+                hasher.finalize().into()
+            })
+            .collect();
+    }
+    level[0]
+}
+
 pub fn hash_to_binary_representation(hash: &[u8]) -> String {
     let mut res: String = String::default();
     for c in hash {
@@ -19,9 +42,20 @@ pub fn hash_to_binary_representation(hash: &[u8]) -> String {
     res
 }
 
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
 #[cfg(test)]
 mod hashing_test {
-    use super::calculate_hash;
+    use super::{calculate_hash, calculate_merkle_root, leading_zero_bits};
 
     #[test]
     fn create_32_len_hash() {
@@ -37,4 +71,24 @@ mod hashing_test {
 
         assert_eq!(32, hash.len());
     }
+
+    #[test]
+    fn count_leading_zero_bits_across_bytes() {
+        assert_eq!(0, leading_zero_bits(&[0b1000_0000, 0]));
+        assert_eq!(3, leading_zero_bits(&[0b0001_0000, 0]));
+        assert_eq!(12, leading_zero_bits(&[0, 0b0000_1000]));
+        assert_eq!(16, leading_zero_bits(&[0, 0]));
+    }
+
+    #[test]
+    fn merkle_root_depends_on_every_leaf_and_their_order() {
+        let leaves = [[1u8; 32], [2u8; 32], [3u8; 32]];
+
+        let root = calculate_merkle_root(&leaves);
+
+        assert_eq!([0u8; 32], calculate_merkle_root(&[]));
+        assert_eq!(leaves[0], calculate_merkle_root(&leaves[..1]));
+        assert_ne!(root, calculate_merkle_root(&[leaves[0], leaves[2], leaves[1]]));
+        assert_ne!(root, calculate_merkle_root(&[leaves[0], leaves[1], [4u8; 32]]));
+    }
 }
@@ -1,43 +1,172 @@
-use super::{AppendToHistoryError, Block};
+use std::{fs, path::Path};
+
+use super::{
+    AppendToHistoryError, Block, BlockContext, ChainState, Clock, ConsensusParams, PersistenceError,
+    SystemClock,
+};
+
+/// Version of the file written by `History::dump`.
+pub const CHAIN_FILE_VERSION: u64 = 1;
 
 pub struct History {
     chain: Vec<Block>,
+    state: ChainState,
+    params: ConsensusParams,
     reorg_chain_strategy: Box<dyn ReorgChainStrategy>,
+    clock: Box<dyn Clock>,
 }
 
 impl History {
-    pub fn new(reorg_strategy: Box<dyn ReorgChainStrategy>) -> History {
+    pub fn new(params: ConsensusParams, reorg_strategy: Box<dyn ReorgChainStrategy>) -> History {
+        History::with_clock(params, reorg_strategy, Box::new(SystemClock {}))
+    }
+
+    pub fn with_clock(
+        params: ConsensusParams,
+        reorg_strategy: Box<dyn ReorgChainStrategy>,
+        clock: Box<dyn Clock>,
+    ) -> History {
         History {
-            chain: vec![Block::genesis()],
+            chain: vec![Block::genesis(&params)],
+            state: ChainState::new(),
+            params,
             reorg_chain_strategy: reorg_strategy,
+            clock,
         }
     }
 
+    /// Runs the whole validation pipeline on `new_block` and appends it if
+    /// every stage passes: context-free, contextual, then state checks.
     pub fn try_to_append(&mut self, new_block: Block) -> Result<bool, AppendToHistoryError> {
-        let tail_block = self.chain.last().ok_or(AppendToHistoryError {})?;
+        new_block.check_context_free(&self.params)?;
 
-        new_block.verify(tail_block)?;
+        let context = self.block_context().ok_or(AppendToHistoryError::EmptyHistory)?;
+        new_block.check_contextual(&context, &self.params)?;
+
+        self.state.apply_block(&new_block, &self.params)?;
 
         self.chain.push(new_block);
 
         Ok(true)
     }
 
-    pub fn choose_chain(&self, other_chain: &Vec<Block>) -> History {
-        // todo: should verify other chain
+    /// Context the next block is validated against, `None` if the chain is empty.
+    pub fn block_context(&self) -> Option<BlockContext<'_>> {
+        let parent = self.chain.last()?;
+
+        Some(BlockContext {
+            parent,
+            expected_difficulty: self.next_difficulty(),
+            median_time_past: self.median_time_past(),
+            now: self.clock.now(),
+        })
+    }
+
+    /// Returns a history with the chain preferred by the reorg strategy
+    /// between this one and `other_chain`. Switching to `other_chain` goes
+    /// back to the last block both chains share, then appends every block of
+    /// the other branch with `try_to_append`, so that the whole validation
+    /// pipeline runs on them. Nothing is switched if one of them fails.
+    pub fn choose_chain(&self, other_chain: &[Block]) -> Result<History, AppendToHistoryError> {
         let chosen_chain = self
             .reorg_chain_strategy
             .choose_chain(&self.chain, other_chain);
 
-        let new_chain = match chosen_chain {
-            ReorgChoice::First => self.chain.clone(),
-            ReorgChoice::Second => other_chain.clone(),
-        };
+        let fork_index = self
+            .chain
+            .iter()
+            .zip(other_chain)
+            .take_while(|(block, other_block)| block.hash == other_block.hash)
+            .count();
+        let same_chain = fork_index == self.chain.len() && fork_index == other_chain.len();
+        if matches!(chosen_chain, ReorgChoice::First) || same_chain {
+            return Ok(self.with_chain(self.chain.clone(), self.state.clone()));
+        }
+        if fork_index == 0 {
+            return Err(AppendToHistoryError::UnknownGenesis {
+                expected: self.chain.first().map_or(String::new(), |genesis| genesis.hash.clone()),
+                found: other_chain.first().map_or(String::new(), |genesis| genesis.hash.clone()),
+            });
+        }
+
+        let common_chain = self.chain[..fork_index].to_vec();
+        let common_state = ChainState::from_chain(&common_chain, &self.params)?;
+        let mut history = self.with_chain(common_chain, common_state);
+        for block in &other_chain[fork_index..] {
+            history.try_to_append(block.clone())?;
+        }
+        Ok(history)
+    }
 
+    fn with_chain(&self, chain: Vec<Block>, state: ChainState) -> History {
         History {
-            chain: new_chain,
-            reorg_chain_strategy: self.reorg_chain_strategy.clone()
+            chain,
+            state,
+            params: self.params.clone(),
+            reorg_chain_strategy: self.reorg_chain_strategy.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    /// Median timestamp of the last blocks of the chain. A new block must
+    /// have a timestamp strictly greater than this value.
+    pub fn median_time_past(&self) -> i64 {
+        match self.chain.len().checked_sub(1) {
+            Some(last_index) => self.median_time_past_at(last_index),
+            None => i64::MIN,
+        }
+    }
+
+    /// Median time past of the chain ending at the block at `height`,
+    /// `None` if the chain is not that long.
+    pub fn median_time_past_at_height(&self, height: u64) -> Option<i64> {
+        let index = usize::try_from(height).ok()?;
+        if index >= self.chain.len() {
+            return None;
         }
+        Some(self.median_time_past_at(index))
+    }
+
+    fn median_time_past_at(&self, index: usize) -> i64 {
+        self.params.median_time_past(index as u64, |height| self.chain[height as usize].timestamp)
+    }
+
+    /// Difficulty the next block must be mined with, see
+    /// `ConsensusParams::next_difficulty`.
+    pub fn next_difficulty(&self) -> u32 {
+        match self.chain.len().checked_sub(1) {
+            Some(last_index) => self.next_difficulty_after(last_index),
+            None => self.params.initial_difficulty,
+        }
+    }
+
+    /// Difficulty of a block built on the block at `height`, `None` if the
+    /// chain is not that long.
+    pub fn next_difficulty_at_height(&self, height: u64) -> Option<u32> {
+        let index = usize::try_from(height).ok()?;
+        if index >= self.chain.len() {
+            return None;
+        }
+        Some(self.next_difficulty_after(index))
+    }
+
+    fn next_difficulty_after(&self, index: usize) -> u32 {
+        let parent = &self.chain[index];
+        self.params.next_difficulty(parent.height, parent.difficulty, |height| {
+            self.chain[height as usize].timestamp
+        })
+    }
+
+    pub fn get_state(&self) -> &ChainState {
+        &self.state
+    }
+
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.state.get_balance(address)
+    }
+
+    pub fn get_params(&self) -> &ConsensusParams {
+        &self.params
     }
 
     pub fn get_height(&self) -> usize {
@@ -47,6 +176,52 @@ impl History {
     pub fn get_last_block(&self) -> Option<&Block> {
         self.chain.last()
     }
+
+    pub fn get_chain(&self) -> &[Block] {
+        &self.chain
+    }
+
+    pub fn get_block(&self, hash: &str) -> Option<&Block> {
+        self.chain.iter().rev().find(|block| block.hash == hash)
+    }
+
+    /// Total work of the chain, the sum of the work of its blocks.
+    pub fn chain_work(&self) -> u128 {
+        self.chain.iter().map(|block| block.header().work()).sum()
+    }
+
+    /// Total work of the chain ending at the block at `height`, `None` if
+    /// the chain is not that long.
+    pub fn chain_work_at_height(&self, height: u64) -> Option<u128> {
+        let index = usize::try_from(height).ok()?;
+        let blocks = self.chain.get(..=index)?;
+        Some(blocks.iter().map(|block| block.header().work()).sum())
+    }
+
+    /// Writes the blocks of the chain to `path`, see `load_blocks`.
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        let content = serde_json::json!({
+            "version": CHAIN_FILE_VERSION,
+            "blocks": self.chain,
+        });
+        fs::write(path, serde_json::to_vec(&content)?)?;
+        Ok(())
+    }
+
+    /// Reads the blocks written by `dump`, genesis first. They are not
+    /// validated, that happens when they are appended to a history.
+    pub fn load_blocks<P: AsRef<Path>>(path: P) -> Result<Vec<Block>, PersistenceError> {
+        let content: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+        let version = content["version"].as_u64().unwrap_or(0);
+        if version != CHAIN_FILE_VERSION {
+            return Err(PersistenceError::UnsupportedVersion {
+                found: version,
+                supported: CHAIN_FILE_VERSION,
+            });
+        }
+
+        Ok(serde_json::from_value(content["blocks"].clone())?)
+    }
 }
 
 pub enum ReorgChoice {
@@ -54,15 +229,15 @@ pub enum ReorgChoice {
     Second,
 }
 
-pub trait ReorgChainStrategy {
-    fn choose_chain(&self, first_chain: &Vec<Block>, second_chain: &Vec<Block>) -> ReorgChoice;
+pub trait ReorgChainStrategy: Send {
+    fn choose_chain(&self, first_chain: &[Block], second_chain: &[Block]) -> ReorgChoice;
     fn clone_dyn(&self) -> Box<dyn ReorgChainStrategy>;
 }
 
 #[derive(Clone)]
 pub struct NaiveReorgStrategy;
 impl ReorgChainStrategy for NaiveReorgStrategy {
-    fn choose_chain(&self, first_chain: &Vec<Block>, second_chain: &Vec<Block>) -> ReorgChoice {
+    fn choose_chain(&self, first_chain: &[Block], second_chain: &[Block]) -> ReorgChoice {
         if first_chain.len() > second_chain.len() {
             return ReorgChoice::First;
         }
@@ -74,6 +249,27 @@ impl ReorgChainStrategy for NaiveReorgStrategy {
         Box::new(self.clone())
     }
 }
+
+/// Prefers the chain with the most work, the sum of the work of its blocks,
+/// which cannot be outdone by a longer chain mined at a lower difficulty.
+/// Keeps the first chain on ties, so that the chain seen first stays.
+#[derive(Clone)]
+pub struct MostWorkReorgStrategy;
+impl ReorgChainStrategy for MostWorkReorgStrategy {
+    fn choose_chain(&self, first_chain: &[Block], second_chain: &[Block]) -> ReorgChoice {
+        let work = |chain: &[Block]| -> u128 { chain.iter().map(|block| block.header().work()).sum() };
+        if work(second_chain) > work(first_chain) {
+            return ReorgChoice::Second;
+        }
+
+        ReorgChoice::First
+    }
+
+    fn clone_dyn(&self) -> Box<dyn ReorgChainStrategy> {
+        Box::new(self.clone())
+    }
+}
+
 impl Clone for Box<dyn ReorgChainStrategy> {
     fn clone(&self) -> Self {
         self.clone_dyn()
@@ -82,25 +278,171 @@ impl Clone for Box<dyn ReorgChainStrategy> {
 
 #[cfg(test)]
 mod history_tests {
-    use crate::core::{NaiveReorgStrategy, Block};
+    use crate::core::{
+        test_utils::{mine_on_top_at, test_params},
+        AppendToHistoryError, Block, BlockValidationError, ConsensusParams, ManualClock,
+        MostWorkReorgStrategy, NaiveReorgStrategy,
+    };
 
-    use super::History;
+    use super::{History, ReorgChainStrategy, ReorgChoice};
 
     #[test]
     fn history_choose_chain_returns_a_new_history_with_chain_chosen_by_naive_strategy() {
-        let mut hs = History::new(Box::new(NaiveReorgStrategy {}));
-        let mut hs2 = History::new(Box::new(NaiveReorgStrategy {}));
+        let params = low_difficulty_params();
+        let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        let mut hs2 = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
 
-        for _ in 0..5 {
-            hs.chain.push(Block::genesis());
-            hs2.chain.push(Block::genesis());
+        for i in 1..=2 {
+            let block = mine_on_top_at(&hs, params.genesis_timestamp + i, Vec::new());
+            assert!(hs.try_to_append(block).is_ok());
         }
-        for _ in 0..3 {
-            hs2.chain.push(Block::genesis());
+        for i in 1..=3 {
+            let block = mine_on_top_at(&hs2, params.genesis_timestamp + i * 10, Vec::new());
+            assert!(hs2.try_to_append(block).is_ok());
         }
 
-        let new_hs = hs.choose_chain(&hs2.chain);
-        
+        let new_hs = hs.choose_chain(&hs2.chain).unwrap();
+
         assert_eq!(hs2.get_height(), new_hs.get_height());
+        assert_eq!(hs2.get_last_block().unwrap().hash, new_hs.get_last_block().unwrap().hash);
+        assert_eq!(hs.get_height(), hs2.choose_chain(&hs.chain).unwrap().get_height() - 1);
+    }
+
+    #[test]
+    fn branch_with_an_invalid_block_is_not_switched_to() {
+        let params = low_difficulty_params();
+        let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        let block = mine_on_top_at(&hs, params.genesis_timestamp + 1, Vec::new());
+        assert!(hs.try_to_append(block).is_ok());
+
+        let mut branch = hs.chain.clone();
+        let mut forged = mine_on_top_at(&hs, params.genesis_timestamp + 2, Vec::new());
+        forged.hash = "zz-not-hex".to_string();
+        branch.push(forged.clone());
+        let mut unmined = mine_on_top_at(&hs, params.genesis_timestamp + 2, Vec::new());
+        unmined.difficulty = 0;
+
+        assert!(matches!(
+            hs.choose_chain(&branch),
+            Err(AppendToHistoryError::InvalidBlock(BlockValidationError::MalformedHash(_)))
+        ));
+        branch.pop();
+        branch.push(unmined);
+        assert!(matches!(
+            hs.choose_chain(&branch),
+            Err(AppendToHistoryError::InvalidBlock(_))
+        ));
+        let other_params = ConsensusParams {
+            chain_id: params.chain_id + 1,
+            ..params.clone()
+        };
+        let other_genesis = vec![Block::genesis(&other_params); 3];
+        assert!(matches!(
+            hs.choose_chain(&other_genesis),
+            Err(AppendToHistoryError::UnknownGenesis { .. })
+        ));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn most_work_strategy_prefers_fewer_harder_blocks_and_keeps_the_first_on_ties() {
+        let params = ConsensusParams::default();
+        let block_with_difficulty = |difficulty| Block {
+            difficulty,
+            ..Block::genesis(&params)
+        };
+        let long_chain = vec![block_with_difficulty(4); 5];
+        let heavy_chain = vec![block_with_difficulty(8); 2];
+
+        let strategy = MostWorkReorgStrategy {};
+
+        assert!(matches!(strategy.choose_chain(&long_chain, &heavy_chain), ReorgChoice::Second));
+        assert!(matches!(strategy.choose_chain(&heavy_chain, &long_chain), ReorgChoice::First));
+        assert!(matches!(strategy.choose_chain(&long_chain, &long_chain), ReorgChoice::First));
+    }
+
+    #[test]
+    fn next_difficulty_increases_when_blocks_are_mined_too_fast() {
+        let params = ConsensusParams::default();
+        let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        let genesis_timestamp = hs.chain[0].timestamp;
+
+        for i in 1..params.retarget_interval {
+            let mut block = Block::genesis(&params);
+            block.height = i;
+            block.timestamp = genesis_timestamp + i as i64;
+            hs.chain.push(block);
+        }
+
+        assert_eq!(params.initial_difficulty + 1, hs.next_difficulty());
+        let tip_height = params.retarget_interval - 1;
+        assert_eq!(Some(hs.next_difficulty()), hs.next_difficulty_at_height(tip_height));
+        assert_eq!(Some(params.initial_difficulty), hs.next_difficulty_at_height(tip_height - 1));
+        assert_eq!(None, hs.next_difficulty_at_height(tip_height + 1));
+    }
+
+    #[test]
+    fn next_difficulty_does_not_change_inside_a_retarget_interval() {
+        let params = ConsensusParams::default();
+        let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+
+        let mut block = Block::genesis(&params);
+        block.height = 1;
+        hs.chain.push(block);
+
+        assert_eq!(params.initial_difficulty, hs.next_difficulty());
+    }
+
+    fn low_difficulty_params() -> ConsensusParams {
+        ConsensusParams {
+            median_time_span: 3,
+            ..test_params()
+        }
+    }
+
+    #[test]
+    fn block_with_timestamp_not_after_median_time_past_is_rejected() {
+        let params = low_difficulty_params();
+        let clock = ManualClock::new(params.genesis_timestamp + 1000);
+        let mut hs = History::with_clock(params.clone(), Box::new(NaiveReorgStrategy {}), Box::new(clock));
+
+        for i in 1..=3 {
+            let block = mine_on_top_at(&hs, params.genesis_timestamp + i * 100, Vec::new());
+            assert!(hs.try_to_append(block).is_ok());
+        }
+
+        // median of the last 3 timestamps is genesis + 200
+        assert_eq!(params.genesis_timestamp + 200, hs.median_time_past());
+
+        let old_block = mine_on_top_at(&hs, params.genesis_timestamp + 200, Vec::new());
+        assert!(matches!(
+            hs.try_to_append(old_block),
+            Err(AppendToHistoryError::InvalidBlock(BlockValidationError::TimestampTooOld { .. }))
+        ));
+
+        let before_parent_block = mine_on_top_at(&hs, params.genesis_timestamp + 250, Vec::new());
+        assert!(hs.try_to_append(before_parent_block).is_ok());
+    }
+
+    #[test]
+    fn block_too_far_in_the_future_is_rejected_until_clock_catches_up() {
+        let params = low_difficulty_params();
+        let clock = ManualClock::new(params.genesis_timestamp);
+        let mut hs = History::with_clock(
+            params.clone(),
+            Box::new(NaiveReorgStrategy {}),
+            Box::new(clock.clone()),
+        );
+
+        let future_timestamp = params.genesis_timestamp + params.max_future_drift + 1;
+        let block = mine_on_top_at(&hs, future_timestamp, Vec::new());
+        assert!(matches!(
+            hs.try_to_append(block.clone()),
+            Err(AppendToHistoryError::InvalidBlock(
+                BlockValidationError::TimestampTooFarInFuture { .. }
+            ))
+        ));
+
+        clock.advance(1);
+        assert!(hs.try_to_append(block).is_ok());
+    }
+}
@@ -1,105 +1,919 @@
-use std::collections::{BTreeSet, HashMap};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+};
 
-use super::{Transaction, TransactionPriority, TransactionValidationError};
+use super::{
+    AdmissionContext, AdmissionPolicy, Block, ChainState, Clock, ConsensusParams, MemPoolError,
+    PersistenceError, StandardAdmissionPolicy, SystemClock, Transaction, TransactionPriority,
+    TransactionValidationError,
+};
 
+/// Version of the file written by `MemPool::dump`.
+pub const MEMPOOL_FILE_VERSION: u64 = 1;
+
+#[derive(Debug, Clone)]
+pub struct MemPoolConfig {
+    /// Memory limit of the mempool, counted as the serialized size of the
+    /// pending transactions.
+    pub max_bytes: usize,
+    /// How much more fee a transaction must pay to replace a pending one
+    /// from the same sender with the same sequence.
+    pub min_replacement_fee_bump: u64,
+}
+
+impl Default for MemPoolConfig {
+    fn default() -> Self {
+        MemPoolConfig {
+            max_bytes: 5_000_000,
+            min_replacement_fee_bump: 1,
+        }
+    }
+}
+
+struct MemPoolEntry {
+    tx: Transaction,
+    priority: TransactionPriority,
+    /// Combined fee and size of the transaction with its pending ancestors.
+    package: TransactionPriority,
+    /// Fee and size giving the descendant score of the transaction, see
+    /// `evict_tx`.
+    descendant_score: TransactionPriority,
+    arrival_time: i64,
+}
+
+/// What the mempool knows about a pending transaction.
+#[derive(Debug, Clone)]
+pub struct MemPoolEntryInfo<'a> {
+    pub tx: &'a Transaction,
+    pub size: usize,
+    /// Fee per byte, only meant for display.
+    pub fee_rate: f64,
+    /// Time the transaction entered the mempool, in seconds since the unix epoch.
+    pub arrival_time: i64,
+    /// Pending transactions that must be mined before this one.
+    pub ancestor_count: usize,
+    pub descendant_count: usize,
+}
+
+/// Transactions paying at least `min_fee_per_kb` and less than the lower
+/// bound of the next bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeRateBucket {
+    pub min_fee_per_kb: u64,
+    pub tx_count: usize,
+    pub total_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemPoolStats {
+    pub tx_count: usize,
+    pub sender_count: usize,
+    pub total_bytes: usize,
+    pub max_bytes: usize,
+    pub total_fees: u64,
+    /// Fee a 1000 bytes transaction must pay to be accepted right now.
+    pub min_fee_per_kb: u64,
+}
+
+/// Transactions to evict to make room for a new one.
+struct EvictionPlan {
+    ids: Vec<String>,
+    /// Best descendant score among the evicted packages, as fee and size.
+    best_score: Option<(u64, usize)>,
+}
+
+/// Pending transactions. Transactions from the same sender form a chain by
+/// sequence number: a transaction depends on the one with the previous
+/// sequence if it is also pending, so the two are mined and evicted together.
 pub struct MemPool {
     prioritized_txs: BTreeSet<TransactionPriority>,
-    txs: HashMap<String, Transaction>,
-    max_cap: usize,
+    /// Packages of the pending transactions, best combined fee rate first.
+    prioritized_packages: BTreeSet<TransactionPriority>,
+    /// Descendant scores of the pending transactions, the next one to evict last.
+    descendant_scores: BTreeSet<TransactionPriority>,
+    txs: HashMap<String, MemPoolEntry>,
+    txs_by_sender: HashMap<String, BTreeMap<u64, String>>,
+    txs_by_recipient: HashMap<String, HashSet<String>>,
+    total_bytes: usize,
+    config: MemPoolConfig,
+    params: ConsensusParams,
+    policy: Box<dyn AdmissionPolicy>,
+    clock: Box<dyn Clock>,
+    next_block_height: u64,
+    median_time_past: i64,
+    arrival_counter: u64,
 }
 
 impl MemPool {
-    pub fn new(max_cap: usize) -> MemPool {
+    pub fn new(max_bytes: usize, params: ConsensusParams) -> MemPool {
+        MemPool::with_config(
+            MemPoolConfig {
+                max_bytes,
+                ..MemPoolConfig::default()
+            },
+            params,
+        )
+    }
+
+    pub fn with_config(config: MemPoolConfig, params: ConsensusParams) -> MemPool {
+        MemPool::with_policy(config, params, Box::new(StandardAdmissionPolicy::default()))
+    }
+
+    pub fn with_policy(
+        config: MemPoolConfig,
+        params: ConsensusParams,
+        policy: Box<dyn AdmissionPolicy>,
+    ) -> MemPool {
+        MemPool::with_clock(config, params, policy, Box::new(SystemClock {}))
+    }
+
+    pub fn with_clock(
+        config: MemPoolConfig,
+        params: ConsensusParams,
+        policy: Box<dyn AdmissionPolicy>,
+        clock: Box<dyn Clock>,
+    ) -> MemPool {
         MemPool {
             prioritized_txs: BTreeSet::new(),
+            prioritized_packages: BTreeSet::new(),
+            descendant_scores: BTreeSet::new(),
             txs: HashMap::new(),
-            max_cap,
+            txs_by_sender: HashMap::new(),
+            txs_by_recipient: HashMap::new(),
+            total_bytes: 0,
+            config,
+            policy,
+            clock,
+            next_block_height: 1,
+            median_time_past: params.genesis_timestamp,
+            arrival_counter: 0,
+            params,
+        }
+    }
+
+    /// Moves the mempool on top of a new chain tip. Transactions that can no
+    /// longer be included in the next block because they expired are dropped
+    /// and returned.
+    pub fn update_chain_tip(&mut self, tip_height: u64, median_time_past: i64) -> Vec<Transaction> {
+        self.next_block_height = tip_height + 1;
+        self.median_time_past = median_time_past;
+
+        let expired_ids: Vec<String> = self
+            .txs
+            .values()
+            .filter(|entry| entry.tx.is_expired(self.next_block_height, self.median_time_past))
+            .map(|entry| entry.tx.id.clone())
+            .collect();
+
+        expired_ids
+            .iter()
+            .filter_map(|id| self.remove_tx(id))
+            .collect()
+    }
+
+    /// Removes the transactions included in `block`, which just became the
+    /// chain tip, and the pending ones conflicting with them: a sender
+    /// sequence already used in the chain can never be mined again.
+    /// Returns the conflicting and expired transactions that were dropped.
+    pub fn on_block_connected(&mut self, block: &Block, median_time_past: i64) -> Vec<Transaction> {
+        let mut dropped = Vec::new();
+
+        for tx in block.txs.iter().filter(|tx| !tx.is_coinbase()) {
+            self.remove_tx(&tx.id);
+
+            let conflicting_ids: Vec<String> = self
+                .txs_by_sender
+                .get(&tx.from)
+                .map(|sender_txs| sender_txs.range(..=tx.sequence).map(|(_, id)| id.clone()).collect())
+                .unwrap_or_default();
+            dropped.extend(conflicting_ids.iter().filter_map(|id| self.remove_tx(id)));
         }
+
+        dropped.extend(self.update_chain_tip(block.height, median_time_past));
+        dropped
+    }
+
+    /// Puts back the transactions of `block`, which was removed from the top
+    /// of the chain, so that they can be mined again on the new branch.
+    /// `median_time_past` is the one of the new tip, the parent of `block`,
+    /// and `state` the one transactions are admitted against. Returns the
+    /// transactions that could not be added back.
+    pub fn on_block_disconnected(
+        &mut self,
+        block: &Block,
+        median_time_past: i64,
+        state: &ChainState,
+    ) -> Vec<Transaction> {
+        self.on_blocks_disconnected(std::slice::from_ref(block), median_time_past, state)
     }
 
-    pub fn add_tx(&mut self, tx: Transaction) -> Result<(), TransactionValidationError> {
+    /// Like `on_block_disconnected` for a whole branch, given in chain order.
+    /// During a reorg `state` is the one of the new branch, so that
+    /// transactions it already includes are not added back.
+    pub fn on_blocks_disconnected(
+        &mut self,
+        blocks: &[Block],
+        median_time_past: i64,
+        state: &ChainState,
+    ) -> Vec<Transaction> {
+        let fork_height = match blocks.first() {
+            Some(block) => block.height.saturating_sub(1),
+            None => return Vec::new(),
+        };
+        let mut dropped = self.update_chain_tip(fork_height, median_time_past);
+
+        for tx in blocks.iter().flat_map(|block| &block.txs).filter(|tx| !tx.is_coinbase()) {
+            if self.add_tx(tx.clone(), state).is_err() {
+                dropped.push(tx.clone());
+            }
+        }
+
+        dropped
+    }
+
+    /// Admits `tx` if it passes the structural checks and the admission
+    /// policy run against `state`, the state at the current chain tip. When
+    /// the mempool is full the transaction must pay a better fee rate than
+    /// the packages that would be evicted to make room for it.
+    pub fn add_tx(&mut self, tx: Transaction, state: &ChainState) -> Result<(), MemPoolError> {
         tx.validate()?;
 
-        if self.txs.len() == self.max_cap {
-            self.evict_tx();
+        if tx.chain_id != self.params.chain_id {
+            return Err(TransactionValidationError::WrongChain {
+                expected: self.params.chain_id,
+                found: tx.chain_id,
+            }
+            .into());
+        }
+
+        if let Some(expires_at) = tx.expires_at {
+            if tx.is_expired(self.next_block_height, self.median_time_past) {
+                return Err(TransactionValidationError::Expired { expires_at }.into());
+            }
+        }
+
+        // A transaction that does not fit in a block could never be mined
+        let size = tx.size();
+        if size > self.params.max_block_size {
+            return Err(TransactionValidationError::TooLarge {
+                size,
+                max: self.params.max_block_size,
+            }
+            .into());
+        }
+
+        let conflicting_id = self.sender_tx_id(&tx.from, tx.sequence);
+        self.policy.check(&tx, &self.admission_context(&tx, state))?;
+
+        // A transaction spending the same sender sequence replaces the old
+        // one only if it pays enough more to be worth relaying again
+        if let Some(conflicting_id) = &conflicting_id {
+            let conflicting_fee = self.txs[conflicting_id].tx.fee;
+            let required_fee = conflicting_fee.saturating_add(self.config.min_replacement_fee_bump);
+            if tx.fee < required_fee {
+                return Err(MemPoolError::InsufficientReplacementFee {
+                    replaced_id: conflicting_id.clone(),
+                    required_fee,
+                    found_fee: tx.fee,
+                });
+            }
         }
 
-        let tx_priority = TransactionPriority::new_from_tx(&tx);
-        if let Some(already_existing_tx) = self.txs.insert(tx.nonce.clone(), tx) {
-            self.prioritized_txs
-                .retain(|t| t.nonce != already_existing_tx.nonce);
+        // Evicting a package the transaction depends on, or one paying a
+        // better rate, would leave the mempool worse off than refusing it
+        let evicted_ids = match self.plan_eviction(size, conflicting_id.as_deref()) {
+            Some(EvictionPlan { ids: evicted_ids, best_score }) => {
+                let beats_evicted = best_score
+                    .is_none_or(|score| compare_fee_rates((tx.fee, size), score) == Ordering::Greater);
+                let evicts_ancestor = evicted_ids.iter().any(|id| {
+                    let pending = &self.txs[id].tx;
+                    pending.from == tx.from && pending.sequence < tx.sequence
+                });
+                if !beats_evicted || evicts_ancestor {
+                    return Err(MemPoolError::MemPoolFull { id: tx.id.clone() });
+                }
+                evicted_ids
+            }
+            None => return Err(MemPoolError::MemPoolFull { id: tx.id.clone() }),
+        };
+
+        if let Some(conflicting_id) = &conflicting_id {
+            self.remove_tx(conflicting_id);
+        }
+        for id in &evicted_ids {
+            self.remove_tx(id);
+        }
+
+        let priority = TransactionPriority::new_from_tx(&tx, self.arrival_counter);
+        self.arrival_counter += 1;
+
+        self.prioritized_txs.insert(priority.clone());
+        self.prioritized_packages.insert(priority.clone());
+        self.descendant_scores.insert(priority.clone());
+        self.txs_by_sender
+            .entry(tx.from.clone())
+            .or_default()
+            .insert(tx.sequence, tx.id.clone());
+        self.txs_by_recipient
+            .entry(tx.to.clone())
+            .or_default()
+            .insert(tx.id.clone());
+        self.total_bytes += priority.size;
+        let arrival_time = self.clock.now();
+        let (id, sender, sequence) = (tx.id.clone(), tx.from.clone(), tx.sequence);
+        self.txs.insert(
+            tx.id.clone(),
+            MemPoolEntry {
+                tx,
+                package: priority.clone(),
+                descendant_score: priority.clone(),
+                priority,
+                arrival_time,
+            },
+        );
+        self.update_packages(&sender, sequence);
+        self.update_descendant_scores(&sender, sequence);
+
+        // Descendants of the replaced transaction were admitted on top of it,
+        // and may no longer pass on top of the replacement
+        if conflicting_id.is_some() {
+            self.recheck_descendants(&id, state);
         }
-        self.prioritized_txs.insert(tx_priority);
 
         assert_eq!(self.prioritized_txs.len(), self.txs.len());
+        assert_eq!(self.prioritized_packages.len(), self.txs.len());
+        assert_eq!(self.descendant_scores.len(), self.txs.len());
 
         Ok(())
     }
 
+    /// Takes up to `limit` transactions for a block, stopping before the
+    /// total size would exceed the maximum block size. Transactions are picked
+    /// as packages with their pending ancestors, best combined fee rate first,
+    /// so a child paying a high fee pulls its parents in. Parents always come
+    /// before their children in the result. Transactions that are not valid
+    /// yet in the next block, and their descendants, stay in the mempool.
     pub fn take_txs_w_limit(&mut self, limit: usize) -> Vec<Transaction> {
-        let mut result: Vec<Transaction> = Vec::new();
-        for _ in 0..limit {
-            let tx_priority_opt = self.prioritized_txs.pop_first();
-            if let Some(tx_priority) = tx_priority_opt {
-                if let Some(tx) = self.txs.remove(&tx_priority.nonce) {
-                    result.push(tx);
-                }
-            } else {
-                break;
+        let mut selected: Vec<Transaction> = Vec::new();
+        // Kept out of the index until the block is full, along with the
+        // updates of their packages
+        let mut excluded: Vec<TransactionPriority> = Vec::new();
+        let mut total_size = 0;
+
+        while selected.len() < limit {
+            let package = match self.prioritized_packages.pop_first() {
+                Some(package) => package,
+                None => break,
+            };
+            let mut package_ids = self.ancestor_ids(&package.id);
+            package_ids.push(package.id.clone());
+
+            let fits = selected.len() + package_ids.len() <= limit
+                && total_size + package.size <= self.params.max_block_size;
+            let is_valid = package_ids.iter().all(|id| {
+                self.txs[id]
+                    .tx
+                    .check_validity_window(self.next_block_height, self.median_time_past)
+                    .is_ok()
+            });
+            if !fits || !is_valid {
+                excluded.push(package);
+                continue;
+            }
+
+            // Ancestors first, removing them updates the packages of their
+            // descendants
+            total_size += package.size;
+            selected.extend(package_ids.iter().filter_map(|id| self.remove_tx(id)));
+        }
+
+        for package in excluded {
+            if let Some(entry) = self.txs.get(&package.id) {
+                self.prioritized_packages.insert(entry.package.clone());
             }
         }
         assert_eq!(self.prioritized_txs.len(), self.txs.len());
+        assert_eq!(self.prioritized_packages.len(), self.txs.len());
+        assert_eq!(self.descendant_scores.len(), self.txs.len());
 
-        result
+        selected
     }
 
-    pub fn get_tx(&self, nonce: &str) -> Option<&Transaction> {
-        self.txs.get(nonce)
+    pub fn get_tx(&self, id: &str) -> Option<&Transaction> {
+        self.txs.get(id).map(|entry| &entry.tx)
     }
 
-    pub fn evict_tx(&mut self) -> Option<Transaction> {
-        if let Some(ev_tx) = self.prioritized_txs.pop_last() {
-            let evicted_nonce = ev_tx.nonce;
-            return self.txs.remove(&evicted_nonce);
+    /// Pending transactions of the same sender the given one depends on,
+    /// from the oldest to the direct parent.
+    pub fn ancestor_ids(&self, id: &str) -> Vec<String> {
+        let tx = match self.txs.get(id) {
+            Some(entry) => &entry.tx,
+            None => return Vec::new(),
+        };
+
+        let mut ancestors = Vec::new();
+        let mut sequence = tx.sequence;
+        while sequence > 0 {
+            sequence -= 1;
+            match self.sender_tx_id(&tx.from, sequence) {
+                Some(ancestor_id) => ancestors.push(ancestor_id),
+                None => break,
+            }
         }
+        ancestors.reverse();
+        ancestors
+    }
 
-        None
+    /// Pending transactions of the same sender depending on the given one,
+    /// from the direct child onwards.
+    pub fn descendant_ids(&self, id: &str) -> Vec<String> {
+        let tx = match self.txs.get(id) {
+            Some(entry) => &entry.tx,
+            None => return Vec::new(),
+        };
+
+        let mut descendants = Vec::new();
+        let mut sequence = tx.sequence;
+        while let Some(descendant_id) = self.sender_tx_id(&tx.from, sequence + 1) {
+            descendants.push(descendant_id);
+            sequence += 1;
+        }
+        descendants
     }
 
-    pub fn remove_tx(&mut self, nonce: &str) -> Option<Transaction> {
-        if let Some(removed_tx) = self.txs.remove(nonce) {
-            let tx_prior_to_remove = TransactionPriority::new_from_tx(&removed_tx);
-            self.prioritized_txs.remove(&tx_prior_to_remove);
-            return Some(removed_tx);
+    /// What the admission policy checks `tx` against: `state` and the other
+    /// pending transactions of its sender.
+    fn admission_context<'a>(&'a self, tx: &Transaction, state: &'a ChainState) -> AdmissionContext<'a> {
+        AdmissionContext {
+            state,
+            sender_pending: self
+                .txs_by_sender
+                .get(&tx.from)
+                .map(|sender_txs| {
+                    sender_txs
+                        .iter()
+                        .filter(|(sequence, _)| **sequence != tx.sequence)
+                        .map(|(_, id)| &self.txs[id].tx)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Runs the admission policy again on the descendants of `id`. The first
+    /// one failing it is removed with the transactions depending on it.
+    fn recheck_descendants(&mut self, id: &str, state: &ChainState) {
+        let descendant_ids = self.descendant_ids(id);
+        let failed_index = descendant_ids.iter().position(|descendant_id| {
+            let tx = &self.txs[descendant_id].tx;
+            self.policy.check(tx, &self.admission_context(tx, state)).is_err()
+        });
+
+        if let Some(failed_index) = failed_index {
+            for descendant_id in descendant_ids[failed_index..].iter().rev() {
+                self.remove_tx(descendant_id);
+            }
+        }
+    }
+
+    /// Evicts the transaction with the lowest descendant score together with
+    /// all of its descendants, which could not be mined without it. The
+    /// descendant score of a transaction is the best between its own fee rate
+    /// and the combined rate with its descendants, so a parent whose child
+    /// pays for it is not evicted first.
+    pub fn evict_tx(&mut self) -> Vec<Transaction> {
+        let worst_id = match self.descendant_scores.last() {
+            Some(score) => score.id.clone(),
+            None => return Vec::new(),
+        };
+
+        let mut package = vec![worst_id.clone()];
+        package.extend(self.descendant_ids(&worst_id));
+        package
+            .iter()
+            .rev()
+            .filter_map(|id| self.remove_tx(id))
+            .collect()
+    }
+
+    /// Minimum fee a transaction of `size` bytes must pay to be accepted
+    /// right now. This is the relay fee of the admission policy, raised while
+    /// the mempool is full to beat the fee rate of what would be evicted to
+    /// make room for it.
+    pub fn min_fee(&self, size: usize) -> u64 {
+        let relay_fee = self.policy.min_relay_fee(size);
+        match self.plan_eviction(size, None) {
+            Some(EvictionPlan { best_score: None, .. }) => relay_fee,
+            Some(EvictionPlan {
+                best_score: Some((fee, package_size)),
+                ..
+            }) => {
+                let beating_fee = fee as u128 * size as u128 / package_size.max(1) as u128 + 1;
+                relay_fee.max(u64::try_from(beating_fee).unwrap_or(u64::MAX))
+            }
+            None => u64::MAX,
+        }
+    }
+
+    /// Packages to evict, worst first, so that `size` more bytes fit in the
+    /// mempool, with the best descendant score among them. `replaced_id` is a
+    /// transaction about to be replaced, whose bytes are freed anyway. `None`
+    /// if not even emptying the mempool makes enough room.
+    fn plan_eviction(&self, size: usize, replaced_id: Option<&str>) -> Option<EvictionPlan> {
+        let mut planned: HashSet<String> = replaced_id.map(str::to_string).into_iter().collect();
+        let mut total_bytes = self.total_bytes
+            - replaced_id
+                .and_then(|id| self.txs.get(id))
+                .map_or(0, |entry| entry.priority.size);
+        let mut evicted_ids = Vec::new();
+        let mut best_score: Option<(u64, usize)> = None;
+
+        // Ancestors of the planned transactions have fewer descendants left,
+        // their scores are recomputed rather than read from the index
+        let mut rescored = RescoredAncestors::default();
+        if let Some(replaced_id) = replaced_id {
+            self.rescore_ancestors(replaced_id, &planned, &mut rescored);
+        }
+        let mut indexed = self.descendant_scores.iter().rev().peekable();
+
+        while total_bytes + size > self.config.max_bytes {
+            while indexed
+                .next_if(|score| planned.contains(&score.id) || rescored.by_id.contains_key(&score.id))
+                .is_some()
+            {}
+            while rescored.scores.last().is_some_and(|score| planned.contains(&score.id)) {
+                rescored.scores.pop_last();
+            }
+
+            let is_rescored_worse = match (indexed.peek(), rescored.scores.last()) {
+                (Some(indexed_score), Some(rescored_score)) => rescored_score > *indexed_score,
+                (_, rescored_score) => rescored_score.is_some(),
+            };
+            let candidate = if is_rescored_worse {
+                rescored.scores.pop_last()
+            } else {
+                indexed.next().cloned()
+            }?;
+
+            let mut package = vec![candidate.id.clone()];
+            package.extend(
+                self.descendant_ids(&candidate.id)
+                    .into_iter()
+                    .filter(|id| !planned.contains(id)),
+            );
+            for id in package {
+                total_bytes -= self.txs[&id].priority.size;
+                planned.insert(id.clone());
+                evicted_ids.push(id);
+            }
+
+            let score = (candidate.fee, candidate.size);
+            if best_score.is_none_or(|best_score| compare_fee_rates(score, best_score) == Ordering::Greater) {
+                best_score = Some(score);
+            }
+            self.rescore_ancestors(&candidate.id, &planned, &mut rescored);
+        }
+
+        Some(EvictionPlan {
+            ids: evicted_ids,
+            best_score,
+        })
+    }
+
+    /// Recomputes the descendant scores of the ancestors of `id` that are not
+    /// in `excluded`, without counting the descendants that are.
+    fn rescore_ancestors(&self, id: &str, excluded: &HashSet<String>, rescored: &mut RescoredAncestors) {
+        for ancestor_id in self.ancestor_ids(id) {
+            if excluded.contains(&ancestor_id) {
+                continue;
+            }
+
+            let priority = &self.txs[&ancestor_id].priority;
+            let (fee, size) = self
+                .descendant_ids(&ancestor_id)
+                .iter()
+                .filter(|id| !excluded.contains(*id))
+                .map(|id| &self.txs[id].priority)
+                .fold((priority.fee, priority.size), |(fee, size), descendant| {
+                    (fee.saturating_add(descendant.fee), size + descendant.size)
+                });
+
+            let score = descendant_score(priority, fee, size);
+            if let Some(old_score) = rescored.by_id.insert(ancestor_id, score.clone()) {
+                rescored.scores.remove(&old_score);
+            }
+            rescored.scores.insert(score);
+        }
+    }
+
+    pub fn remove_tx(&mut self, id: &str) -> Option<Transaction> {
+        if let Some(removed_entry) = self.txs.remove(id) {
+            self.prioritized_txs.remove(&removed_entry.priority);
+            self.prioritized_packages.remove(&removed_entry.package);
+            self.descendant_scores.remove(&removed_entry.descendant_score);
+            self.total_bytes -= removed_entry.priority.size;
+            let tx = removed_entry.tx;
+            if let Some(sender_txs) = self.txs_by_sender.get_mut(&tx.from) {
+                sender_txs.remove(&tx.sequence);
+                if sender_txs.is_empty() {
+                    self.txs_by_sender.remove(&tx.from);
+                }
+            }
+            if let Some(child_sequence) = tx.sequence.checked_add(1) {
+                self.update_packages(&tx.from, child_sequence);
+            }
+            if let Some(parent_sequence) = tx.sequence.checked_sub(1) {
+                self.update_descendant_scores(&tx.from, parent_sequence);
+            }
+            if let Some(recipient_txs) = self.txs_by_recipient.get_mut(&tx.to) {
+                recipient_txs.remove(&tx.id);
+                if recipient_txs.is_empty() {
+                    self.txs_by_recipient.remove(&tx.to);
+                }
+            }
+            return Some(tx);
         }
         None
     }
 
+    fn sender_tx_id(&self, sender: &str, sequence: u64) -> Option<String> {
+        self.txs_by_sender
+            .get(sender)
+            .and_then(|sender_txs| sender_txs.get(&sequence))
+            .cloned()
+    }
+
+    /// Recomputes the packages of the pending transactions of `sender` from
+    /// `sequence` onwards, as long as each one depends on the previous: a
+    /// package is the one of the parent, if pending, plus the transaction.
+    /// Packages left out of the index by `take_txs_w_limit` stay out of it.
+    fn update_packages(&mut self, sender: &str, mut sequence: u64) {
+        let parent_package = sequence
+            .checked_sub(1)
+            .and_then(|parent_sequence| self.sender_tx_id(sender, parent_sequence))
+            .map(|parent_id| &self.txs[&parent_id].package);
+        let (mut fee, mut size) = parent_package.map_or((0, 0), |package| (package.fee, package.size));
+
+        while let Some(id) = self.sender_tx_id(sender, sequence) {
+            let entry = self.txs.get_mut(&id).unwrap();
+            fee = fee.saturating_add(entry.priority.fee);
+            size += entry.priority.size;
+            let package = TransactionPriority::new(id, fee, size, entry.priority.arrival);
+            let old_package = std::mem::replace(&mut entry.package, package.clone());
+            if self.prioritized_packages.remove(&old_package) {
+                self.prioritized_packages.insert(package);
+            }
+
+            match sequence.checked_add(1) {
+                Some(next_sequence) => sequence = next_sequence,
+                None => break,
+            }
+        }
+    }
+
+    /// Recomputes the descendant scores of the pending transactions of
+    /// `sender` from `sequence` back, as long as each one is the parent of
+    /// the next: the descendants of all of them changed.
+    fn update_descendant_scores(&mut self, sender: &str, sequence: u64) {
+        let (mut fee, mut size) = (0u64, 0);
+        let mut next_sequence = sequence.checked_add(1);
+        while let Some(id) = next_sequence.and_then(|next_sequence| self.sender_tx_id(sender, next_sequence)) {
+            let priority = &self.txs[&id].priority;
+            fee = fee.saturating_add(priority.fee);
+            size += priority.size;
+            next_sequence = next_sequence.and_then(|next_sequence| next_sequence.checked_add(1));
+        }
+
+        let mut sequence = Some(sequence);
+        while let Some(id) = sequence.and_then(|sequence| self.sender_tx_id(sender, sequence)) {
+            let entry = self.txs.get_mut(&id).unwrap();
+            fee = fee.saturating_add(entry.priority.fee);
+            size += entry.priority.size;
+            let score = descendant_score(&entry.priority, fee, size);
+            let old_score = std::mem::replace(&mut entry.descendant_score, score.clone());
+            self.descendant_scores.remove(&old_score);
+            self.descendant_scores.insert(score);
+
+            sequence = sequence.and_then(|sequence| sequence.checked_sub(1));
+        }
+    }
+
+    /// Writes the pending transactions to `path` in arrival order, so that
+    /// they survive a restart.
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        let mut entries: Vec<&MemPoolEntry> = self.txs.values().collect();
+        entries.sort_by_key(|entry| entry.priority.arrival);
+        let txs: Vec<&Transaction> = entries.iter().map(|entry| &entry.tx).collect();
+
+        let content = serde_json::json!({
+            "version": MEMPOOL_FILE_VERSION,
+            "txs": txs,
+        });
+        fs::write(path, serde_json::to_vec(&content)?)?;
+        Ok(())
+    }
+
+    /// Adds back the transactions written by `dump`. Each one goes through
+    /// `add_tx` against `state` and the current chain tip, so the ones that
+    /// confirmed or expired in the meantime are dropped and returned.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P, state: &ChainState) -> Result<Vec<Transaction>, PersistenceError> {
+        let content: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+        let version = content["version"].as_u64().unwrap_or(0);
+        if version != MEMPOOL_FILE_VERSION {
+            return Err(PersistenceError::UnsupportedVersion {
+                found: version,
+                supported: MEMPOOL_FILE_VERSION,
+            });
+        }
+
+        // Parents must be added before their children, the sort is stable
+        // so arrival order is kept otherwise
+        let mut txs: Vec<Transaction> = serde_json::from_value(content["txs"].clone())?;
+        txs.sort_by_key(|tx| tx.sequence);
+
+        let mut dropped = Vec::new();
+        for tx in txs {
+            if self.add_tx(tx.clone(), state).is_err() {
+                dropped.push(tx);
+            }
+        }
+        Ok(dropped)
+    }
+
+    /// Pending transactions in priority order, best fee rate first. Note
+    /// that blocks are filled by package, see `take_txs_w_limit`.
+    pub fn iter(&self) -> impl Iterator<Item = MemPoolEntryInfo<'_>> {
+        self.prioritized_txs
+            .iter()
+            .filter_map(|tx_priority| self.get_entry_info(&tx_priority.id))
+    }
+
+    pub fn get_entry_info(&self, id: &str) -> Option<MemPoolEntryInfo<'_>> {
+        let entry = self.txs.get(id)?;
+        Some(MemPoolEntryInfo {
+            tx: &entry.tx,
+            size: entry.priority.size,
+            fee_rate: entry.priority.fee_rate(),
+            arrival_time: entry.arrival_time,
+            ancestor_count: self.ancestor_ids(id).len(),
+            descendant_count: self.descendant_ids(id).len(),
+        })
+    }
+
+    /// Pending transactions sent by `address`, by increasing sequence.
+    pub fn get_txs_from(&self, address: &str) -> Vec<&Transaction> {
+        self.txs_by_sender
+            .get(address)
+            .map(|sender_txs| sender_txs.values().map(|id| &self.txs[id].tx).collect())
+            .unwrap_or_default()
+    }
+
+    /// Pending transactions paying `address`, in priority order.
+    pub fn get_txs_to(&self, address: &str) -> Vec<&Transaction> {
+        let mut entries: Vec<&MemPoolEntry> = self
+            .txs_by_recipient
+            .get(address)
+            .map(|recipient_txs| recipient_txs.iter().map(|id| &self.txs[id]).collect())
+            .unwrap_or_default();
+        entries.sort_by(|first, second| first.priority.cmp(&second.priority));
+        entries.into_iter().map(|entry| &entry.tx).collect()
+    }
+
+    /// Counts the pending transactions by fee per 1000 bytes. `bounds` are
+    /// the increasing lower bounds of the buckets, transactions paying less
+    /// than the first one are not counted.
+    pub fn fee_rate_histogram(&self, bounds: &[u64]) -> Vec<FeeRateBucket> {
+        let mut buckets: Vec<FeeRateBucket> = bounds
+            .iter()
+            .map(|min_fee_per_kb| FeeRateBucket {
+                min_fee_per_kb: *min_fee_per_kb,
+                tx_count: 0,
+                total_bytes: 0,
+            })
+            .collect();
+
+        for entry in self.txs.values() {
+            let fee_per_kb = entry.priority.fee_per_kb();
+            let bucket = buckets
+                .iter_mut()
+                .rev()
+                .find(|bucket| bucket.min_fee_per_kb <= fee_per_kb);
+            if let Some(bucket) = bucket {
+                bucket.tx_count += 1;
+                bucket.total_bytes += entry.priority.size;
+            }
+        }
+
+        buckets
+    }
+
+    pub fn stats(&self) -> MemPoolStats {
+        MemPoolStats {
+            tx_count: self.txs.len(),
+            sender_count: self.txs_by_sender.len(),
+            total_bytes: self.total_bytes,
+            max_bytes: self.config.max_bytes,
+            total_fees: self
+                .txs
+                .values()
+                .fold(0, |total, entry| total.saturating_add(entry.tx.fee)),
+            min_fee_per_kb: self.min_fee(1000),
+        }
+    }
+
+    /// Serialized size of all the pending transactions.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
     pub fn len(&self) -> usize {
         self.txs.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+}
+
+/// Descendant scores computed while planning an eviction, by id and in the
+/// order of the index.
+#[derive(Default)]
+struct RescoredAncestors {
+    by_id: HashMap<String, TransactionPriority>,
+    scores: BTreeSet<TransactionPriority>,
+}
+
+/// Descendant score of the transaction of `priority`, whose combined fee and
+/// size with its descendants are `fee` and `size`: the best of the two rates.
+fn descendant_score(priority: &TransactionPriority, fee: u64, size: usize) -> TransactionPriority {
+    match compare_fee_rates((priority.fee, priority.size), (fee, size)) {
+        Ordering::Less => TransactionPriority::new(priority.id.clone(), fee, size, priority.arrival),
+        _ => priority.clone(),
+    }
+}
+
+fn compare_fee_rates(first: (u64, usize), second: (u64, usize)) -> Ordering {
+    let first_rate = first.0 as u128 * second.1.max(1) as u128;
+    let second_rate = second.0 as u128 * first.1.max(1) as u128;
+    first_rate.cmp(&second_rate)
 }
 
 #[cfg(test)]
 mod memory_pool_test {
-    use std::ptr::null;
+    use std::{env, fs, path::PathBuf};
 
-    use crate::core::Transaction;
+    use crate::core::{
+        test_utils::block_at, ChainState, ConsensusParams, FeeRateBucket, LockTime, ManualClock,
+        MemPoolConfig, MemPoolError, PersistenceError, StandardAdmissionPolicy, Transaction,
+        TransactionValidationError,
+    };
 
     use super::MemPool;
 
+    /// Mempool admitting unsigned transactions from unfunded senders, so that
+    /// tests can focus on ordering, replacement and eviction.
+    fn mempool_without_state_checks(max_bytes: usize, params: ConsensusParams) -> MemPool {
+        mempool_with_config(
+            MemPoolConfig {
+                max_bytes,
+                ..MemPoolConfig::default()
+            },
+            params,
+        )
+    }
+
+    fn mempool_with_config(config: MemPoolConfig, params: ConsensusParams) -> MemPool {
+        MemPool::with_policy(config, params, Box::new(policy_without_state_checks()))
+    }
+
+    fn policy_without_state_checks() -> StandardAdmissionPolicy {
+        StandardAdmissionPolicy {
+            verify_signatures: false,
+            check_chain_state: false,
+            ..StandardAdmissionPolicy::default()
+        }
+    }
+
     #[test]
     fn add_tx_to_mempool_with_space_adds_the_tx() {
-        let mut mempool = MemPool::new(10);
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
 
         assert_eq!(0, mempool.len());
 
-        let add_res = mempool.add_tx(Transaction::new(
-            "from_address".to_string(),
-            "to_string".to_string(),
-            1234500,
-            100,
-        ));
+        let add_res = mempool.add_tx(
+            Transaction::new(
+                "from_address".to_string(),
+                "to_string".to_string(),
+                1234500,
+                100,
+                0,
+                1,
+            ),
+            &ChainState::new(),
+        );
 
         assert!(add_res.is_ok());
         assert_eq!(1, mempool.len());
@@ -107,57 +921,92 @@ mod memory_pool_test {
 
     #[test]
     fn adding_new_tx_when_max_capacity_removes_tx_with_lower_fee_in_place_of_the_new_one() {
-        let mut mempool = MemPool::new(5);
+        let txs: Vec<Transaction> = (1..=5)
+            .map(|i| {
+                Transaction::new(
+                    "from_address".to_string(),
+                    "to_string".to_string(),
+                    1234500,
+                    15 - i,
+                    i,
+                    1,
+                )
+            })
+            .collect();
+        let max_bytes = txs.iter().map(|tx| tx.size()).sum();
+        let mut mempool = mempool_without_state_checks(max_bytes, ConsensusParams::default());
 
         assert_eq!(0, mempool.len());
 
-        let mut tx_low_fee_nonce: String = String::from("");
-        for i in 1..=5 {
-            let tx_to_add = Transaction::new(
-                "from_address".to_string(),
-                "to_string".to_string(),
-                1234500,
-                15 - i,
-            );
-            tx_low_fee_nonce = tx_to_add.nonce.clone();
-            let add_res = mempool.add_tx(tx_to_add);
+        let tx_low_fee_id = txs[4].id.clone();
+        for tx_to_add in txs {
+            let add_res = mempool.add_tx(tx_to_add, &ChainState::new());
 
             assert!(add_res.is_ok());
         }
 
         assert_eq!(5, mempool.len());
+        assert_eq!(max_bytes, mempool.total_bytes());
 
         let new_tx = Transaction::new(
-            "from_address".to_string(),
+            "other_sender".to_string(),
             "to_string".to_string(),
             1234500,
-            5,
+            20,
+            0,
+            1,
         );
-        let new_tx_nonce = new_tx.nonce.clone();
-        let add_res = mempool.add_tx(new_tx);
+        let new_tx_id = new_tx.id.clone();
+        let add_res = mempool.add_tx(new_tx, &ChainState::new());
 
         assert!(add_res.is_ok());
         assert_eq!(5, mempool.len());
-        assert!(mempool.get_tx(&tx_low_fee_nonce).is_none());
-        assert!(mempool.get_tx(&new_tx_nonce).is_some());
+        assert!(mempool.get_tx(&tx_low_fee_id).is_none());
+        assert!(mempool.get_tx(&new_tx_id).is_some());
+    }
+
+    #[test]
+    fn tx_that_would_be_evicted_at_once_is_rejected_when_full() {
+        let txs: Vec<Transaction> = [("from_address", 10), ("another_address", 20)]
+            .into_iter()
+            .map(|(sender, fee)| Transaction::new(sender.to_string(), "to_string".to_string(), 1234500, fee, 0, 1))
+            .collect();
+        let max_bytes = txs.iter().map(|tx| tx.size()).sum();
+        let mut mempool = mempool_without_state_checks(max_bytes, ConsensusParams::default());
+
+        for tx in txs {
+            assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        }
+
+        let cheap_tx = Transaction::new("third_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1);
+        let add_res = mempool.add_tx(cheap_tx, &ChainState::new());
+        assert!(matches!(add_res, Err(MemPoolError::MemPoolFull { .. })));
+
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 100, 1, 1);
+        let add_res = mempool.add_tx(child, &ChainState::new());
+        assert!(matches!(add_res, Err(MemPoolError::MemPoolFull { .. })));
+
+        assert_eq!(2, mempool.len());
     }
 
     #[test]
     fn take_n_txs_returns_n_txs_if_enough_txs() {
-        let mut mempool = MemPool::new(5);
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
 
         assert_eq!(0, mempool.len());
-        let mut inserted_nonce = Vec::new();
+        let mut inserted_ids = Vec::new();
 
         for i in 0..5 {
             let tx_to_add = Transaction::new(
-                "from_address".to_string(),
+                format!("from_address_{}", i),
                 "to_string".to_string(),
                 1234500,
                 i + 10,
+                0,
+                1,
             );
-            inserted_nonce.push(tx_to_add.nonce.clone());
-            let add_res = mempool.add_tx(tx_to_add);
+            inserted_ids.push(tx_to_add.id.clone());
+            let add_res = mempool.add_tx(tx_to_add, &ChainState::new());
 
             assert!(add_res.is_ok());
         }
@@ -167,14 +1016,14 @@ mod memory_pool_test {
         let retrieved_txs = mempool.take_txs_w_limit(3);
 
         assert_eq!(3, retrieved_txs.len());
-        assert_eq!(inserted_nonce[4], retrieved_txs[0].nonce);
-        assert_eq!(inserted_nonce[3], retrieved_txs[1].nonce);
-        assert_eq!(inserted_nonce[2], retrieved_txs[2].nonce);
+        assert_eq!(inserted_ids[4], retrieved_txs[0].id);
+        assert_eq!(inserted_ids[3], retrieved_txs[1].id);
+        assert_eq!(inserted_ids[2], retrieved_txs[2].id);
 
         let left_txs = mempool.take_txs_w_limit(10);
         assert_eq!(2, left_txs.len());
-        assert_eq!(inserted_nonce[1], left_txs[0].nonce);
-        assert_eq!(inserted_nonce[0], left_txs[1].nonce);
+        assert_eq!(inserted_ids[1], left_txs[0].id);
+        assert_eq!(inserted_ids[0], left_txs[1].id);
 
         let empty_txs = mempool.take_txs_w_limit(10);
         assert_eq!(0, empty_txs.len());
@@ -182,7 +1031,7 @@ mod memory_pool_test {
 
     #[test]
     fn remove_tx_returns_the_removed_tx_if_any() {
-        let mut mempool = MemPool::new(5);
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
 
         assert_eq!(0, mempool.len());
 
@@ -191,23 +1040,25 @@ mod memory_pool_test {
             "to_string".to_string(),
             1234500,
             100,
+            0,
+            1,
         );
-        let tx_nonce = tx.nonce.to_string();
-        let add_res = mempool.add_tx(tx);
+        let tx_id = tx.id.to_string();
+        let add_res = mempool.add_tx(tx, &ChainState::new());
 
         assert!(add_res.is_ok());
         assert_eq!(1, mempool.len());
 
-        let removed_tx_opt = mempool.remove_tx(&tx_nonce);
-        assert!(removed_tx_opt.is_some_and(|t| t.nonce == tx_nonce));
+        let removed_tx_opt = mempool.remove_tx(&tx_id);
+        assert!(removed_tx_opt.is_some_and(|t| t.id == tx_id));
 
-        let non_existing_tx = mempool.remove_tx(&tx_nonce);
+        let non_existing_tx = mempool.remove_tx(&tx_id);
         assert!(non_existing_tx.is_none());
     }
 
     #[test]
-    fn adding_two_txs_with_same_nonce_will_replace_the_tx() {
-        let mut mempool = MemPool::new(5);
+    fn adding_tx_with_same_sender_sequence_and_higher_fee_will_replace_the_tx() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
 
         assert_eq!(0, mempool.len());
 
@@ -216,27 +1067,607 @@ mod memory_pool_test {
             "to_string".to_string(),
             1234500,
             100,
+            0,
+            1,
         );
-        let tx_nonce = tx.nonce.clone();
-        let add_res = mempool.add_tx(tx);
+        let tx_id = tx.id.clone();
+        let add_res = mempool.add_tx(tx, &ChainState::new());
 
         assert!(add_res.is_ok());
         assert_eq!(1, mempool.len());
 
         let tx2 = Transaction::new(
             "from_address".to_string(),
-            "to_string".to_string(),
+            "another_address".to_string(),
             1234500,
-            100,
+            101,
+            0,
+            1,
         );
-        let add_res2 = mempool.add_tx(tx2);
+        let tx2_id = tx2.id.clone();
+        let add_res2 = mempool.add_tx(tx2, &ChainState::new());
 
         assert!(add_res2.is_ok());
         assert_eq!(1, mempool.len());
+        assert!(mempool.get_tx(&tx_id).is_none());
 
-        let tx = mempool.get_tx(&tx_nonce);
+        let tx = mempool.get_tx(&tx2_id);
         assert!(
-            tx.is_some_and(|t| t.nonce == tx_nonce && t.amount == 1234500 && t.fee == 100)
+            tx.is_some_and(|t| t.id == tx2_id && t.amount == 1234500 && t.fee == 101)
+        );
+    }
+
+    #[test]
+    fn adding_two_identical_payments_with_different_sequences_keeps_both() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        for sequence in 0..2 {
+            let add_res = mempool.add_tx(
+                Transaction::new(
+                    "from_address".to_string(),
+                    "to_string".to_string(),
+                    1234500,
+                    100,
+                    sequence,
+                    1,
+                ),
+                &ChainState::new(),
+            );
+            assert!(add_res.is_ok());
+        }
+
+        assert_eq!(2, mempool.len());
+    }
+
+    #[test]
+    fn tx_for_another_chain_is_rejected() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let add_res = mempool.add_tx(
+            Transaction::new(
+                "from_address".to_string(),
+                "to_string".to_string(),
+                1234500,
+                100,
+                0,
+                2,
+            ),
+            &ChainState::new(),
+        );
+
+        assert!(matches!(
+            add_res,
+            Err(MemPoolError::InvalidTransaction(TransactionValidationError::WrongChain {
+                expected: 1,
+                found: 2
+            }))
+        ));
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn take_txs_w_limit_does_not_exceed_max_block_size() {
+        let tx = Transaction::new(
+            "from_address".to_string(),
+            "to_string".to_string(),
+            1234500,
+            100,
+            0,
+            1,
+        );
+        let params = ConsensusParams {
+            max_block_size: tx.size() * 2,
+            ..ConsensusParams::default()
+        };
+        let mut mempool = mempool_without_state_checks(1_000_000, params);
+
+        for i in 0..3 {
+            let add_res = mempool.add_tx(
+                Transaction::new(
+                    "from_address".to_string(),
+                    "to_string".to_string(),
+                    1234500,
+                    100 + i,
+                    i,
+                    1,
+                ),
+                &ChainState::new(),
+            );
+            assert!(add_res.is_ok());
+        }
+
+        let retrieved_txs = mempool.take_txs_w_limit(10);
+
+        assert_eq!(2, retrieved_txs.len());
+        assert_eq!(1, mempool.len());
+    }
+
+    #[test]
+    fn tx_bigger_than_a_block_is_rejected() {
+        let params = ConsensusParams {
+            max_block_size: 10,
+            ..ConsensusParams::default()
+        };
+        let mut mempool = mempool_without_state_checks(1_000_000, params);
+
+        let add_res = mempool.add_tx(
+            Transaction::new(
+                "from_address".to_string(),
+                "to_string".to_string(),
+                1234500,
+                100,
+                0,
+                1,
+            ),
+            &ChainState::new(),
+        );
+
+        assert!(add_res.is_err());
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn not_yet_valid_tx_is_held_back_until_its_lock_time() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let locked_tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 200, 0, 1)
+            .with_validity_window(Some(LockTime::Height(2)), None);
+        let locked_tx_id = locked_tx.id.clone();
+        assert!(mempool.add_tx(locked_tx, &ChainState::new()).is_ok());
+        assert!(mempool
+            .add_tx(
+                Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1),
+                &ChainState::new(),
+            )
+            .is_ok());
+
+        let retrieved_txs = mempool.take_txs_w_limit(10);
+        assert_eq!(1, retrieved_txs.len());
+        assert_ne!(locked_tx_id, retrieved_txs[0].id);
+        assert_eq!(1, mempool.len());
+
+        mempool.update_chain_tip(2, 0);
+
+        let retrieved_txs = mempool.take_txs_w_limit(10);
+        assert_eq!(1, retrieved_txs.len());
+        assert_eq!(locked_tx_id, retrieved_txs[0].id);
+    }
+
+    #[test]
+    fn expired_txs_are_dropped_on_new_tip_and_rejected_on_add() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let expiring_tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1)
+            .with_validity_window(None, Some(LockTime::Height(3)));
+        let expiring_tx_id = expiring_tx.id.clone();
+        assert!(mempool.add_tx(expiring_tx.clone(), &ChainState::new()).is_ok());
+
+        assert!(mempool.update_chain_tip(1, 0).is_empty());
+        assert_eq!(1, mempool.len());
+
+        let dropped = mempool.update_chain_tip(2, 0);
+        assert_eq!(1, dropped.len());
+        assert_eq!(expiring_tx_id, dropped[0].id);
+        assert!(mempool.is_empty());
+
+        assert!(matches!(
+            mempool.add_tx(expiring_tx, &ChainState::new()),
+            Err(MemPoolError::InvalidTransaction(TransactionValidationError::Expired {
+                expires_at: LockTime::Height(3)
+            }))
+        ));
+    }
+
+    #[test]
+    fn small_tx_paying_more_per_byte_is_taken_before_a_large_one() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let large_tx = Transaction::new(
+            "from_address".to_string(),
+            "to_string".repeat(100),
+            1234500,
+            150,
+            0,
+            1,
+        );
+        let small_tx = Transaction::new(
+            "another_address".to_string(),
+            "to_string".to_string(),
+            1234500,
+            100,
+            0,
+            1,
+        );
+        let small_tx_id = small_tx.id.clone();
+        assert!(large_tx.size() > 2 * small_tx.size());
+
+        assert!(mempool.add_tx(large_tx, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(small_tx, &ChainState::new()).is_ok());
+
+        let retrieved_txs = mempool.take_txs_w_limit(1);
+        assert_eq!(small_tx_id, retrieved_txs[0].id);
+    }
+
+    #[test]
+    fn txs_with_equal_fee_rate_are_all_kept_and_taken_in_arrival_order() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+        let mut inserted_ids = Vec::new();
+
+        for sender in ["sender_c", "sender_a", "sender_b"] {
+            let tx = Transaction::new(sender.to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+            inserted_ids.push(tx.id.clone());
+            assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        }
+
+        assert_eq!(3, mempool.len());
+
+        let retrieved_ids: Vec<String> = mempool
+            .take_txs_w_limit(3)
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert_eq!(inserted_ids, retrieved_ids);
+    }
+
+    #[test]
+    fn replacement_without_enough_fee_bump_is_rejected() {
+        let config = MemPoolConfig {
+            min_replacement_fee_bump: 10,
+            ..MemPoolConfig::default()
+        };
+        let mut mempool = mempool_with_config(config, ConsensusParams::default());
+
+        let tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+        let tx_id = tx.id.clone();
+        assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+
+        let low_bump_tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 109, 0, 1);
+        let add_res = mempool.add_tx(low_bump_tx, &ChainState::new());
+
+        assert!(matches!(
+            add_res,
+            Err(MemPoolError::InsufficientReplacementFee { replaced_id, required_fee: 110, found_fee: 109 })
+                if replaced_id == tx_id
+        ));
+        assert!(mempool.get_tx(&tx_id).is_some());
+
+        let high_bump_tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 110, 0, 1);
+        let high_bump_tx_id = high_bump_tx.id.clone();
+        assert!(mempool.add_tx(high_bump_tx, &ChainState::new()).is_ok());
+
+        assert_eq!(1, mempool.len());
+        assert!(mempool.get_tx(&tx_id).is_none());
+        assert!(mempool.get_tx(&high_bump_tx_id).is_some());
+    }
+
+    #[test]
+    fn child_paying_high_fee_pulls_its_parent_into_the_block_first() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 1, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+        let (parent_id, child_id) = (parent.id.clone(), child.id.clone());
+
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(other, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(parent, &ChainState::new()).is_ok());
+
+        assert_eq!(vec![parent_id.clone()], mempool.ancestor_ids(&child_id));
+        assert_eq!(vec![child_id.clone()], mempool.descendant_ids(&parent_id));
+
+        let retrieved_ids: Vec<String> = mempool
+            .take_txs_w_limit(2)
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert_eq!(vec![parent_id, child_id], retrieved_ids);
+    }
+
+    #[test]
+    fn packages_follow_the_removal_and_replacement_of_their_ancestors() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let mined = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 1, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 2, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+        let (mined_id, child_id, other_id) = (mined.id.clone(), child.id.clone(), other.id.clone());
+        for tx in [mined, parent, child, other] {
+            assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        }
+
+        assert!(mempool.remove_tx(&mined_id).is_some());
+        let replacement = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 2, 1, 1);
+        let replacement_id = replacement.id.clone();
+        assert!(mempool.add_tx(replacement, &ChainState::new()).is_ok());
+
+        // The package of the child does not fit, and is still there after
+        let retrieved_ids: Vec<String> = mempool
+            .take_txs_w_limit(1)
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert_eq!(vec![other_id], retrieved_ids);
+        let retrieved_ids: Vec<String> = mempool
+            .take_txs_w_limit(2)
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert_eq!(vec![replacement_id, child_id], retrieved_ids);
+    }
+
+    #[test]
+    fn child_is_not_taken_without_room_for_its_parent() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 1, 1);
+        let parent_id = parent.id.clone();
+
+        assert!(mempool.add_tx(parent, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+
+        let retrieved_txs = mempool.take_txs_w_limit(1);
+        assert_eq!(1, retrieved_txs.len());
+        assert_eq!(parent_id, retrieved_txs[0].id);
+    }
+
+    #[test]
+    fn evicting_a_parent_evicts_its_descendants() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 5, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 6, 1, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 50, 0, 1);
+        let other_id = other.id.clone();
+
+        assert!(mempool.add_tx(parent, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(other, &ChainState::new()).is_ok());
+
+        let evicted = mempool.evict_tx();
+
+        assert_eq!(2, evicted.len());
+        assert_eq!(1, mempool.len());
+        assert!(mempool.get_tx(&other_id).is_some());
+    }
+
+    #[test]
+    fn parent_paid_for_by_its_child_is_not_evicted_first() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 1, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 50, 0, 1);
+        let other_id = other.id.clone();
+
+        assert!(mempool.add_tx(parent, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(other, &ChainState::new()).is_ok());
+
+        let evicted = mempool.evict_tx();
+
+        assert_eq!(1, evicted.len());
+        assert_eq!(other_id, evicted[0].id);
+        assert_eq!(2, mempool.len());
+    }
+
+    #[test]
+    fn parent_left_by_the_child_paying_for_it_is_evicted_first() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 1, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 50, 0, 1);
+        let parent_id = parent.id.clone();
+        let child_id = child.id.clone();
+
+        assert!(mempool.add_tx(parent, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(other, &ChainState::new()).is_ok());
+        assert!(mempool.remove_tx(&child_id).is_some());
+
+        let evicted = mempool.evict_tx();
+
+        assert_eq!(1, evicted.len());
+        assert_eq!(parent_id, evicted[0].id);
+    }
+
+    #[test]
+    fn eviction_plan_rescores_the_ancestors_of_evicted_txs() {
+        let first = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let second = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1000, 1, 1);
+        let third = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 2, 2, 1);
+        let other = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 400, 0, 1);
+        let txs = [first, second, third.clone(), other.clone()];
+        let max_bytes = txs.iter().map(Transaction::size).sum();
+        let mut mempool = mempool_without_state_checks(max_bytes, ConsensusParams::default());
+        for tx in txs {
+            assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        }
+
+        // The third transaction goes first, after which the first one only
+        // shares the fee of the second and pays more than the other one
+        let size = third.size() + other.size();
+        let beating_fee = other.fee as u128 * size as u128 / other.size() as u128 + 1;
+
+        assert_eq!(beating_fee as u64, mempool.min_fee(size));
+    }
+
+    #[test]
+    fn connected_block_removes_included_and_conflicting_txs() {
+        let params = ConsensusParams::default();
+        let mut mempool = mempool_without_state_checks(1_000_000, params.clone());
+
+        let included = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1);
+        let conflicting = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1);
+        let child = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 10, 1, 1);
+        let child_id = child.id.clone();
+        let mined_instead = Transaction::new("another_address".to_string(), "to_address".to_string(), 1, 1, 0, 1);
+
+        assert!(mempool.add_tx(included.clone(), &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(conflicting.clone(), &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(child, &ChainState::new()).is_ok());
+
+        let dropped = mempool.on_block_connected(
+            &block_at(1, vec![included, mined_instead]),
+            params.genesis_timestamp,
         );
+
+        assert_eq!(vec![conflicting], dropped);
+        assert_eq!(1, mempool.len());
+        assert!(mempool.get_tx(&child_id).is_some());
+    }
+
+    #[test]
+    fn disconnected_block_gives_its_txs_back_except_the_coinbase() {
+        let params = ConsensusParams::default();
+        let mut mempool = mempool_without_state_checks(1_000_000, params.clone());
+
+        let coinbase = Transaction::coinbase("miner".to_string(), 50, 1, 1);
+        let tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1);
+        let tx_id = tx.id.clone();
+        let block = block_at(1, vec![coinbase, tx]);
+
+        assert!(mempool.on_block_connected(&block, params.genesis_timestamp).is_empty());
+        let dropped = mempool.on_block_disconnected(&block, params.genesis_timestamp, &ChainState::new());
+
+        assert!(dropped.is_empty());
+        assert_eq!(1, mempool.len());
+        assert!(mempool.get_tx(&tx_id).is_some());
+    }
+
+    #[test]
+    fn min_fee_rises_above_the_evicted_fee_rate_when_full() {
+        let tx = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+        let size = tx.size();
+        let mut mempool = mempool_without_state_checks(size, ConsensusParams::default());
+
+        assert_eq!(1, mempool.min_fee(size));
+        assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        assert_eq!(101, mempool.min_fee(size));
+        assert_eq!(u64::MAX, mempool.min_fee(size + 1));
+
+        let underpaying = Transaction::new("other_sender".to_string(), "to_string".to_string(), 1234500, 100, 0, 1);
+        assert!(matches!(
+            mempool.add_tx(underpaying, &ChainState::new()),
+            Err(MemPoolError::MemPoolFull { .. })
+        ));
+
+        let paying_min_fee = Transaction::new("other_sender".to_string(), "to_string".to_string(), 1234500, 101, 0, 1);
+        assert!(mempool.add_tx(paying_min_fee.clone(), &ChainState::new()).is_ok());
+        assert!(mempool.get_tx(&paying_min_fee.id).is_some());
+        assert_eq!(1, mempool.len());
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rust-chain-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn dumped_mempool_is_loaded_back_dropping_expired_txs() {
+        let params = ConsensusParams::default();
+        let path = temp_file("mempool-dump");
+        let mut mempool = mempool_without_state_checks(1_000_000, params.clone());
+
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 100, 1, 1);
+        let parent = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1);
+        let expiring = Transaction::new("another_address".to_string(), "to_string".to_string(), 1234500, 10, 0, 1)
+            .with_validity_window(None, Some(LockTime::Height(5)));
+        for tx in [parent.clone(), child.clone(), expiring.clone()] {
+            assert!(mempool.add_tx(tx, &ChainState::new()).is_ok());
+        }
+        assert!(mempool.dump(&path).is_ok());
+
+        let mut restarted = mempool_without_state_checks(1_000_000, params);
+        restarted.update_chain_tip(10, 0);
+        let dropped = restarted.load(&path, &ChainState::new());
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(dropped, Ok(dropped) if dropped == vec![expiring]));
+        assert_eq!(2, restarted.len());
+        assert_eq!(vec![parent.id], restarted.ancestor_ids(&child.id));
+    }
+
+    #[test]
+    fn mempool_file_with_unknown_version_is_rejected() {
+        let path = temp_file("mempool-version");
+        fs::write(&path, r#"{"version": 99, "txs": []}"#).unwrap();
+
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+        let res = mempool.load(&path, &ChainState::new());
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            res,
+            Err(PersistenceError::UnsupportedVersion { found: 99, .. })
+        ));
+    }
+
+    #[test]
+    fn entries_can_be_queried_by_priority_and_address() {
+        let clock = ManualClock::new(1000);
+        let mut mempool = MemPool::with_clock(
+            MemPoolConfig::default(),
+            ConsensusParams::default(),
+            Box::new(policy_without_state_checks()),
+            Box::new(clock.clone()),
+        );
+
+        let parent = Transaction::new("from_address".to_string(), "to_address".to_string(), 1234500, 10, 0, 1);
+        let child = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 300, 1, 1);
+        let other = Transaction::new("other_sender".to_string(), "to_address".to_string(), 1234500, 100, 0, 1);
+        assert!(mempool.add_tx(parent.clone(), &ChainState::new()).is_ok());
+        clock.advance(5);
+        assert!(mempool.add_tx(child.clone(), &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(other.clone(), &ChainState::new()).is_ok());
+
+        let ordered_ids: Vec<&str> = mempool.iter().map(|info| info.tx.id.as_str()).collect();
+        assert_eq!(vec![child.id.as_str(), other.id.as_str(), parent.id.as_str()], ordered_ids);
+
+        let child_info = mempool.get_entry_info(&child.id).unwrap();
+        assert_eq!(1005, child_info.arrival_time);
+        assert_eq!(1, child_info.ancestor_count);
+        assert_eq!(0, child_info.descendant_count);
+        assert_eq!(1000, mempool.get_entry_info(&parent.id).unwrap().arrival_time);
+
+        assert_eq!(vec![&parent, &child], mempool.get_txs_from("from_address"));
+        assert_eq!(vec![&other, &parent], mempool.get_txs_to("to_address"));
+        assert!(mempool.get_txs_to("nobody").is_empty());
+    }
+
+    #[test]
+    fn stats_and_fee_rate_histogram_cover_all_entries() {
+        let mut mempool = mempool_without_state_checks(1_000_000, ConsensusParams::default());
+
+        let cheap = Transaction::new("from_address".to_string(), "to_string".to_string(), 1234500, 1, 0, 1);
+        let expensive = Transaction::new("other_sender".to_string(), "to_string".to_string(), 1234500, 1000, 0, 1);
+        let total_bytes = cheap.size() + expensive.size();
+        assert!(mempool.add_tx(cheap.clone(), &ChainState::new()).is_ok());
+        assert!(mempool.add_tx(expensive.clone(), &ChainState::new()).is_ok());
+
+        assert_eq!(
+            vec![
+                FeeRateBucket {
+                    min_fee_per_kb: 0,
+                    tx_count: 1,
+                    total_bytes: cheap.size(),
+                },
+                FeeRateBucket {
+                    min_fee_per_kb: 100,
+                    tx_count: 1,
+                    total_bytes: expensive.size(),
+                },
+            ],
+            mempool.fee_rate_histogram(&[0, 100])
+        );
+
+        let stats = mempool.stats();
+        assert_eq!(2, stats.tx_count);
+        assert_eq!(2, stats.sender_count);
+        assert_eq!(total_bytes, stats.total_bytes);
+        assert_eq!(1001, stats.total_fees);
+        assert_eq!(1, stats.min_fee_per_kb);
     }
 }
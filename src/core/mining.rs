@@ -1,44 +1,43 @@
 use hex;
-use crate::core::hashing::{hash_to_binary_representation, calculate_hash};
-use super::Transaction;
+use crate::core::hashing::{hash_to_binary_representation, leading_zero_bits};
+use super::{Block, BlockHeader, Transaction};
 
 pub fn mine_new_block(
     height: u64,
     timestamp: i64,
     previous_hash: &str,
-    txs: &Vec<Transaction>,
+    txs: &[Transaction],
+    difficulty: u32,
 ) -> (u64, String) {
     println!("Mining new block...");
 
-    let mut nonce: u64 = 0;
+    let mut header = BlockHeader {
+        height,
+        previous_hash: previous_hash.to_string(),
+        merkle_root: Block::calculate_merkle_root(txs),
+        timestamp,
+        difficulty,
+        nonce: 0,
+    };
 
     loop {
-        if nonce % 100000 == 0 {
+        let nonce = header.nonce;
+        if nonce.is_multiple_of(100000) {
             println!("Still computing...");
         }
 
-        let data = serde_json::json!({
-            "height": height,
-            "previous_hash": previous_hash,
-            "txs": txs,
-            "timestamp": timestamp,
-            "nonce": nonce
-        });
-        let hash = calculate_hash(&data);
-        let binary_hash = hash_to_binary_representation(&hash);
+        let hash = header.calculate_hash();
 
-        if binary_hash.starts_with(DIFFICULTY_PREFIX) {
+        if leading_zero_bits(&hash) >= difficulty {
             println!(
                 "mined! nonce: {}, hash: {}, binary hash: {}",
                 nonce,
-                hex::encode(&hash),
-                binary_hash
+                hex::encode(hash),
+                hash_to_binary_representation(&hash)
             );
             return (nonce, hex::encode(hash));
         }
 
-        nonce += 1;
+        header.nonce += 1;
     }
 }
-
-pub const DIFFICULTY_PREFIX: &str = "00";
\ No newline at end of file
@@ -4,18 +4,61 @@ mod hashing;
 mod history;
 mod errors;
 mod memory_pool;
+mod admission;
+mod fee_estimator;
 mod wallet;
+mod consensus;
+mod clock;
+mod state;
+mod node;
+mod events;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 pub type Block = models::block::Block;
+pub type BlockHeader = models::block::BlockHeader;
+pub type BlockContext<'a> = models::block::BlockContext<'a>;
 pub type Transaction = models::transaction::Transaction;
 pub type TransactionPriority = models::transaction::TransactionPriority;
+pub type LockTime = models::transaction::LockTime;
 pub type History = history::History;
+pub type ChainState = state::ChainState;
+pub type MemPool = memory_pool::MemPool;
+pub type MemPoolConfig = memory_pool::MemPoolConfig;
+pub type MemPoolEntryInfo<'a> = memory_pool::MemPoolEntryInfo<'a>;
+pub type MemPoolStats = memory_pool::MemPoolStats;
+pub type FeeRateBucket = memory_pool::FeeRateBucket;
+pub type FeeEstimator = fee_estimator::FeeEstimator;
+pub type FeeEstimatorConfig = fee_estimator::FeeEstimatorConfig;
+pub type FeeEstimate = fee_estimator::FeeEstimate;
+pub type AdmissionContext<'a> = admission::AdmissionContext<'a>;
+pub type StandardAdmissionPolicy = admission::StandardAdmissionPolicy;
+pub type Wallet = wallet::Wallet;
 pub type WalletKeyPair = wallet::WalletKeyPair;
+pub type Node = node::Node;
+pub type ChainEvent = events::ChainEvent;
+pub type ChainSpec = consensus::ChainSpec;
+pub type ConsensusParams = consensus::ConsensusParams;
+pub type RewardSchedule = consensus::RewardSchedule;
+pub type SystemClock = clock::SystemClock;
+pub type ManualClock = clock::ManualClock;
 
 pub type NaiveReorgStrategy = history::NaiveReorgStrategy;
+pub type MostWorkReorgStrategy = history::MostWorkReorgStrategy;
 
 pub type AppendToHistoryError = errors::AppendToHistoryError;
 pub type TransactionValidationError = errors::TransactionValidationError;
-pub type EmptySignatureError = errors::EmptySignatureError;
+pub type BlockValidationError = errors::BlockValidationError;
+pub type StateTransitionError = errors::StateTransitionError;
+pub type MemPoolError = errors::MemPoolError;
+pub type ConsensusParamsError = errors::ConsensusParamsError;
+pub type PersistenceError = errors::PersistenceError;
 
-pub use mining::mine_new_block as mine_new_block;
\ No newline at end of file
+pub use clock::Clock;
+pub use admission::AdmissionPolicy;
+pub use models::transaction::{COINBASE_SENDER, MIN_TX_SIZE};
+pub use memory_pool::MEMPOOL_FILE_VERSION;
+pub use node::MAX_PENDING_EVENTS;
+pub use history::CHAIN_FILE_VERSION;
+pub use history::{ReorgChainStrategy, ReorgChoice};
+pub use mining::mine_new_block as mine_new_block;
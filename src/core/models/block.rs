@@ -1,72 +1,256 @@
 use crate::core::{
-    hashing::{hash_to_binary_representation, calculate_hash},
-    mining::DIFFICULTY_PREFIX,
+    hashing::{calculate_hash, calculate_merkle_root, leading_zero_bits},
+    BlockValidationError, ConsensusParams,
 };
-use chrono::Utc;
-use hex::FromHexError;
+use serde::{Deserialize, Serialize};
 
 use super::transaction::Transaction;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub height: u64,
     pub hash: String,
     pub previous_hash: String,
+    pub merkle_root: String,
     pub timestamp: i64,
     pub txs: Vec<Transaction>,
+    pub difficulty: u32,
     pub nonce: u64,
 }
 
+/// The part of a block covered by its proof of work. Transactions are
+/// committed to through the merkle root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub timestamp: i64,
+    pub difficulty: u32,
+    pub nonce: u64,
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> [u8; 32] {
+        calculate_hash(&serde_json::json!(self))
+    }
+
+    /// Hex encoded hash, the same as the `hash` of the block.
+    pub fn hash(&self) -> String {
+        hex::encode(self.calculate_hash())
+    }
+
+    /// Expected number of hashes needed to mine a block at this difficulty.
+    pub fn work(&self) -> u128 {
+        1u128 << self.difficulty.min(127)
+    }
+
+    /// Checks that the header hash has at least `difficulty` leading zero
+    /// bits, which can be done before downloading the transactions.
+    pub fn check_proof_of_work(&self, params: &ConsensusParams) -> Result<(), BlockValidationError> {
+        if self.difficulty < params.min_difficulty {
+            return Err(BlockValidationError::InsufficientWork {
+                required: params.min_difficulty,
+                found: self.difficulty,
+            });
+        }
+
+        let work = leading_zero_bits(&self.calculate_hash());
+        if work < self.difficulty {
+            return Err(BlockValidationError::InsufficientWork {
+                required: self.difficulty,
+                found: work,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// What a block needs to know about the chain it extends to run the
+/// contextual checks.
+pub struct BlockContext<'a> {
+    pub parent: &'a Block,
+    pub expected_difficulty: u32,
+    pub median_time_past: i64,
+    pub now: i64,
+}
+
 impl Block {
-    pub fn genesis() -> Block {
-        Block {
+    /// First block of the chain described by `params`. Its hash is computed
+    /// from a header depending on the chain id, genesis timestamp and initial
+    /// difficulty, so that networks with different params do not share it.
+    /// It is trusted as is, and is not mined.
+    pub fn genesis(params: &ConsensusParams) -> Block {
+        let header = BlockHeader {
             height: 0,
-            hash: "0000f816a87f806bb0073dcf026a64fb40c946b5abee2573702828694d5b4c43".to_string(),
-            previous_hash: String::from("genesis"),
-            timestamp: Utc::now().timestamp(),
-            txs: Vec::new(),
-            nonce: 2836
-        }
+            previous_hash: format!("genesis-{}", params.chain_id),
+            merkle_root: Block::calculate_merkle_root(&[]),
+            timestamp: params.genesis_timestamp,
+            difficulty: params.initial_difficulty,
+            nonce: 0,
+        };
+        Block::from_header(header, Vec::new())
     }
 
-    pub fn new(prev_block: &Block, hash: String, timestamp: i64, txs: Vec<Transaction>, nonce: u64) -> Block {
+    pub fn new(
+        prev_block: &Block,
+        hash: String,
+        timestamp: i64,
+        txs: Vec<Transaction>,
+        difficulty: u32,
+        nonce: u64,
+    ) -> Block {
         Block {
             height: prev_block.height + 1,
-            hash: hash,
+            hash,
             previous_hash: prev_block.hash.clone(),
-            timestamp: timestamp,
-            txs: txs,
-            nonce: nonce
+            merkle_root: Block::calculate_merkle_root(&txs),
+            timestamp,
+            txs,
+            difficulty,
+            nonce
+        }
+    }
+
+    /// Block made of `header` and `txs`, which are not checked against its
+    /// merkle root.
+    pub fn from_header(header: BlockHeader, txs: Vec<Transaction>) -> Block {
+        Block {
+            height: header.height,
+            hash: header.hash(),
+            previous_hash: header.previous_hash,
+            merkle_root: header.merkle_root,
+            timestamp: header.timestamp,
+            txs,
+            difficulty: header.difficulty,
+            nonce: header.nonce,
         }
     }
 
-    pub fn verify(&self, prev_block: &Block) -> Result<bool, FromHexError> {
-        if self.previous_hash != prev_block.hash {
-            return Ok(false);
+    pub fn calculate_merkle_root(txs: &[Transaction]) -> String {
+        let leaves: Vec<[u8; 32]> = txs.iter().map(|tx| tx.leaf_hash()).collect();
+        hex::encode(calculate_merkle_root(&leaves))
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            height: self.height,
+            previous_hash: self.previous_hash.clone(),
+            merkle_root: self.merkle_root.clone(),
+            timestamp: self.timestamp,
+            difficulty: self.difficulty,
+            nonce: self.nonce,
         }
+    }
 
-        if self.height != prev_block.height + 1 {
-            return Ok(false);
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self).map(|bytes| bytes.len()).unwrap_or(usize::MAX)
+    }
+
+    /// Checks that only need the block itself: size, proof of work, merkle
+    /// root and transaction signatures. A peer sending a block failing here
+    /// is misbehaving regardless of the state of our chain.
+    pub fn check_context_free(&self, params: &ConsensusParams) -> Result<(), BlockValidationError> {
+        let size = self.size();
+        if size > params.max_block_size {
+            return Err(BlockValidationError::TooLarge {
+                size,
+                max: params.max_block_size,
+            });
         }
 
         let decoded_hash = &hex::decode(&self.hash)?;
-        if !hash_to_binary_representation(&decoded_hash).starts_with(DIFFICULTY_PREFIX) {
-            return Ok(false);
+        let work = leading_zero_bits(decoded_hash);
+        if work < self.difficulty {
+            return Err(BlockValidationError::InsufficientWork {
+                required: self.difficulty,
+                found: work,
+            });
         }
 
-        let data = serde_json::json!({
-            "height": self.height,
-            "previous_hash": &self.previous_hash,
-            "txs": &self.txs,
-            "timestamp": self.timestamp,
-            "nonce": self.nonce
-        });
-        let encoded_hash = hex::encode(calculate_hash(&data));
-
+        let encoded_hash = hex::encode(self.header().calculate_hash());
         if encoded_hash != self.hash {
-            return Ok(false);
+            return Err(BlockValidationError::HashMismatch {
+                declared: self.hash.clone(),
+                computed: encoded_hash,
+            });
+        }
+
+        let merkle_root = Block::calculate_merkle_root(&self.txs);
+        if merkle_root != self.merkle_root {
+            return Err(BlockValidationError::BadMerkleRoot {
+                declared: self.merkle_root.clone(),
+                computed: merkle_root,
+            });
+        }
+
+        for (index, tx) in self.txs.iter().enumerate() {
+            if tx.is_coinbase() && index != 0 {
+                return Err(BlockValidationError::MisplacedCoinbase { index });
+            }
+
+            tx.verify_for_chain(params.chain_id)
+                .map_err(|err| BlockValidationError::InvalidTransaction {
+                    id: tx.id.clone(),
+                    source: err,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the block correctly extends the chain described by `context`
+    /// and that its transactions are within their validity window.
+    pub fn check_contextual(
+        &self,
+        context: &BlockContext,
+        params: &ConsensusParams,
+    ) -> Result<(), BlockValidationError> {
+        if self.previous_hash != context.parent.hash {
+            return Err(BlockValidationError::BadParent {
+                expected: context.parent.hash.clone(),
+                found: self.previous_hash.clone(),
+            });
+        }
+
+        if self.height != context.parent.height + 1 {
+            return Err(BlockValidationError::BadHeight {
+                expected: context.parent.height + 1,
+                found: self.height,
+            });
+        }
+
+        if self.difficulty != context.expected_difficulty {
+            return Err(BlockValidationError::BadDifficulty {
+                expected: context.expected_difficulty,
+                found: self.difficulty,
+            });
+        }
+
+        if self.timestamp <= context.median_time_past {
+            return Err(BlockValidationError::TimestampTooOld {
+                timestamp: self.timestamp,
+                median_time_past: context.median_time_past,
+            });
+        }
+
+        let max_allowed = context.now + params.max_future_drift;
+        if self.timestamp > max_allowed {
+            return Err(BlockValidationError::TimestampTooFarInFuture {
+                timestamp: self.timestamp,
+                max_allowed,
+            });
+        }
+
+        for tx in &self.txs {
+            tx.check_validity_window(self.height, context.median_time_past)
+                .map_err(|err| BlockValidationError::InvalidTransaction {
+                    id: tx.id.clone(),
+                    source: err,
+                })?;
         }
 
-        Ok(true)
+        Ok(())
     }
 }
@@ -1,47 +1,139 @@
+use std::{fmt, str::FromStr};
+
 use secp256k1::{ecdsa::Signature, Message, PublicKey, SecretKey};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::core::{hashing::calculate_hash, EmptySignatureError, TransactionValidationError};
+use crate::core::{hashing::calculate_hash, TransactionValidationError};
 
-#[derive(Debug, Serialize, Clone, Eq)]
+/// Sender of the transaction paying the block reward to the miner.
+pub const COINBASE_SENDER: &str = "coinbase";
+
+/// Size of the smallest serialized transaction: empty addresses, zero
+/// amounts, no validity window and no signature.
+pub const MIN_TX_SIZE: usize = 190;
+
+/// A point in the chain, either a block height or a unix time compared
+/// against the median time past of the chain.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum LockTime {
+    Height(u64),
+    Time(i64),
+}
+
+impl LockTime {
+    fn is_reached(&self, height: u64, median_time_past: i64) -> bool {
+        match self {
+            LockTime::Height(lock_height) => height >= *lock_height,
+            LockTime::Time(lock_time) => median_time_past >= *lock_time,
+        }
+    }
+
+    fn is_passed(&self, height: u64, median_time_past: i64) -> bool {
+        match self {
+            LockTime::Height(lock_height) => height > *lock_height,
+            LockTime::Time(lock_time) => median_time_past > *lock_time,
+        }
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockTime::Height(height) => write!(f, "height {}", height),
+            LockTime::Time(time) => write!(f, "time {}", time),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq)]
 pub struct Transaction {
-    pub nonce: String,
+    pub id: String,
     pub from: String,
     pub to: String,
     pub amount: u64,
     pub fee: u64,
+    /// Position of this transaction among the ones sent by `from`. Each
+    /// sequence number can be included in the chain only once.
+    pub sequence: u64,
+    /// Chain the transaction is meant for, so that it cannot be replayed
+    /// on another network.
+    pub chain_id: u32,
+    /// The transaction can only be included in blocks strictly after this point.
+    pub valid_after: Option<LockTime>,
+    /// The transaction can only be included in blocks strictly before this point.
+    pub expires_at: Option<LockTime>,
     pub signature: Option<Signature>,
 }
 
 impl Transaction {
-    pub fn new(from: String, to: String, amount: u64, fee: u64) -> Transaction {
-        let data = serde_json::json!({
-            "from": from,
-            "to": to,
-            "amount": amount,
-            "fee": fee
-        });
-        Transaction {
-            nonce: hex::encode(calculate_hash(&data)),
-            from: from,
-            to: to,
-            amount: amount,
-            fee: fee,
+    pub fn new(from: String, to: String, amount: u64, fee: u64, sequence: u64, chain_id: u32) -> Transaction {
+        let mut tx = Transaction {
+            id: String::new(),
+            from,
+            to,
+            amount,
+            fee,
+            sequence,
+            chain_id,
+            valid_after: None,
+            expires_at: None,
             signature: None,
+        };
+        tx.id = hex::encode(tx.to_hash());
+        tx
+    }
+
+    /// Restricts the blocks the transaction can be included in. Must be
+    /// called before signing, since the window is part of the signed payload.
+    pub fn with_validity_window(mut self, valid_after: Option<LockTime>, expires_at: Option<LockTime>) -> Transaction {
+        self.valid_after = valid_after;
+        self.expires_at = expires_at;
+        self.id = hex::encode(self.to_hash());
+        self
+    }
+
+    /// Checks that a block at `height`, built on a chain whose median time
+    /// past is `median_time_past`, can include the transaction.
+    pub fn check_validity_window(&self, height: u64, median_time_past: i64) -> Result<(), TransactionValidationError> {
+        if let Some(valid_after) = self.valid_after {
+            if !valid_after.is_passed(height, median_time_past) {
+                return Err(TransactionValidationError::NotYetValid { valid_after });
+            }
         }
+
+        if self.is_expired(height, median_time_past) {
+            return Err(TransactionValidationError::Expired {
+                expires_at: self.expires_at.unwrap(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn is_expired(&self, height: u64, median_time_past: i64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at.is_reached(height, median_time_past))
+    }
+
+    /// The coinbase of a block uses its height as sequence, which keeps the
+    /// ids of rewards paid to the same address unique.
+    pub fn coinbase(to: String, amount: u64, height: u64, chain_id: u32) -> Transaction {
+        Transaction::new(COINBASE_SENDER.to_string(), to, amount, 0, height, chain_id)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.from == COINBASE_SENDER
     }
 
     pub fn validate(&self) -> Result<(), TransactionValidationError> {
-        let data = serde_json::json!({
-            "from": self.from,
-            "to": self.to,
-            "amount": self.amount,
-            "fee": self.fee
-        });
-        if hex::encode(calculate_hash(&data)) == self.nonce {
+        let expected_id = hex::encode(self.to_hash());
+        if expected_id == self.id {
             Ok(())
         } else {
-            Err(TransactionValidationError {})
+            Err(TransactionValidationError::IdMismatch {
+                expected: expected_id,
+                found: self.id.clone(),
+            })
         }
     }
 
@@ -50,67 +142,135 @@ impl Transaction {
         self.signature = Some(secret_key.sign_ecdsa(message));
     }
 
+    /// Hash of the signed payload, which is also the transaction id.
     pub fn to_hash(&self) -> [u8; 32] {
         let data = serde_json::json!({
-            "nonce": &self.nonce,
+            "chain_id": self.chain_id,
             "from": &self.from,
             "to": &self.to,
             "amount": self.amount,
-            "fee": self.fee
+            "fee": self.fee,
+            "sequence": self.sequence,
+            "valid_after": self.valid_after,
+            "expires_at": self.expires_at
         });
         calculate_hash(&data)
     }
 
-    pub fn verify_signature(&self, public_key: &PublicKey) -> Result<(), Box<dyn std::error::Error>> {
+    /// Hash of the whole transaction, signature included, used as a leaf of
+    /// the block merkle tree.
+    pub fn leaf_hash(&self) -> [u8; 32] {
+        calculate_hash(&serde_json::json!(self))
+    }
+
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self).map(|bytes| bytes.len()).unwrap_or(usize::MAX)
+    }
+
+    /// Fee paid per 1000 bytes, rounded down.
+    pub fn fee_per_kb(&self) -> u64 {
+        fee_per_kb(self.fee, self.size())
+    }
+
+    pub fn verify_signature(&self, public_key: &PublicKey) -> Result<(), TransactionValidationError> {
         let message: Message = Message::from_digest(self.to_hash());
         match self.signature {
             Some(sig) => {
                 Ok(sig.verify(&message, public_key)?)
             },
-            None => Err(Box::new(EmptySignatureError::new(format!(
-                "Transaction {} has an empty signature", self.nonce
-            )))),
+            None => Err(TransactionValidationError::MissingSignature {
+                id: self.id.clone(),
+            }),
         }
     }
+
+    /// Checks the id and, unless this is a coinbase, that the transaction
+    /// is signed by the key its `from` address encodes.
+    pub fn verify(&self) -> Result<(), TransactionValidationError> {
+        self.validate()?;
+
+        if self.is_coinbase() {
+            return Ok(());
+        }
+
+        let public_key =
+            PublicKey::from_str(&self.from).map_err(TransactionValidationError::MalformedSender)?;
+        self.verify_signature(&public_key)
+    }
+
+    /// Like `verify`, also rejecting transactions signed for another chain.
+    pub fn verify_for_chain(&self, chain_id: u32) -> Result<(), TransactionValidationError> {
+        if self.chain_id != chain_id {
+            return Err(TransactionValidationError::WrongChain {
+                expected: chain_id,
+                found: self.chain_id,
+            });
+        }
+
+        self.verify()
+    }
 }
 
 impl PartialEq for Transaction {
     fn eq(&self, other: &Self) -> bool {
-        self.nonce == other.nonce
+        self.id == other.id
     }
 }
 
+/// Position of a transaction in the mempool. Transactions paying a higher
+/// fee per byte come first, ties are broken by arrival order.
 #[derive(Debug, Serialize, Clone, Eq)]
 pub struct TransactionPriority {
-    pub nonce: String,
+    pub id: String,
     pub fee: u64,
-    pub amount: u64,
+    pub size: usize,
+    pub arrival: u64,
 }
 
 impl TransactionPriority {
-    pub fn new(nonce: String, fee: u64, amount: u64) -> TransactionPriority {
+    pub fn new(id: String, fee: u64, size: usize, arrival: u64) -> TransactionPriority {
         TransactionPriority {
-            nonce: nonce,
-            fee: fee,
-            amount: amount,
+            id,
+            fee,
+            size,
+            arrival,
         }
     }
 
-    pub fn new_from_tx(tx: &Transaction) -> TransactionPriority {
+    pub fn new_from_tx(tx: &Transaction, arrival: u64) -> TransactionPriority {
         TransactionPriority {
-            nonce: tx.nonce.to_string(),
+            id: tx.id.to_string(),
             fee: tx.fee,
-            amount: tx.amount,
+            size: tx.size(),
+            arrival,
         }
     }
+
+    /// Fee rate in fee units per byte, only meant for display. Comparisons
+    /// are done on the exact fraction.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.size.max(1) as f64
+    }
+
+    /// Fee paid per 1000 bytes, rounded down.
+    pub fn fee_per_kb(&self) -> u64 {
+        fee_per_kb(self.fee, self.size)
+    }
+}
+
+fn fee_per_kb(fee: u64, size: usize) -> u64 {
+    u64::try_from(fee as u128 * 1000 / size.max(1) as u128).unwrap_or(u64::MAX)
 }
 
 impl Ord for TransactionPriority {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other
-            .fee
-            .cmp(&self.fee)
-            .then_with(|| other.amount.cmp(&self.amount))
+        let self_rate = self.fee as u128 * other.size.max(1) as u128;
+        let other_rate = other.fee as u128 * self.size.max(1) as u128;
+
+        other_rate
+            .cmp(&self_rate)
+            .then_with(|| self.arrival.cmp(&other.arrival))
+            .then_with(|| self.id.cmp(&other.id))
     }
 }
 
@@ -122,48 +282,114 @@ impl PartialOrd for TransactionPriority {
 
 impl PartialEq for TransactionPriority {
     fn eq(&self, other: &Self) -> bool {
-        self.nonce == other.nonce
+        self.id == other.id
     }
 }
 
 #[cfg(test)]
 mod transaction_test {
-    use crate::core::Transaction;
+    use crate::core::{LockTime, Transaction, TransactionPriority, TransactionValidationError, MIN_TX_SIZE};
+
+    #[test]
+    fn smallest_transaction_is_min_tx_size_bytes() {
+        let tx = Transaction::new(String::new(), String::new(), 0, 0, 0, 0);
+
+        assert_eq!(MIN_TX_SIZE, tx.size());
+    }
 
     #[test]
-    fn verify_correct_nonce_returns_true() {
+    fn verify_correct_id_returns_true() {
         let tx = Transaction::new(
             "from-address".to_string(),
             "to-address".to_string(),
             12345,
             100,
+            0,
+            1,
         );
 
         assert!(tx.validate().is_ok());
     }
 
     #[test]
-    fn verify_bad_nonce_returns_false() {
+    fn verify_bad_id_returns_false() {
         let mut tx = Transaction::new(
             "from-address".to_string(),
             "to-address".to_string(),
             12345,
             100,
+            0,
+            1,
         );
 
-        tx.nonce = "bad-nonce".to_string();
+        tx.id = "bad-id".to_string();
 
-        assert!(tx.validate().is_err());
+        assert!(matches!(
+            tx.validate(),
+            Err(TransactionValidationError::IdMismatch { found, .. }) if found == "bad-id"
+        ));
 
         let tx2 = Transaction {
-            nonce: "another-bad-nonce".to_string(),
+            id: "another-bad-id".to_string(),
             from: "from-address".to_string(),
             to: "to-address".to_string(),
             amount: 12345,
             fee: 100,
+            sequence: 0,
+            chain_id: 1,
+            valid_after: None,
+            expires_at: None,
             signature: None,
         };
 
         assert!(tx2.validate().is_err());
     }
+
+    #[test]
+    fn identical_payments_with_different_sequences_have_different_ids() {
+        let tx = Transaction::new("from-address".to_string(), "to-address".to_string(), 12345, 100, 0, 1);
+        let tx2 = Transaction::new("from-address".to_string(), "to-address".to_string(), 12345, 100, 1, 1);
+
+        assert_ne!(tx.id, tx2.id);
+    }
+
+    #[test]
+    fn changing_chain_id_invalidates_the_transaction() {
+        let mut tx = Transaction::new("from-address".to_string(), "to-address".to_string(), 12345, 100, 0, 1);
+        let original_id = tx.id.clone();
+
+        tx.chain_id = 2;
+
+        assert!(tx.validate().is_err());
+        assert_ne!(original_id, hex::encode(tx.to_hash()));
+    }
+
+    #[test]
+    fn validity_window_is_checked_against_height_and_median_time_past() {
+        let tx = Transaction::new("from-address".to_string(), "to-address".to_string(), 12345, 100, 0, 1)
+            .with_validity_window(Some(LockTime::Time(1000)), Some(LockTime::Height(10)));
+
+        assert!(tx.validate().is_ok());
+        assert!(matches!(
+            tx.check_validity_window(5, 1000),
+            Err(TransactionValidationError::NotYetValid { .. })
+        ));
+        assert!(tx.check_validity_window(5, 1001).is_ok());
+        assert!(tx.check_validity_window(9, 1001).is_ok());
+        assert!(matches!(
+            tx.check_validity_window(10, 1001),
+            Err(TransactionValidationError::Expired { .. })
+        ));
+    }
+
+    #[test]
+    fn priority_orders_by_fee_rate_then_arrival() {
+        let small = TransactionPriority::new("small".to_string(), 100, 100, 2);
+        let large = TransactionPriority::new("large".to_string(), 150, 1000, 0);
+        let small_late = TransactionPriority::new("small-late".to_string(), 200, 200, 3);
+
+        assert!(small < large);
+        assert!(small < small_late);
+        assert!(small_late < large);
+    }
 }
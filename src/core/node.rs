@@ -0,0 +1,240 @@
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver, SyncSender},
+};
+
+use super::{
+    AppendToHistoryError, Block, ChainEvent, FeeEstimate, FeeEstimator, History, MemPool, MemPoolError,
+    PersistenceError, Transaction,
+};
+
+/// Most events waiting to be received by a subscriber. One that falls
+/// further behind is dropped, closing its receiver.
+pub const MAX_PENDING_EVENTS: usize = 1024;
+
+/// Owns the chain and the mempool and keeps them in sync: every block
+/// connected to or disconnected from the chain is reported to the mempool.
+/// The fee estimator learns from the transactions going through both, and
+/// subscribers are told about every change as a `ChainEvent`.
+pub struct Node {
+    history: History,
+    mempool: MemPool,
+    fee_estimator: FeeEstimator,
+    subscribers: Vec<SyncSender<ChainEvent>>,
+}
+
+impl Node {
+    pub fn new(history: History, mut mempool: MemPool) -> Node {
+        if let Some(tip) = history.get_last_block() {
+            mempool.update_chain_tip(tip.height, history.median_time_past());
+        }
+
+        Node {
+            history,
+            mempool,
+            fee_estimator: FeeEstimator::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Returns a receiver of every change of the chain and of the mempool
+    /// from now on. Dropping it unsubscribes. It is disconnected if more
+    /// than `MAX_PENDING_EVENTS` events are left waiting in it.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Adds `tx` to the mempool, checking it against the state at the tip.
+    pub fn submit_tx(&mut self, tx: Transaction) -> Result<(), MemPoolError> {
+        let accepted_tx = tx.clone();
+        self.mempool.add_tx(tx, self.history.get_state())?;
+
+        let tip_height = self.history.get_last_block().map_or(0, |tip| tip.height);
+        self.fee_estimator.on_tx_accepted(&accepted_tx, tip_height);
+        Node::publish(&mut self.subscribers, || vec![ChainEvent::TxAccepted(accepted_tx)]);
+        Ok(())
+    }
+
+    /// Appends `block` to the chain and removes its transactions, and the
+    /// ones conflicting with them, from the mempool.
+    pub fn submit_block(&mut self, block: Block) -> Result<bool, AppendToHistoryError> {
+        let appended = self.history.try_to_append(block)?;
+
+        if let Some(tip) = self.history.get_last_block() {
+            self.mempool
+                .on_block_connected(tip, self.history.median_time_past());
+            self.fee_estimator.on_block_connected(tip);
+            Node::publish(&mut self.subscribers, || vec![ChainEvent::BlockConnected(tip.clone())]);
+        }
+        self.forget_txs_left_mempool();
+
+        Ok(appended)
+    }
+
+    /// Switches to `other_chain` if the reorg strategy prefers it and every
+    /// block of its branch is valid, see `History::choose_chain`. Blocks of
+    /// the abandoned branch are disconnected, giving their transactions back
+    /// to the mempool, then the blocks of the new branch are connected.
+    /// Returns whether the chain changed.
+    pub fn reorganize(&mut self, other_chain: &[Block]) -> Result<bool, AppendToHistoryError> {
+        let new_history = self.history.choose_chain(other_chain)?;
+
+        let old_chain = self.history.get_chain();
+        let new_chain = new_history.get_chain();
+        let fork_index = old_chain
+            .iter()
+            .zip(new_chain)
+            .take_while(|(old_block, new_block)| old_block.hash == new_block.hash)
+            .count();
+        if fork_index == old_chain.len() && fork_index == new_chain.len() {
+            return Ok(false);
+        }
+
+        let fork_median_time_past = fork_index
+            .checked_sub(1)
+            .and_then(|fork_height| self.history.median_time_past_at_height(fork_height as u64))
+            .unwrap_or(i64::MIN);
+        self.mempool.on_blocks_disconnected(
+            &old_chain[fork_index..],
+            fork_median_time_past,
+            new_history.get_state(),
+        );
+
+        for block in &new_chain[fork_index..] {
+            let median_time_past = new_history
+                .median_time_past_at_height(block.height)
+                .unwrap_or(i64::MIN);
+            self.mempool.on_block_connected(block, median_time_past);
+            self.fee_estimator.on_block_connected(block);
+        }
+        self.forget_txs_left_mempool();
+
+        let old_history = std::mem::replace(&mut self.history, new_history);
+        let events = || {
+            let disconnected = &old_history.get_chain()[fork_index..];
+            let connected = &self.history.get_chain()[fork_index..];
+            let reorganized = ChainEvent::Reorganized {
+                fork_height: fork_index.saturating_sub(1) as u64,
+                old_tip: old_history.get_last_block().map_or(String::new(), |tip| tip.hash.clone()),
+                new_tip: self.history.get_last_block().map_or(String::new(), |tip| tip.hash.clone()),
+                disconnected: disconnected.iter().map(|block| block.hash.clone()).collect(),
+                connected: connected.iter().map(|block| block.hash.clone()).collect(),
+            };
+            let given_back = disconnected
+                .iter()
+                .flat_map(|block| &block.txs)
+                .filter_map(|tx| self.mempool.get_tx(&tx.id))
+                .map(|tx| ChainEvent::TxAccepted(tx.clone()));
+
+            disconnected
+                .iter()
+                .rev()
+                .map(|block| ChainEvent::BlockDisconnected(block.clone()))
+                .chain(connected.iter().map(|block| ChainEvent::BlockConnected(block.clone())))
+                .chain(std::iter::once(reorganized))
+                .chain(given_back)
+                .collect()
+        };
+        Node::publish(&mut self.subscribers, events);
+        Ok(true)
+    }
+
+    pub fn dump_mempool<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        self.mempool.dump(path)
+    }
+
+    /// Loads a mempool dump, dropping the transactions that are no longer
+    /// valid on top of the current chain.
+    pub fn load_mempool<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Transaction>, PersistenceError> {
+        self.mempool.load(path, self.history.get_state())
+    }
+
+    pub fn dump_chain<P: AsRef<Path>>(&self, path: P) -> Result<(), PersistenceError> {
+        self.history.dump(path)
+    }
+
+    /// Appends the blocks of a chain dump that are not in the chain yet,
+    /// stopping at the first one that does not validate. Returns how many
+    /// blocks were appended.
+    pub fn load_chain<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, PersistenceError> {
+        let mut appended = 0;
+        for block in History::load_blocks(path)? {
+            if self.history.get_block(&block.hash).is_some() {
+                continue;
+            }
+            if self.submit_block(block).is_err() {
+                break;
+            }
+            appended += 1;
+        }
+        Ok(appended)
+    }
+
+    /// Fee rate to pay to get confirmed within `target` blocks with the
+    /// given `confidence`, between 0 and 1.
+    pub fn estimate_fee(&self, target: u64, confidence: f64) -> Option<FeeEstimate> {
+        self.fee_estimator.estimate_smart_fee(target, confidence)
+    }
+
+    pub fn get_history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn get_mempool(&self) -> &MemPool {
+        &self.mempool
+    }
+
+    /// Takes the transactions for the next block out of the mempool.
+    pub fn take_txs_for_block(&mut self, limit: usize) -> Vec<Transaction> {
+        self.mempool.take_txs_w_limit(limit)
+    }
+
+    /// Builds the events only if someone listens, and forgets the
+    /// subscribers that dropped their receiver or are too far behind.
+    fn publish<F: FnOnce() -> Vec<ChainEvent>>(subscribers: &mut Vec<SyncSender<ChainEvent>>, events: F) {
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let events = events();
+        subscribers.retain(|subscriber| events.iter().all(|event| subscriber.try_send(event.clone()).is_ok()));
+    }
+
+    fn forget_txs_left_mempool(&mut self) {
+        let mempool = &self.mempool;
+        self.fee_estimator
+            .retain_tracked(|id| mempool.get_tx(id).is_some());
+    }
+}
+
+#[cfg(test)]
+mod node_test {
+    use crate::core::{
+        test_utils::{block_at, test_params},
+        History, MemPool, NaiveReorgStrategy,
+    };
+
+    use super::{ChainEvent, Node, MAX_PENDING_EVENTS};
+
+    #[test]
+    fn subscribers_too_far_behind_are_dropped() {
+        let params = test_params();
+        let history = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        let mut node = Node::new(history, MemPool::new(1_000_000, params));
+        let lagging = node.subscribe();
+        let reading = node.subscribe();
+
+        Node::publish(&mut node.subscribers, || {
+            vec![ChainEvent::BlockConnected(block_at(1, Vec::new())); MAX_PENDING_EVENTS]
+        });
+        assert_eq!(MAX_PENDING_EVENTS, reading.try_iter().count());
+        Node::publish(&mut node.subscribers, || vec![ChainEvent::BlockConnected(block_at(2, Vec::new()))]);
+
+        assert_eq!(1, node.subscribers.len());
+        assert_eq!(MAX_PENDING_EVENTS, lagging.try_iter().count());
+        assert!(lagging.recv().is_err());
+        assert_eq!(1, reading.try_iter().count());
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{Block, BlockValidationError, ConsensusParams, StateTransitionError, Transaction};
+
+#[derive(Debug, Clone)]
+struct ImmatureReward {
+    height: u64,
+    address: String,
+    amount: u64,
+}
+
+/// Balances resulting from applying every block of the chain in order.
+#[derive(Debug, Clone, Default)]
+pub struct ChainState {
+    balances: HashMap<String, u64>,
+    immature_rewards: VecDeque<ImmatureReward>,
+    next_sequences: HashMap<String, u64>,
+}
+
+impl ChainState {
+    pub fn new() -> ChainState {
+        ChainState::default()
+    }
+
+    pub fn from_chain(chain: &[Block], params: &ConsensusParams) -> Result<ChainState, BlockValidationError> {
+        let mut state = ChainState::new();
+        for block in chain {
+            state.apply_block(block, params)?;
+        }
+        Ok(state)
+    }
+
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// Sequence number the next transaction sent by `address` must have.
+    pub fn get_next_sequence(&self, address: &str) -> u64 {
+        self.next_sequences.get(address).copied().unwrap_or(0)
+    }
+
+    /// Runs the state checks of `block` and, only if all of them pass,
+    /// applies its transactions. On error the state is left untouched.
+    pub fn apply_block(&mut self, block: &Block, params: &ConsensusParams) -> Result<(), BlockValidationError> {
+        let mut changed_balances: HashMap<String, u64> = HashMap::new();
+        let mut changed_sequences: HashMap<&str, u64> = HashMap::new();
+        let mut total_fees: u64 = 0;
+
+        let matured_count = self
+            .immature_rewards
+            .iter()
+            .take_while(|reward| reward.height + params.coinbase_maturity <= block.height)
+            .count();
+        for reward in self.immature_rewards.iter().take(matured_count) {
+            let balance = self.balance_with_changes(&changed_balances, &reward.address);
+            changed_balances.insert(reward.address.clone(), balance.saturating_add(reward.amount));
+        }
+
+        for tx in block.txs.iter().filter(|tx| !tx.is_coinbase()) {
+            let to_error = |err| BlockValidationError::BadStateTransition {
+                id: tx.id.clone(),
+                source: err,
+            };
+
+            let expected_sequence = changed_sequences
+                .get(tx.from.as_str())
+                .copied()
+                .unwrap_or_else(|| self.get_next_sequence(&tx.from));
+            if tx.sequence != expected_sequence {
+                return Err(to_error(StateTransitionError::BadSequence {
+                    address: tx.from.clone(),
+                    expected: expected_sequence,
+                    found: tx.sequence,
+                }));
+            }
+            changed_sequences.insert(&tx.from, expected_sequence + 1);
+
+            let required = tx.amount.saturating_add(tx.fee);
+            let balance = self.balance_with_changes(&changed_balances, &tx.from);
+            if balance < required {
+                return Err(to_error(StateTransitionError::InsufficientBalance {
+                    address: tx.from.clone(),
+                    balance,
+                    required,
+                }));
+            }
+            changed_balances.insert(tx.from.clone(), balance - required);
+
+            let to_balance = self.balance_with_changes(&changed_balances, &tx.to);
+            changed_balances.insert(tx.to.clone(), to_balance.saturating_add(tx.amount));
+
+            total_fees = total_fees.saturating_add(tx.fee);
+        }
+
+        let coinbase: Option<&Transaction> = block.txs.first().filter(|tx| tx.is_coinbase());
+        if let Some(coinbase) = coinbase {
+            let to_error = |err| BlockValidationError::BadStateTransition {
+                id: coinbase.id.clone(),
+                source: err,
+            };
+
+            if coinbase.sequence != block.height {
+                return Err(to_error(StateTransitionError::BadSequence {
+                    address: coinbase.from.clone(),
+                    expected: block.height,
+                    found: coinbase.sequence,
+                }));
+            }
+
+            let max = params.block_reward(block.height).saturating_add(total_fees);
+            if coinbase.amount > max {
+                return Err(to_error(StateTransitionError::ExcessiveCoinbase {
+                    amount: coinbase.amount,
+                    max,
+                }));
+            }
+        }
+
+        self.immature_rewards.drain(..matured_count);
+        self.balances.extend(changed_balances);
+        self.next_sequences.extend(
+            changed_sequences
+                .into_iter()
+                .map(|(address, sequence)| (address.to_string(), sequence)),
+        );
+
+        if let Some(coinbase) = coinbase {
+            if params.coinbase_maturity == 0 {
+                let balance = self.get_balance(&coinbase.to);
+                self.balances
+                    .insert(coinbase.to.clone(), balance.saturating_add(coinbase.amount));
+            } else {
+                self.immature_rewards.push_back(ImmatureReward {
+                    height: block.height,
+                    address: coinbase.to.clone(),
+                    amount: coinbase.amount,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn balance_with_changes(&self, changed_balances: &HashMap<String, u64>, address: &str) -> u64 {
+        changed_balances
+            .get(address)
+            .copied()
+            .unwrap_or_else(|| self.get_balance(address))
+    }
+}
+
+#[cfg(test)]
+mod state_test {
+    use crate::core::{
+        test_utils::block_at, BlockValidationError, ConsensusParams, StateTransitionError, Transaction,
+    };
+
+    use super::ChainState;
+
+    fn params_with_maturity(coinbase_maturity: u64) -> ConsensusParams {
+        ConsensusParams {
+            coinbase_maturity,
+            ..ConsensusParams::default()
+        }
+    }
+
+    fn coinbase(to: &str, amount: u64, height: u64) -> Transaction {
+        Transaction::coinbase(to.to_string(), amount, height, 1)
+    }
+
+    fn transfer(from: &str, to: &str, amount: u64, fee: u64, sequence: u64) -> Transaction {
+        Transaction::new(from.to_string(), to.to_string(), amount, fee, sequence, 1)
+    }
+
+    #[test]
+    fn coinbase_reward_is_spendable_only_after_maturity() {
+        let params = params_with_maturity(2);
+        let mut state = ChainState::new();
+
+        let reward = coinbase("miner", params.block_reward(1), 1);
+        assert!(state.apply_block(&block_at(1, vec![reward]), &params).is_ok());
+        assert_eq!(0, state.get_balance("miner"));
+
+        assert!(state.apply_block(&block_at(2, Vec::new()), &params).is_ok());
+        assert_eq!(0, state.get_balance("miner"));
+
+        assert!(state.apply_block(&block_at(3, Vec::new()), &params).is_ok());
+        assert_eq!(params.block_reward(1), state.get_balance("miner"));
+    }
+
+    #[test]
+    fn coinbase_cannot_claim_more_than_reward_and_fees() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+
+        let reward = coinbase("miner", params.block_reward(1) + 1, 1);
+        let res = state.apply_block(&block_at(1, vec![reward]), &params);
+
+        assert!(matches!(
+            res,
+            Err(BlockValidationError::BadStateTransition {
+                source: StateTransitionError::ExcessiveCoinbase { .. },
+                ..
+            })
+        ));
+        assert_eq!(0, state.get_balance("miner"));
+    }
+
+    #[test]
+    fn coinbase_sequence_must_be_the_block_height() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+
+        let reward = coinbase("miner", params.block_reward(1), 2);
+        let res = state.apply_block(&block_at(1, vec![reward]), &params);
+
+        assert!(matches!(
+            res,
+            Err(BlockValidationError::BadStateTransition {
+                source: StateTransitionError::BadSequence { expected: 1, found: 2, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn transfer_moves_amount_and_pays_fee_to_miner() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+        let reward = params.block_reward(1);
+
+        assert!(state
+            .apply_block(&block_at(1, vec![coinbase("alice", reward, 1)]), &params)
+            .is_ok());
+
+        let payment = transfer("alice", "bob", 10, 5, 0);
+        let miner_reward = coinbase("miner", params.block_reward(2) + 5, 2);
+        assert!(state
+            .apply_block(&block_at(2, vec![miner_reward, payment]), &params)
+            .is_ok());
+
+        assert_eq!(reward - 15, state.get_balance("alice"));
+        assert_eq!(10, state.get_balance("bob"));
+        assert_eq!(params.block_reward(2) + 5, state.get_balance("miner"));
+        assert_eq!(1, state.get_next_sequence("alice"));
+        assert_eq!(0, state.get_next_sequence("bob"));
+    }
+
+    #[test]
+    fn failed_block_leaves_state_untouched() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+
+        assert!(state
+            .apply_block(&block_at(1, vec![coinbase("alice", 20, 1)]), &params)
+            .is_ok());
+
+        let first = transfer("alice", "bob", 10, 0, 0);
+        let second = transfer("alice", "carol", 11, 0, 1);
+        let res = state.apply_block(&block_at(2, vec![first, second]), &params);
+
+        assert!(matches!(
+            res,
+            Err(BlockValidationError::BadStateTransition {
+                source: StateTransitionError::InsufficientBalance { balance: 10, required: 11, .. },
+                ..
+            })
+        ));
+        assert_eq!(20, state.get_balance("alice"));
+        assert_eq!(0, state.get_balance("bob"));
+        assert_eq!(0, state.get_next_sequence("alice"));
+    }
+
+    #[test]
+    fn identical_payments_with_consecutive_sequences_are_both_applied() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+
+        assert!(state
+            .apply_block(&block_at(1, vec![coinbase("alice", 50, 1)]), &params)
+            .is_ok());
+
+        let payments = vec![transfer("alice", "bob", 10, 0, 0), transfer("alice", "bob", 10, 0, 1)];
+        assert!(state.apply_block(&block_at(2, payments), &params).is_ok());
+
+        assert_eq!(20, state.get_balance("bob"));
+    }
+
+    #[test]
+    fn replayed_transaction_is_rejected() {
+        let params = params_with_maturity(0);
+        let mut state = ChainState::new();
+
+        assert!(state
+            .apply_block(&block_at(1, vec![coinbase("alice", 50, 1)]), &params)
+            .is_ok());
+
+        let payment = transfer("alice", "bob", 10, 0, 0);
+        assert!(state.apply_block(&block_at(2, vec![payment.clone()]), &params).is_ok());
+
+        let res = state.apply_block(&block_at(3, vec![payment]), &params);
+        assert!(matches!(
+            res,
+            Err(BlockValidationError::BadStateTransition {
+                source: StateTransitionError::BadSequence { expected: 1, found: 0, .. },
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+use super::{mine_new_block, Block, ConsensusParams, History, Transaction};
+
+/// Params of a chain where blocks are mined at once and rewards can be
+/// spent right away.
+pub(crate) fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        initial_difficulty: 1,
+        coinbase_maturity: 0,
+        ..ConsensusParams::default()
+    }
+}
+
+/// Mines a block with `txs` on top of `hs`, one second after its median
+/// time past.
+pub(crate) fn mine_on_top(hs: &History, txs: Vec<Transaction>) -> Block {
+    mine_on_top_at(hs, hs.median_time_past() + 1, txs)
+}
+
+pub(crate) fn mine_on_top_at(hs: &History, timestamp: i64, txs: Vec<Transaction>) -> Block {
+    let prev_block = hs.get_last_block().unwrap();
+    let difficulty = hs.next_difficulty();
+
+    let (nonce, hash) = mine_new_block(prev_block.height + 1, timestamp, &prev_block.hash, &txs, difficulty);
+    Block::new(prev_block, hash, timestamp, txs, difficulty, nonce)
+}
+
+/// Block at `height` with `txs`, neither mined nor linked to a chain.
+pub(crate) fn block_at(height: u64, txs: Vec<Transaction>) -> Block {
+    let mut block = Block::genesis(&ConsensusParams::default());
+    block.height = height;
+    block.txs = txs;
+    block
+}
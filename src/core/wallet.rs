@@ -37,6 +37,11 @@ impl WalletKeyPair {
             public_key,
         }
     }
+
+    /// Address other users send funds to, the hex encoded public key.
+    pub fn address(&self) -> String {
+        self.public_key.to_string()
+    }
 }
 
 #[cfg(test)]
@@ -52,7 +57,7 @@ mod wallet_test {
         assert_eq!(0, wallet.get_public_keys().len());
 
         let key_pair = WalletKeyPair::new();
-        let public_key = key_pair.public_key.clone();
+        let public_key = key_pair.public_key;
         wallet.add_key_pair(key_pair);
 
         let retrieved_key_pair = wallet.get_public_keys();
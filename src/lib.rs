@@ -1 +1,3 @@
-pub mod core;
\ No newline at end of file
+pub mod core;
+pub mod network;
+pub mod rpc;
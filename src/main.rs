@@ -1,29 +1,105 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
 use chrono::Utc;
-use rust_chain::core::{mine_new_block, Block, History, NaiveReorgStrategy};
+use rust_chain::core::{
+    mine_new_block, Block, ConsensusParams, History, MemPool, MemPoolConfig, MostWorkReorgStrategy, Node,
+};
+use rust_chain::rpc::{RpcConfig, RpcServer};
+
+/// File the RPC auth token is written to, in the working directory.
+const RPC_COOKIE_FILE: &str = "rpc.cookie";
 
 fn main() {
     println!("Starting the rust chain...");
 
-    let mut h = History::new(Box::new(NaiveReorgStrategy {}));
+    let params = match env::args().nth(1) {
+        Some(path) => match ConsensusParams::from_file(&path) {
+            Ok(params) => params,
+            Err(e) => {
+                eprintln!("Error occurred while loading consensus params from {}: {}", path, e);
+                return;
+            }
+        },
+        None => ConsensusParams::default(),
+    };
 
-    loop {
-        let prev_block = h.get_last_block().unwrap();
-        let height = h.get_height();
-        let timestamp = Utc::now().timestamp();
-        let txs = Vec::new();
+    let mempool = MemPool::with_config(MemPoolConfig::default(), params.clone());
+    let mut node = Node::new(History::new(params, Box::new(MostWorkReorgStrategy {})), mempool);
 
-        if height == 5 {
-            return;
+    let mempool_path = env::args().nth(2);
+    if let Some(path) = mempool_path.as_ref().filter(|path| Path::new(path).exists()) {
+        match node.load_mempool(path) {
+            Ok(dropped) => println!("Mempool loaded, {} transactions dropped", dropped.len()),
+            Err(e) => eprintln!("Error occurred while loading the mempool from {}: {}", path, e),
         }
+    }
+
+    let node = Arc::new(Mutex::new(node));
+    // The node keeps answering RPC requests once done mining if a listen
+    // address is given, until the `stop` method is called
+    let rpc_server = match env::args().nth(3) {
+        Some(addr) => {
+            let config = RpcConfig {
+                cookie_path: Some(PathBuf::from(RPC_COOKIE_FILE)),
+                ..RpcConfig::default()
+            };
+            match RpcServer::start(Arc::clone(&node), &addr, config) {
+                Ok(server) => {
+                    println!("RPC server listening on {}, token in {}", server.local_addr(), RPC_COOKIE_FILE);
+                    Some(server)
+                }
+                Err(e) => {
+                    eprintln!("Error occurred while starting the RPC server on {}: {}", addr, e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    loop {
+        // The node is only locked around mining, so that RPC requests are
+        // answered in the meantime
+        let (txs, prev_block, height, timestamp, difficulty) = {
+            let mut node = node.lock().unwrap();
+            let stopped = rpc_server.as_ref().is_some_and(|server| !server.is_running());
+            if node.get_history().get_height() >= 5 || stopped {
+                break;
+            }
+
+            let txs = node.take_txs_for_block(100);
+            let h = node.get_history();
+            let prev_block = h.get_last_block().unwrap().clone();
+            let height = h.get_height();
+            let timestamp = Utc::now().timestamp().max(h.median_time_past() + 1);
+            (txs, prev_block, height, timestamp, h.next_difficulty())
+        };
 
         println!("Start computing hash...");
-        let (nonce, hash) = mine_new_block(height as u64, timestamp, &prev_block.hash, &txs);
+        let (nonce, hash) = mine_new_block(height as u64, timestamp, &prev_block.hash, &txs, difficulty);
         println!("Computed hash");
-        let new_block = Block::new(prev_block, hash, timestamp, txs, nonce);
+        let new_block = Block::new(&prev_block, hash, timestamp, txs, difficulty, nonce);
         println!("Appending new block");
-        match h.try_to_append(new_block) {
+        match node.lock().unwrap().submit_block(new_block) {
             Ok(_) => println!("Block appended successfully"),
             Err(e) => eprintln!("Error occurred while trying to append a new block: {}", e)
         }
     }
+
+    if let Some(server) = rpc_server {
+        println!("Done mining, serving RPC requests until stopped");
+        server.wait();
+        println!("Stopping");
+    }
+
+    // Saved last, so that the transactions received over RPC are kept too
+    if let Some(path) = &mempool_path {
+        if let Err(e) = node.lock().unwrap().dump_mempool(path) {
+            eprintln!("Error occurred while saving the mempool to {}: {}", path, e);
+        }
+    }
 }
@@ -0,0 +1,317 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use rand::seq::{IteratorRandom, SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use super::{NetworkError, PeerAddress};
+
+/// Version of the format written by `AddressBook::save`.
+pub const ADDRESS_BOOK_FILE_VERSION: u32 = 1;
+
+/// Failed connections after which an address that never worked is forgotten.
+const MAX_FAILURES: u32 = 5;
+
+/// What is known of the address of a node accepting connections. Times are
+/// in seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownAddress {
+    pub addr: SocketAddr,
+    /// Last time the node was heard of, from us or from other peers.
+    pub last_seen: i64,
+    /// Last time we completed a handshake with the node.
+    pub last_success: Option<i64>,
+    pub last_attempt: Option<i64>,
+    /// Failed connections since the last successful one.
+    pub failures: u32,
+}
+
+/// Network group of `ip`, the /16 of IPv4 addresses and the /32 of IPv6
+/// ones. Outbound peers are picked in different groups when possible, so
+/// that a single operator cannot easily surround the node.
+pub fn net_group(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => [&[4], &ip.octets()[..2]].concat(),
+        IpAddr::V6(ip) => [&[6], &ip.octets()[..4]].concat(),
+    }
+}
+
+/// Addresses of the nodes we know of, learnt from seed nodes, peers and
+/// our own connections. Past `capacity` addresses the least useful ones
+/// are forgotten.
+#[derive(Debug)]
+pub struct AddressBook {
+    capacity: usize,
+    addresses: HashMap<SocketAddr, KnownAddress>,
+}
+
+impl AddressBook {
+    pub fn new(capacity: usize) -> AddressBook {
+        AddressBook {
+            capacity,
+            addresses: HashMap::new(),
+        }
+    }
+
+    /// Reads an address book written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P, capacity: usize) -> Result<AddressBook, NetworkError> {
+        let content: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+        let version = content["version"].as_u64().unwrap_or(0);
+        if version != ADDRESS_BOOK_FILE_VERSION as u64 {
+            return Err(NetworkError::UnsupportedAddressBookVersion {
+                found: version,
+                supported: ADDRESS_BOOK_FILE_VERSION,
+            });
+        }
+
+        let addresses: Vec<KnownAddress> = serde_json::from_value(content["addresses"].clone())?;
+        let mut book = AddressBook::new(capacity);
+        for address in addresses.into_iter().take(capacity) {
+            book.addresses.insert(address.addr, address);
+        }
+        Ok(book)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), NetworkError> {
+        let addresses: Vec<&KnownAddress> = self.addresses.values().collect();
+        let content = serde_json::json!({
+            "version": ADDRESS_BOOK_FILE_VERSION,
+            "addresses": addresses,
+        });
+        fs::write(path, serde_json::to_vec(&content)?)?;
+        Ok(())
+    }
+
+    /// Records an address heard of at `address.last_seen`, which cannot be
+    /// later than `now`. Returns `true` if the address was not known.
+    pub fn add(&mut self, address: PeerAddress, now: i64) -> bool {
+        if address.addr.port() == 0 || address.addr.ip().is_unspecified() || self.capacity == 0 {
+            return false;
+        }
+
+        let last_seen = address.last_seen.min(now);
+        if let Some(known) = self.addresses.get_mut(&address.addr) {
+            known.last_seen = known.last_seen.max(last_seen);
+            return false;
+        }
+
+        if self.addresses.len() >= self.capacity {
+            self.evict();
+        }
+        self.addresses.insert(
+            address.addr,
+            KnownAddress {
+                addr: address.addr,
+                last_seen,
+                last_success: None,
+                last_attempt: None,
+                failures: 0,
+            },
+        );
+        true
+    }
+
+    pub fn mark_attempt(&mut self, addr: &SocketAddr, now: i64) {
+        if let Some(known) = self.addresses.get_mut(addr) {
+            known.last_attempt = Some(now);
+        }
+    }
+
+    /// Records a successful connection to `addr`, adding it if unknown.
+    pub fn mark_success(&mut self, addr: SocketAddr, now: i64) {
+        self.add(PeerAddress { addr, last_seen: now }, now);
+        if let Some(known) = self.addresses.get_mut(&addr) {
+            known.last_seen = now;
+            known.last_success = Some(now);
+            known.last_attempt = Some(now);
+            known.failures = 0;
+        }
+    }
+
+    /// Records a failed connection to `addr`. Addresses that never worked
+    /// are forgotten after a few failures.
+    pub fn mark_failure(&mut self, addr: &SocketAddr) {
+        let forget = match self.addresses.get_mut(addr) {
+            Some(known) => {
+                known.failures += 1;
+                known.last_success.is_none() && known.failures >= MAX_FAILURES
+            }
+            None => false,
+        };
+        if forget {
+            self.addresses.remove(addr);
+        }
+    }
+
+    /// Picks an address to connect to among those not attempted for
+    /// `retry_interval` seconds and accepted by `filter`. Addresses outside
+    /// of `used_groups` are preferred, then the ones that worked before.
+    pub fn select<F: Fn(&KnownAddress) -> bool>(
+        &self,
+        used_groups: &HashSet<Vec<u8>>,
+        retry_interval: i64,
+        now: i64,
+        filter: F,
+    ) -> Option<SocketAddr> {
+        let candidates: Vec<&KnownAddress> = self
+            .addresses
+            .values()
+            .filter(|known| known.last_attempt.is_none_or(|attempt| now - attempt >= retry_interval))
+            .filter(|known| filter(known))
+            .collect();
+
+        let rank = |known: &KnownAddress| {
+            (
+                used_groups.contains(&net_group(&known.addr.ip())),
+                known.last_success.is_none(),
+            )
+        };
+        let best = candidates.iter().map(|known| rank(known)).min()?;
+        candidates
+            .into_iter()
+            .filter(|known| rank(known) == best)
+            .choose(&mut rand::thread_rng())
+            .map(|known| known.addr)
+    }
+
+    /// Random addresses to answer a `GetAddr` with.
+    pub fn sample(&self, max: usize) -> Vec<PeerAddress> {
+        let mut addresses: Vec<PeerAddress> = self
+            .addresses
+            .values()
+            .map(|known| PeerAddress {
+                addr: known.addr,
+                last_seen: known.last_seen,
+            })
+            .collect();
+        addresses.shuffle(&mut rand::thread_rng());
+        addresses.truncate(max);
+        addresses
+    }
+
+    pub fn remove(&mut self, addr: &SocketAddr) -> bool {
+        self.addresses.remove(addr).is_some()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&KnownAddress> {
+        self.addresses.get(addr)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &KnownAddress> {
+        self.addresses.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Forgets the address that never worked and was seen the longest ago,
+    /// or the one seen the longest ago if they all worked.
+    fn evict(&mut self) {
+        let worst = self
+            .addresses
+            .values()
+            .min_by_key(|known| (known.last_success.is_some(), known.last_seen))
+            .map(|known| known.addr);
+        if let Some(addr) = worst {
+            self.addresses.remove(&addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod address_book_test {
+    use std::{
+        collections::HashSet,
+        env,
+        net::{IpAddr, SocketAddr},
+    };
+
+    use crate::network::PeerAddress;
+
+    use super::{net_group, AddressBook};
+
+    fn address(addr: &str, last_seen: i64) -> PeerAddress {
+        PeerAddress {
+            addr: addr.parse().unwrap(),
+            last_seen,
+        }
+    }
+
+    #[test]
+    fn addresses_never_reached_are_evicted_first() {
+        let mut book = AddressBook::new(2);
+
+        assert!(book.add(address("10.0.0.1:8333", 10), 100));
+        assert!(book.add(address("10.0.0.2:8333", 20), 100));
+        book.mark_success("10.0.0.1:8333".parse().unwrap(), 30);
+        assert!(!book.add(address("10.0.0.1:8333", 40), 100));
+        assert!(book.add(address("10.0.0.3:8333", 50), 100));
+
+        assert_eq!(2, book.len());
+        assert!(book.get(&"10.0.0.1:8333".parse().unwrap()).is_some());
+        assert!(book.get(&"10.0.0.2:8333".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn addresses_that_never_worked_are_forgotten_after_failures() {
+        let mut book = AddressBook::new(10);
+        let addr: SocketAddr = "10.0.0.1:8333".parse().unwrap();
+        book.add(address("10.0.0.1:8333", 10), 100);
+
+        for _ in 0..5 {
+            book.mark_failure(&addr);
+        }
+
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn selection_prefers_unused_groups_and_skips_recent_attempts() {
+        let mut book = AddressBook::new(10);
+        book.add(address("10.0.0.1:8333", 10), 100);
+        book.add(address("10.0.0.2:8333", 10), 100);
+        book.add(address("10.1.0.1:8333", 10), 100);
+        let used_groups: HashSet<Vec<u8>> = [net_group(&"10.0.0.3".parse::<IpAddr>().unwrap())].into();
+
+        assert_eq!(
+            Some("10.1.0.1:8333".parse().unwrap()),
+            book.select(&used_groups, 60, 100, |_| true)
+        );
+
+        book.mark_attempt(&"10.1.0.1:8333".parse().unwrap(), 100);
+        let selected = book.select(&used_groups, 60, 130, |_| true).unwrap();
+        assert_eq!(
+            net_group(&"10.0.0.3".parse::<IpAddr>().unwrap()),
+            net_group(&selected.ip())
+        );
+    }
+
+    #[test]
+    fn saved_address_book_can_be_loaded() {
+        let path = env::temp_dir().join(format!("rust-chain-address-book-{}.json", std::process::id()));
+        let mut book = AddressBook::new(10);
+        book.add(address("10.0.0.1:8333", 10), 100);
+        book.mark_success("[2001:db8::1]:8333".parse().unwrap(), 50);
+
+        book.save(&path).unwrap();
+        let loaded = AddressBook::load(&path, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, loaded.len());
+        assert_eq!(
+            Some(50),
+            loaded
+                .get(&"[2001:db8::1]:8333".parse().unwrap())
+                .and_then(|known| known.last_success)
+        );
+    }
+}
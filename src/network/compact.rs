@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::core::{Block, BlockHeader, ConsensusParams, MemPool, Transaction, MIN_TX_SIZE};
+
+use super::NetworkError;
+
+/// Identifies a transaction of a block in 6 bytes. Mixing in the block hash
+/// makes collisions between transactions differ from block to block.
+pub fn short_tx_id(block_hash: &str, tx_id: &str) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(block_hash.as_bytes());
+    hasher.update(tx_id.as_bytes());
+    hasher.finalize()[..6]
+        .iter()
+        .fold(0u64, |short_id, byte| (short_id << 8) | *byte as u64)
+}
+
+/// Transaction sent in full in a compact block, because the receiver cannot
+/// have it in its mempool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefilledTx {
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+/// A block described by its header and the short ids of its transactions,
+/// which the receiver most likely already has in its mempool. Coinbase
+/// transactions are sent in full.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    /// Short ids of the transactions not prefilled, in block order.
+    pub short_ids: Vec<u64>,
+    pub prefilled_txs: Vec<PrefilledTx>,
+}
+
+impl CompactBlock {
+    pub fn from_block(block: &Block) -> CompactBlock {
+        let mut short_ids = Vec::new();
+        let mut prefilled_txs = Vec::new();
+        for (index, tx) in block.txs.iter().enumerate() {
+            if tx.is_coinbase() {
+                prefilled_txs.push(PrefilledTx { index, tx: tx.clone() });
+            } else {
+                short_ids.push(short_tx_id(&block.hash, &tx.id));
+            }
+        }
+
+        CompactBlock {
+            header: block.header(),
+            short_ids,
+            prefilled_txs,
+        }
+    }
+
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled_txs.len()
+    }
+}
+
+/// A compact block being rebuilt from the mempool, waiting for the
+/// transactions that were not found there.
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txs: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// Places the prefilled transactions and those of `mempool` matching a
+    /// short id. Short ids matched by several mempool transactions are left
+    /// missing, the right one is asked to the peer. Compact blocks with more
+    /// transactions than a block of `max_block_size` can hold are rejected
+    /// before anything is allocated for them.
+    pub fn new(
+        compact: &CompactBlock,
+        mempool: &MemPool,
+        params: &ConsensusParams,
+    ) -> Result<PartialBlock, NetworkError> {
+        let hash = compact.header.hash();
+        let malformed = || NetworkError::MalformedCompactBlock { hash: hash.clone() };
+        if compact.tx_count() > params.max_block_size / MIN_TX_SIZE {
+            return Err(malformed());
+        }
+
+        let mut txs: Vec<Option<Transaction>> = vec![None; compact.tx_count()];
+        for prefilled in &compact.prefilled_txs {
+            match txs.get_mut(prefilled.index) {
+                Some(slot @ None) => *slot = Some(prefilled.tx.clone()),
+                _ => return Err(malformed()),
+            }
+        }
+
+        let mut candidates: HashMap<u64, Option<&Transaction>> = HashMap::new();
+        for entry in mempool.iter() {
+            candidates
+                .entry(short_tx_id(&hash, &entry.tx.id))
+                .and_modify(|candidate| *candidate = None)
+                .or_insert(Some(entry.tx));
+        }
+
+        let empty_slots = txs.iter_mut().filter(|slot| slot.is_none());
+        for (slot, short_id) in empty_slots.zip(&compact.short_ids) {
+            *slot = candidates.get(short_id).copied().flatten().cloned();
+        }
+
+        Ok(PartialBlock {
+            header: compact.header.clone(),
+            txs,
+        })
+    }
+
+    pub fn hash(&self) -> String {
+        self.header.hash()
+    }
+
+    /// Positions of the transactions not found in the mempool.
+    pub fn missing(&self) -> Vec<usize> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Builds the block with `missing_txs` filling the gaps, in order.
+    /// Returns `None` if they do not fill them exactly or if the rebuilt
+    /// block does not match the merkle root of the header, which a short id
+    /// collision in the mempool can cause. The full block has to be
+    /// downloaded then.
+    pub fn complete(self, missing_txs: Vec<Transaction>) -> Option<Block> {
+        if missing_txs.len() != self.txs.iter().filter(|tx| tx.is_none()).count() {
+            return None;
+        }
+
+        let mut missing_txs = missing_txs.into_iter();
+        let txs: Vec<Transaction> = self
+            .txs
+            .into_iter()
+            .map(|tx| tx.or_else(|| missing_txs.next()))
+            .collect::<Option<Vec<Transaction>>>()?;
+
+        if Block::calculate_merkle_root(&txs) != self.header.merkle_root {
+            return None;
+        }
+        Some(Block::from_header(self.header, txs))
+    }
+}
+
+#[cfg(test)]
+mod compact_test {
+    use crate::core::{
+        test_utils::{mine_on_top, test_params},
+        Block, ChainState, ConsensusParams, History, MemPool, NaiveReorgStrategy, Transaction, WalletKeyPair,
+        MIN_TX_SIZE,
+    };
+
+    use super::{CompactBlock, PartialBlock};
+
+    /// A chain where `sender` received a reward, and a block spending it
+    /// with `payments` transactions, not appended yet.
+    fn block_with_payments(params: &ConsensusParams, payments: u64) -> (History, Vec<Transaction>, Block) {
+        let sender = WalletKeyPair::new();
+        let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        let reward = Transaction::coinbase(sender.address(), params.block_reward(1), 1, params.chain_id);
+        hs.try_to_append(mine_on_top(&hs, vec![reward])).unwrap();
+
+        let payments: Vec<Transaction> = (0..payments)
+            .map(|sequence| {
+                let mut tx = Transaction::new(
+                    sender.address(),
+                    WalletKeyPair::new().address(),
+                    1,
+                    1,
+                    sequence,
+                    params.chain_id,
+                );
+                tx.sign(&sender.secret_key);
+                tx
+            })
+            .collect();
+        let coinbase = Transaction::coinbase(
+            WalletKeyPair::new().address(),
+            params.block_reward(2),
+            2,
+            params.chain_id,
+        );
+        let txs = [vec![coinbase], payments.clone()].concat();
+        let block = mine_on_top(&hs, txs);
+        (hs, payments, block)
+    }
+
+    fn mempool_with(params: &ConsensusParams, hs: &History, txs: &[Transaction]) -> MemPool {
+        let mut mempool = MemPool::new(1_000_000, params.clone());
+        let state: &ChainState = hs.get_state();
+        for tx in txs {
+            mempool.add_tx(tx.clone(), state).unwrap();
+        }
+        mempool
+    }
+
+    #[test]
+    fn block_is_rebuilt_from_the_mempool() {
+        let params = test_params();
+        let (hs, payments, block) = block_with_payments(&params, 3);
+        let mempool = mempool_with(&params, &hs, &payments);
+
+        let compact = CompactBlock::from_block(&block);
+        let partial = PartialBlock::new(&compact, &mempool, &params).unwrap();
+
+        assert_eq!(1, compact.prefilled_txs.len());
+        assert!(partial.missing().is_empty());
+        let rebuilt = partial.complete(Vec::new()).unwrap();
+        assert_eq!(block.hash, rebuilt.hash);
+        assert_eq!(block.merkle_root, Block::calculate_merkle_root(&rebuilt.txs));
+    }
+
+    #[test]
+    fn transactions_missing_from_the_mempool_fill_the_gaps() {
+        let params = test_params();
+        let (hs, payments, block) = block_with_payments(&params, 3);
+        let mempool = mempool_with(&params, &hs, &payments[..1]);
+
+        let partial = PartialBlock::new(&CompactBlock::from_block(&block), &mempool, &params).unwrap();
+
+        assert_eq!(vec![2, 3], partial.missing());
+        assert!(partial.clone().complete(vec![payments[2].clone()]).is_none());
+        assert!(partial
+            .clone()
+            .complete(vec![payments[2].clone(), payments[1].clone()])
+            .is_none());
+        let rebuilt = partial.complete(payments[1..].to_vec()).unwrap();
+        assert_eq!(block.hash, rebuilt.hash);
+    }
+
+    #[test]
+    fn prefilled_tx_out_of_the_block_is_rejected() {
+        let params = test_params();
+        let (_, _, block) = block_with_payments(&params, 1);
+        let mut compact = CompactBlock::from_block(&block);
+        compact.prefilled_txs[0].index = 5;
+
+        assert!(PartialBlock::new(&compact, &MemPool::new(1_000_000, params.clone()), &params).is_err());
+    }
+
+    #[test]
+    fn compact_block_with_more_txs_than_a_block_can_hold_is_rejected() {
+        let params = ConsensusParams {
+            max_block_size: 10_000,
+            ..test_params()
+        };
+        let (_, _, block) = block_with_payments(&params, 1);
+        let mut compact = CompactBlock::from_block(&block);
+        compact.short_ids = vec![0; 2_000_000];
+
+        assert!(PartialBlock::new(&compact, &MemPool::new(1_000_000, params.clone()), &params).is_err());
+        compact.short_ids.truncate(10_000 / MIN_TX_SIZE - 1);
+        assert!(PartialBlock::new(&compact, &MemPool::new(1_000_000, params.clone()), &params).is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+use std::{error::Error, fmt, net::IpAddr};
+
+use crate::core::BlockValidationError;
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    MessageTooLarge { size: usize, max: usize },
+    UnsupportedProtocolVersion { found: u32, min_supported: u32 },
+    WrongChain { expected: u32, found: u32 },
+    WrongGenesis { expected: String, found: String },
+    UnexpectedMessage { expected: String, found: String },
+    ConnectionClosed,
+    TooManyHeaders { count: usize, max: usize },
+    UnconnectedHeaders { previous_hash: String },
+    InvalidHeader { hash: String, source: BlockValidationError },
+    HeadersWithoutWork { height: u64, max_height: u64 },
+    Banned { ip: IpAddr },
+    TooManyConnections { max: usize },
+    Misbehaving { score: u32 },
+    TooManyAddresses { count: usize, max: usize },
+    UnsupportedAddressBookVersion { found: u64, supported: u32 },
+    SelfConnection,
+    MalformedCompactBlock { hash: String },
+    AuthenticationFailed,
+    DecryptionFailed,
+    UnexpectedPeerKey { expected: String, found: String },
+    EncryptionDisabled,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Io(err) => write!(f, "Network error: {}", err),
+            NetworkError::Parse(err) => write!(f, "Cannot parse message from peer: {}", err),
+            NetworkError::MessageTooLarge { size, max } => {
+                write!(f, "Message of {} bytes is larger than the maximum of {}", size, max)
+            }
+            NetworkError::UnsupportedProtocolVersion { found, min_supported } => write!(
+                f,
+                "Peer speaks protocol version {} but at least {} is required",
+                found, min_supported
+            ),
+            NetworkError::WrongChain { expected, found } => {
+                write!(f, "Peer is on chain {} instead of {}", found, expected)
+            }
+            NetworkError::WrongGenesis { expected, found } => {
+                write!(f, "Peer has genesis block {} instead of {}", found, expected)
+            }
+            NetworkError::UnexpectedMessage { expected, found } => {
+                write!(f, "Expected a {} message from peer but received {}", expected, found)
+            }
+            NetworkError::ConnectionClosed => write!(f, "Connection closed by peer"),
+            NetworkError::TooManyHeaders { count, max } => {
+                write!(f, "Peer sent {} headers but at most {} are allowed", count, max)
+            }
+            NetworkError::UnconnectedHeaders { previous_hash } => {
+                write!(f, "Peer sent headers building on unknown block {}", previous_hash)
+            }
+            NetworkError::InvalidHeader { hash, source } => write!(f, "Peer sent invalid header {}: {}", hash, source),
+            NetworkError::HeadersWithoutWork { height, max_height } => write!(
+                f,
+                "Peer sent headers up to height {} without more work than our chain, past height {}",
+                height, max_height
+            ),
+            NetworkError::Banned { ip } => write!(f, "Peer {} is banned", ip),
+            NetworkError::TooManyConnections { max } => {
+                write!(f, "Already connected to the maximum of {} peers", max)
+            }
+            NetworkError::Misbehaving { score } => {
+                write!(f, "Peer banned for misbehaving with a score of {}", score)
+            }
+            NetworkError::TooManyAddresses { count, max } => {
+                write!(f, "Peer sent {} addresses but at most {} are allowed", count, max)
+            }
+            NetworkError::UnsupportedAddressBookVersion { found, supported } => write!(
+                f,
+                "Address book has version {} but only version {} is supported",
+                found, supported
+            ),
+            NetworkError::SelfConnection => write!(f, "Connected to ourselves"),
+            NetworkError::MalformedCompactBlock { hash } => write!(f, "Peer sent malformed compact block {}", hash),
+            NetworkError::AuthenticationFailed => write!(f, "Peer failed to prove its node key"),
+            NetworkError::DecryptionFailed => write!(f, "Cannot decrypt message from peer"),
+            NetworkError::UnexpectedPeerKey { expected, found } => {
+                write!(f, "Peer has node key {} instead of {}", found, expected)
+            }
+            NetworkError::EncryptionDisabled => {
+                write!(f, "Peer keys can only be checked with a node identity configured")
+            }
+        }
+    }
+}
+
+impl Error for NetworkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetworkError::Io(err) => Some(err),
+            NetworkError::Parse(err) => Some(err),
+            NetworkError::InvalidHeader { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NetworkError {
+    fn from(err: std::io::Error) -> NetworkError {
+        NetworkError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for NetworkError {
+    fn from(err: serde_json::Error) -> NetworkError {
+        NetworkError::Parse(err)
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::{ErrorKind, Read, Write};
+
+use super::{transport::TAG_SIZE, Message, NetworkError, SessionCipher};
+
+/// Writes `message` as a 4 bytes big endian length followed by its JSON
+/// encoding.
+pub fn write_message<W: Write>(writer: &mut W, message: &Message, max_size: usize) -> Result<(), NetworkError> {
+    write_frame(writer, &encode(message, max_size)?, None)
+}
+
+/// Same as `write_message`, encrypting the JSON with `cipher`. The length
+/// stays in clear but is authenticated.
+pub fn write_encrypted_message<W: Write>(
+    writer: &mut W,
+    message: &Message,
+    max_size: usize,
+    cipher: &mut SessionCipher,
+) -> Result<(), NetworkError> {
+    write_frame(writer, &encode(message, max_size)?, Some(cipher))
+}
+
+/// Reads a message written by `write_message`. The length is checked before
+/// reading the payload, so a peer cannot make us allocate more than `max_size`.
+pub fn read_message<R: Read>(reader: &mut R, max_size: usize) -> Result<Message, NetworkError> {
+    read_message_with_size(reader, max_size).map(|(message, _)| message)
+}
+
+/// Same as `read_message`, also returning the size of the payload.
+pub fn read_message_with_size<R: Read>(reader: &mut R, max_size: usize) -> Result<(Message, usize), NetworkError> {
+    let payload = read_frame(reader, max_size, None)?;
+    Ok((serde_json::from_slice(&payload)?, payload.len()))
+}
+
+/// Reads a message written by `write_encrypted_message`, returning the
+/// size of the decrypted payload with it.
+pub fn read_encrypted_message_with_size<R: Read>(
+    reader: &mut R,
+    max_size: usize,
+    cipher: &mut SessionCipher,
+) -> Result<(Message, usize), NetworkError> {
+    let payload = read_frame(reader, max_size, Some(cipher))?;
+    Ok((serde_json::from_slice(&payload)?, payload.len()))
+}
+
+fn encode(message: &Message, max_size: usize) -> Result<Vec<u8>, NetworkError> {
+    let payload = serde_json::to_vec(message)?;
+    if payload.len() > max_size {
+        return Err(NetworkError::MessageTooLarge {
+            size: payload.len(),
+            max: max_size,
+        });
+    }
+    Ok(payload)
+}
+
+/// Writes `payload` prefixed by its length, encrypted by `cipher` if any.
+pub(super) fn write_frame<W: Write>(
+    writer: &mut W,
+    payload: &[u8],
+    cipher: Option<&mut SessionCipher>,
+) -> Result<(), NetworkError> {
+    let overhead = if cipher.is_some() { TAG_SIZE } else { 0 };
+    let length = ((payload.len() + overhead) as u32).to_be_bytes();
+
+    let mut frame = Vec::with_capacity(payload.len() + overhead + 4);
+    frame.extend_from_slice(&length);
+    match cipher {
+        Some(cipher) => frame.extend_from_slice(&cipher.encrypt(&length, payload)),
+        None => frame.extend_from_slice(payload),
+    }
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a frame written by `write_frame` with a payload of at most
+/// `max_size` bytes, checked before reading it.
+pub(super) fn read_frame<R: Read>(
+    reader: &mut R,
+    max_size: usize,
+    cipher: Option<&mut SessionCipher>,
+) -> Result<Vec<u8>, NetworkError> {
+    let mut length = [0u8; 4];
+    if let Err(err) = reader.read_exact(&mut length) {
+        return Err(match err.kind() {
+            ErrorKind::UnexpectedEof => NetworkError::ConnectionClosed,
+            _ => NetworkError::Io(err),
+        });
+    }
+
+    let overhead = if cipher.is_some() { TAG_SIZE } else { 0 };
+    let size = u32::from_be_bytes(length) as usize;
+    if size > max_size + overhead {
+        return Err(NetworkError::MessageTooLarge {
+            size: size.saturating_sub(overhead),
+            max: max_size,
+        });
+    }
+
+    let mut payload = vec![0u8; size];
+    reader.read_exact(&mut payload)?;
+    match cipher {
+        Some(cipher) => cipher.decrypt(&length, &payload),
+        None => Ok(payload),
+    }
+}
+
+#[cfg(test)]
+mod framing_test {
+    use std::io::Cursor;
+
+    use crate::network::{InventoryItem, Message, NetworkError};
+
+    use super::{read_message, write_message};
+
+    #[test]
+    fn written_messages_are_read_back_in_order() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Message::Ping(7), 1024).unwrap();
+        write_message(
+            &mut buffer,
+            &Message::Inv(vec![InventoryItem::Tx("id".to_string())]),
+            1024,
+        )
+        .unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert!(matches!(read_message(&mut reader, 1024), Ok(Message::Ping(7))));
+        assert!(matches!(
+            read_message(&mut reader, 1024),
+            Ok(Message::Inv(items)) if items == vec![InventoryItem::Tx("id".to_string())]
+        ));
+        assert!(matches!(
+            read_message(&mut reader, 1024),
+            Err(NetworkError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected_before_reading_the_payload() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Message::Ping(7), 1024).unwrap();
+
+        let res = read_message(&mut Cursor::new(buffer), 4);
+
+        assert!(matches!(res, Err(NetworkError::MessageTooLarge { max: 4, .. })));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::InventoryItem;
+
+/// Inventory a peer is known to have, because it announced it to us or
+/// we sent it. Past `capacity` items the oldest ones are forgotten, at
+/// worst leading to an announcement the peer will ignore.
+pub struct KnownInventory {
+    capacity: usize,
+    items: HashSet<InventoryItem>,
+    order: VecDeque<InventoryItem>,
+}
+
+impl KnownInventory {
+    pub fn new(capacity: usize) -> KnownInventory {
+        KnownInventory {
+            capacity,
+            items: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records `item`, returns `false` if it was already known. Nothing is
+    /// recorded without capacity, so every item is new.
+    pub fn insert(&mut self, item: InventoryItem) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.items.contains(&item) {
+            return false;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        self.items.insert(item.clone());
+        self.order.push_back(item);
+        true
+    }
+
+    pub fn contains(&self, item: &InventoryItem) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod inventory_test {
+    use crate::network::InventoryItem;
+
+    use super::KnownInventory;
+
+    #[test]
+    fn oldest_items_are_forgotten_past_capacity() {
+        let mut known = KnownInventory::new(2);
+
+        assert!(known.insert(InventoryItem::Tx("first".to_string())));
+        assert!(known.insert(InventoryItem::Block("second".to_string())));
+        assert!(!known.insert(InventoryItem::Tx("first".to_string())));
+        assert!(known.insert(InventoryItem::Tx("third".to_string())));
+
+        assert_eq!(2, known.len());
+        assert!(!known.contains(&InventoryItem::Tx("first".to_string())));
+        assert!(known.contains(&InventoryItem::Block("second".to_string())));
+        assert!(known.contains(&InventoryItem::Tx("third".to_string())));
+    }
+
+    #[test]
+    fn nothing_is_remembered_without_capacity() {
+        let mut known = KnownInventory::new(0);
+
+        assert!(known.insert(InventoryItem::Tx("first".to_string())));
+        assert!(known.insert(InventoryItem::Tx("first".to_string())));
+
+        assert!(known.is_empty());
+        assert!(!known.contains(&InventoryItem::Tx("first".to_string())));
+    }
+}
@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Block, BlockHeader, Transaction};
+
+use super::CompactBlock;
+
+/// Version of the peer-to-peer protocol spoken by this node.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version this node can talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// First protocol version relaying new blocks as compact blocks.
+pub const COMPACT_BLOCKS_VERSION: u32 = 2;
+/// Most addresses sent in one `Addr` message.
+pub const MAX_ADDR_PER_MESSAGE: usize = 1000;
+
+/// First message sent on a connection, describing the chain of the sender.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionMessage {
+    pub protocol_version: u32,
+    pub chain_id: u32,
+    pub genesis_hash: String,
+    pub best_height: u64,
+    /// Port the sender accepts connections on, 0 if it does not.
+    #[serde(default)]
+    pub listen_port: u16,
+    /// Random number picked by the sender at start, to detect connections
+    /// to itself.
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+/// Address of a node accepting connections, and when it was last heard of
+/// in seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAddress {
+    pub addr: SocketAddr,
+    pub last_seen: i64,
+}
+
+/// Reference to an object a peer has or wants, by block hash or transaction id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InventoryItem {
+    Block(String),
+    Tx(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Version(VersionMessage),
+    VerAck,
+    /// Announces objects the sender has.
+    Inv(Vec<InventoryItem>),
+    /// Requests the full objects, answered with `Block` and `Tx` messages
+    /// or with `NotFound`.
+    GetData(Vec<InventoryItem>),
+    NotFound(Vec<InventoryItem>),
+    /// Requests the headers following the first hash of the locator that
+    /// is part of the chain of the receiver, answered with `Headers`.
+    GetHeaders(Vec<String>),
+    Headers(Vec<BlockHeader>),
+    Block(Block),
+    Tx(Transaction),
+    Ping(u64),
+    Pong(u64),
+    /// Requests addresses of other nodes, answered with `Addr`.
+    GetAddr,
+    Addr(Vec<PeerAddress>),
+    /// Announces a new block to peers speaking `COMPACT_BLOCKS_VERSION`,
+    /// instead of `Inv`.
+    CompactBlock(CompactBlock),
+    /// Requests the transactions of a block at the given positions,
+    /// answered with `BlockTxn` or `NotFound`.
+    GetBlockTxn {
+        block_hash: String,
+        indexes: Vec<usize>,
+    },
+    BlockTxn {
+        block_hash: String,
+        txs: Vec<Transaction>,
+    },
+}
+
+impl Message {
+    /// Name of the message, used in logs and errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::Version(_) => "version",
+            Message::VerAck => "verack",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::NotFound(_) => "notfound",
+            Message::GetHeaders(_) => "getheaders",
+            Message::Headers(_) => "headers",
+            Message::Block(_) => "block",
+            Message::Tx(_) => "tx",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::GetAddr => "getaddr",
+            Message::Addr(_) => "addr",
+            Message::CompactBlock(_) => "cmpctblock",
+            Message::GetBlockTxn { .. } => "getblocktxn",
+            Message::BlockTxn { .. } => "blocktxn",
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::core::{AppendToHistoryError, BlockValidationError, MemPoolError, TransactionValidationError};
+
+use super::NetworkError;
+
+/// Misbehavior score at which a peer is disconnected and banned.
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Score of a peer sending messages faster than its rate limit allows.
+pub const RATE_LIMIT_MISBEHAVIOR: u32 = 10;
+
+/// Score of a peer sending a block that failed validation. Blocks that
+/// cannot be valid whatever our chain get the peer banned right away,
+/// while failures that an honest peer may cause, like a block building on
+/// one we do not have yet, are not punished.
+pub fn block_misbehavior(err: &AppendToHistoryError) -> u32 {
+    let err = match err {
+        AppendToHistoryError::InvalidBlock(err) => err,
+        AppendToHistoryError::EmptyHistory | AppendToHistoryError::UnknownGenesis { .. } => return 0,
+    };
+
+    match err {
+        BlockValidationError::BadParent { .. } => 0,
+        BlockValidationError::TimestampTooFarInFuture { .. } => 0,
+        BlockValidationError::TimestampTooOld { .. } => 20,
+        BlockValidationError::BadHeight { .. }
+        | BlockValidationError::BadDifficulty { .. }
+        | BlockValidationError::InsufficientWork { .. }
+        | BlockValidationError::MalformedHash(_)
+        | BlockValidationError::HashMismatch { .. }
+        | BlockValidationError::BadMerkleRoot { .. }
+        | BlockValidationError::MisplacedCoinbase { .. }
+        | BlockValidationError::TooLarge { .. }
+        | BlockValidationError::InvalidTransaction { .. }
+        | BlockValidationError::BadStateTransition { .. } => BAN_THRESHOLD,
+    }
+}
+
+/// Score of a peer relaying a transaction the mempool rejected. Only
+/// malformed transactions count: one conflicting with our view of the
+/// chain or paying too little may come from an honest peer.
+pub fn tx_misbehavior(err: &MemPoolError) -> u32 {
+    match err {
+        MemPoolError::InvalidTransaction(err) => match err {
+            TransactionValidationError::NotYetValid { .. } | TransactionValidationError::Expired { .. } => 0,
+            TransactionValidationError::IdMismatch { .. }
+            | TransactionValidationError::WrongChain { .. }
+            | TransactionValidationError::MissingSignature { .. }
+            | TransactionValidationError::MalformedSender(_)
+            | TransactionValidationError::BadSignature(_)
+            | TransactionValidationError::TooLarge { .. } => 10,
+        },
+        MemPoolError::Coinbase { .. } => 10,
+        MemPoolError::InsufficientReplacementFee { .. }
+        | MemPoolError::InvalidForState(_)
+        | MemPoolError::FeeTooLow { .. }
+        | MemPoolError::TooManyFromSender { .. }
+        | MemPoolError::MemPoolFull { .. } => 0,
+    }
+}
+
+/// Score of a peer whose connection ended with `err`. Protocol violations
+/// are punished, connection failures are not.
+pub fn network_misbehavior(err: &NetworkError) -> u32 {
+    match err {
+        NetworkError::Parse(_)
+        | NetworkError::MessageTooLarge { .. }
+        | NetworkError::UnexpectedMessage { .. }
+        | NetworkError::TooManyHeaders { .. }
+        | NetworkError::TooManyAddresses { .. }
+        | NetworkError::MalformedCompactBlock { .. }
+        | NetworkError::DecryptionFailed
+        | NetworkError::InvalidHeader { .. } => BAN_THRESHOLD,
+        NetworkError::HeadersWithoutWork { .. } => 50,
+        NetworkError::UnconnectedHeaders { .. } => 20,
+        _ => 0,
+    }
+}
+
+/// Addresses of banned peers and when their ban ends.
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: HashMap<IpAddr, Instant>,
+}
+
+impl BanList {
+    pub fn new() -> BanList {
+        BanList { bans: HashMap::new() }
+    }
+
+    pub fn ban(&mut self, ip: IpAddr, duration: Duration, now: Instant) {
+        let until = now + duration;
+        let ban = self.bans.entry(ip).or_insert(until);
+        *ban = (*ban).max(until);
+    }
+
+    pub fn unban(&mut self, ip: &IpAddr) -> bool {
+        self.bans.remove(ip).is_some()
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr, now: Instant) -> bool {
+        self.bans.get(ip).is_some_and(|until| *until > now)
+    }
+
+    /// Forgets the bans that ended.
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.bans.retain(|_, until| *until > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod misbehavior_test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use crate::core::{AppendToHistoryError, BlockValidationError, MemPoolError, StateTransitionError};
+
+    use super::{block_misbehavior, tx_misbehavior, BanList, BAN_THRESHOLD};
+
+    #[test]
+    fn only_blocks_invalid_whatever_our_chain_get_the_peer_banned() {
+        let bad_merkle_root = AppendToHistoryError::InvalidBlock(BlockValidationError::BadMerkleRoot {
+            declared: "declared".to_string(),
+            computed: "computed".to_string(),
+        });
+        let unknown_parent = AppendToHistoryError::InvalidBlock(BlockValidationError::BadParent {
+            expected: "expected".to_string(),
+            found: "found".to_string(),
+        });
+
+        assert_eq!(BAN_THRESHOLD, block_misbehavior(&bad_merkle_root));
+        assert_eq!(0, block_misbehavior(&unknown_parent));
+    }
+
+    #[test]
+    fn tx_conflicting_with_our_state_is_not_punished() {
+        let err = MemPoolError::InvalidForState(StateTransitionError::BadSequence {
+            address: "address".to_string(),
+            expected: 1,
+            found: 2,
+        });
+
+        assert_eq!(0, tx_misbehavior(&err));
+    }
+
+    #[test]
+    fn ban_ends_after_its_duration() {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let now = Instant::now();
+        let mut bans = BanList::new();
+
+        bans.ban(ip, Duration::from_secs(60), now);
+
+        assert!(bans.is_banned(&ip, now + Duration::from_secs(59)));
+        assert!(!bans.is_banned(&ip, now + Duration::from_secs(61)));
+        bans.remove_expired(now + Duration::from_secs(61));
+        assert!(bans.is_empty());
+    }
+}
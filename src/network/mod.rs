@@ -0,0 +1,50 @@
+mod address_book;
+mod compact;
+mod errors;
+mod framing;
+mod inventory;
+mod message;
+mod misbehavior;
+mod p2p;
+mod peer;
+mod rate_limit;
+mod simulator;
+mod sync;
+mod transport;
+
+pub type NetworkError = errors::NetworkError;
+pub type Message = message::Message;
+pub type VersionMessage = message::VersionMessage;
+pub type InventoryItem = message::InventoryItem;
+pub type PeerAddress = message::PeerAddress;
+pub type CompactBlock = compact::CompactBlock;
+pub type PrefilledTx = compact::PrefilledTx;
+pub type PartialBlock = compact::PartialBlock;
+pub type KnownInventory = inventory::KnownInventory;
+pub type AddressBook = address_book::AddressBook;
+pub type KnownAddress = address_book::KnownAddress;
+pub type BanList = misbehavior::BanList;
+pub type RateLimiter = rate_limit::RateLimiter;
+pub type PeerConnection = peer::PeerConnection;
+pub type P2pNode = p2p::P2pNode;
+pub type P2pConfig = p2p::P2pConfig;
+pub type ChainSync = sync::ChainSync;
+pub type SyncConfig = sync::SyncConfig;
+pub type SyncStep = sync::SyncStep;
+pub type SyncStatus = sync::SyncStatus;
+pub type Simulator = simulator::Simulator;
+pub type SimulatorConfig = simulator::SimulatorConfig;
+pub type SimulationReport = simulator::SimulationReport;
+pub type NodeIdentity = transport::NodeIdentity;
+pub type SessionCipher = transport::SessionCipher;
+pub type Session = transport::Session;
+
+pub use address_book::{net_group, ADDRESS_BOOK_FILE_VERSION};
+pub use compact::short_tx_id;
+pub use framing::{
+    read_encrypted_message_with_size, read_message, read_message_with_size, write_encrypted_message, write_message,
+};
+pub use message::{COMPACT_BLOCKS_VERSION, MAX_ADDR_PER_MESSAGE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use misbehavior::{block_misbehavior, network_misbehavior, tx_misbehavior, BAN_THRESHOLD, RATE_LIMIT_MISBEHAVIOR};
+pub use sync::{block_locator, headers_after};
+pub use transport::{handshake as transport_handshake, TAG_SIZE};
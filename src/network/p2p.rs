@@ -0,0 +1,1118 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use rand::seq::SliceRandom;
+use secp256k1::PublicKey;
+
+use crate::core::{
+    AppendToHistoryError, Block, BlockHeader, BlockValidationError, ChainSpec, History, MemPoolError, Node,
+    Transaction,
+};
+
+use super::{
+    block_locator, block_misbehavior, headers_after, net_group, network_misbehavior, tx_misbehavior, AddressBook,
+    BanList, ChainSync, CompactBlock, InventoryItem, KnownAddress, KnownInventory, Message, NetworkError, NodeIdentity,
+    PartialBlock, PeerAddress, PeerConnection, RateLimiter, SyncConfig, SyncStatus, SyncStep, VersionMessage,
+    BAN_THRESHOLD, COMPACT_BLOCKS_VERSION, MAX_ADDR_PER_MESSAGE, PROTOCOL_VERSION, RATE_LIMIT_MISBEHAVIOR,
+};
+
+/// Number of peers new addresses are relayed to.
+const ADDR_RELAY_FANOUT: usize = 2;
+/// `Addr` messages with more addresses are answers to `GetAddr`, which
+/// are not relayed.
+const MAX_RELAYED_ADDRESSES: usize = 10;
+/// Compact blocks waiting for missing transactions, per peer. Past that
+/// the full block is downloaded instead.
+const MAX_PARTIAL_BLOCKS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct P2pConfig {
+    /// Largest message accepted from or sent to a peer, in bytes.
+    pub max_message_size: usize,
+    /// How long a peer has to complete the handshake, and then to take
+    /// every message sent to it.
+    pub handshake_timeout: Duration,
+    /// How often stalled peers are looked for.
+    pub tick_interval: Duration,
+    /// Number of announced or sent items remembered per peer, so that they
+    /// are not announced to it again.
+    pub known_inventory_capacity: usize,
+    /// How long to wait for an announced item requested from a peer before
+    /// requesting it from another peer announcing it.
+    pub inventory_request_timeout: Duration,
+    /// Most connections opened by peers.
+    pub max_inbound: usize,
+    /// Most connections opened to peers.
+    pub max_outbound: usize,
+    /// Messages and bytes a peer may send per second before being
+    /// considered as flooding.
+    pub max_messages_per_sec: u32,
+    pub max_bytes_per_sec: usize,
+    /// How long a misbehaving peer stays banned.
+    pub ban_duration: Duration,
+    /// Outbound connections opened to addresses of the address book, up to
+    /// `max_outbound`.
+    pub target_outbound: usize,
+    /// How long to wait before connecting again to an address that was tried.
+    pub connect_retry_interval: Duration,
+    /// Most addresses remembered in the address book.
+    pub address_book_capacity: usize,
+    /// Nodes, as `host:port`, added to the address book when the node
+    /// starts.
+    pub seed_nodes: Vec<String>,
+    /// File the address book is loaded from at start and saved to at
+    /// shutdown.
+    pub address_book_path: Option<PathBuf>,
+    /// Key pair of the node. When set, connections are encrypted and both
+    /// sides prove their node key, peers without one cannot connect.
+    pub node_identity: Option<NodeIdentity>,
+    pub sync: SyncConfig,
+}
+
+impl Default for P2pConfig {
+    fn default() -> Self {
+        P2pConfig {
+            max_message_size: 4 * 1024 * 1024,
+            handshake_timeout: Duration::from_secs(10),
+            tick_interval: Duration::from_millis(200),
+            known_inventory_capacity: 50_000,
+            inventory_request_timeout: Duration::from_secs(5),
+            max_inbound: 32,
+            max_outbound: 8,
+            max_messages_per_sec: 500,
+            max_bytes_per_sec: 8 * 1024 * 1024,
+            ban_duration: Duration::from_secs(24 * 60 * 60),
+            target_outbound: 8,
+            connect_retry_interval: Duration::from_secs(60),
+            address_book_capacity: 10_000,
+            seed_nodes: Vec::new(),
+            address_book_path: None,
+            node_identity: None,
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+impl P2pConfig {
+    /// Default config of a node of the chain described by `spec`, starting
+    /// from its seed nodes.
+    pub fn for_chain(spec: &ChainSpec) -> P2pConfig {
+        P2pConfig {
+            seed_nodes: spec.seed_nodes.clone(),
+            ..P2pConfig::default()
+        }
+    }
+}
+
+/// A connected peer, the inventory it is known to have and how well it
+/// behaves.
+struct Peer {
+    connection: PeerConnection,
+    inbound: bool,
+    /// Address the peer accepts connections on, if known.
+    listen_addr: Option<SocketAddr>,
+    known_inventory: Mutex<KnownInventory>,
+    misbehavior: AtomicU32,
+    rate_limiter: Mutex<RateLimiter>,
+    /// Compact blocks from the peer waiting for their missing transactions.
+    partial_blocks: Mutex<HashMap<String, PartialBlock>>,
+}
+
+impl Peer {
+    fn supports_compact_blocks(&self) -> bool {
+        self.connection.remote_version().protocol_version >= COMPACT_BLOCKS_VERSION
+    }
+
+    fn mark_known(&self, item: InventoryItem) {
+        self.known_inventory.lock().unwrap().insert(item);
+    }
+
+    /// Keeps the items the peer does not know yet, which it will know
+    /// once they are sent.
+    fn filter_unknown(&self, items: &[InventoryItem]) -> Vec<InventoryItem> {
+        let mut known_inventory = self.known_inventory.lock().unwrap();
+        items
+            .iter()
+            .filter(|item| known_inventory.insert((*item).clone()))
+            .cloned()
+            .collect()
+    }
+}
+
+struct Shared {
+    node: Mutex<Node>,
+    sync: Mutex<ChainSync>,
+    peers: Mutex<HashMap<u64, Arc<Peer>>>,
+    /// Announced items requested from a peer and not received yet.
+    requested: Mutex<HashMap<InventoryItem, Instant>>,
+    bans: Mutex<BanList>,
+    addresses: Mutex<AddressBook>,
+    /// Addresses the connection manager is connecting to.
+    connecting: Mutex<HashSet<SocketAddr>>,
+    /// Inbound connections still running the handshake.
+    pending_inbound: AtomicUsize,
+    next_peer_id: AtomicU64,
+    running: AtomicBool,
+    local_addr: SocketAddr,
+    nonce: u64,
+    config: P2pConfig,
+}
+
+/// A `Node` reachable over TCP. Every peer connection is served by its own
+/// thread, announcing new blocks and transactions with `Inv` messages and
+/// answering the `GetData` requests of the peers. Items are only relayed
+/// once validated, and never announced to a peer that already has them.
+/// New blocks are sent as `CompactBlock`s to the peers supporting them,
+/// which rebuild them from their mempool and only ask for the transactions
+/// they miss. Peers with a better chain are synchronized from headers
+/// first, see `ChainSync`. Peers sending invalid data or flooding the node
+/// are banned.
+///
+/// Addresses of other nodes are learnt from the configured seed nodes and
+/// gossiped between peers. They are kept in an `AddressBook`, from which
+/// outbound peers in different network groups are picked until
+/// `target_outbound` are connected.
+///
+/// With a `node_identity` configured, connections are encrypted and every
+/// peer is known by its node key, which `connect_pinned` checks.
+pub struct P2pNode {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+}
+
+impl P2pNode {
+    /// Starts listening on `listen_addr`. Use port 0 to let the system pick
+    /// a free one, see `local_addr`. The address book is loaded from
+    /// `config.address_book_path` if the file exists.
+    pub fn start<A: ToSocketAddrs>(node: Node, listen_addr: A, config: P2pConfig) -> Result<P2pNode, NetworkError> {
+        let listener = TcpListener::bind(listen_addr)?;
+        let local_addr = listener.local_addr()?;
+
+        let mut addresses = match &config.address_book_path {
+            Some(path) if path.exists() => AddressBook::load(path, config.address_book_capacity)?,
+            _ => AddressBook::new(config.address_book_capacity),
+        };
+        let now = Utc::now().timestamp();
+        for seed in &config.seed_nodes {
+            // Seeds that cannot be resolved are skipped, the others may be enough
+            for addr in seed.to_socket_addrs().into_iter().flatten() {
+                addresses.add(PeerAddress { addr, last_seen: now }, now);
+            }
+        }
+
+        let shared = Arc::new(Shared {
+            node: Mutex::new(node),
+            sync: Mutex::new(ChainSync::new(config.sync.clone())),
+            peers: Mutex::new(HashMap::new()),
+            requested: Mutex::new(HashMap::new()),
+            bans: Mutex::new(BanList::new()),
+            addresses: Mutex::new(addresses),
+            connecting: Mutex::new(HashSet::new()),
+            pending_inbound: AtomicUsize::new(0),
+            next_peer_id: AtomicU64::new(0),
+            running: AtomicBool::new(true),
+            local_addr,
+            nonce: rand::random(),
+            config,
+        });
+
+        let accepting = Arc::clone(&shared);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !accepting.running.load(Ordering::SeqCst) {
+                    break;
+                }
+                // Refused connections are dropped before the handshake
+                let stream = match stream {
+                    Ok(stream) if accepting.accepts_inbound(&stream) => stream,
+                    _ => continue,
+                };
+                accepting.pending_inbound.fetch_add(1, Ordering::SeqCst);
+                let shared = Arc::clone(&accepting);
+                thread::spawn(move || {
+                    let _ = shared.add_peer(stream, true, None);
+                    shared.pending_inbound.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        let ticking = Arc::clone(&shared);
+        thread::spawn(move || {
+            while ticking.running.load(Ordering::SeqCst) {
+                thread::sleep(ticking.config.tick_interval);
+                ticking.on_tick();
+            }
+        });
+
+        Ok(P2pNode { shared, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Opens a connection to the peer at `addr` and runs the handshake.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr, NetworkError> {
+        self.shared.check_outbound_slot()?;
+
+        let stream = TcpStream::connect(addr)?;
+        let ip = stream.peer_addr()?.ip();
+        if self.is_banned(&ip) {
+            return Err(NetworkError::Banned { ip });
+        }
+        self.shared.add_peer(stream, false, None)
+    }
+
+    /// Same as `connect`, refusing the peer unless it proves it owns `key`.
+    /// Needs a `node_identity` to be configured.
+    pub fn connect_pinned<A: ToSocketAddrs>(&self, addr: A, key: &PublicKey) -> Result<SocketAddr, NetworkError> {
+        if self.shared.config.node_identity.is_none() {
+            return Err(NetworkError::EncryptionDisabled);
+        }
+        self.shared.check_outbound_slot()?;
+
+        let stream = TcpStream::connect(addr)?;
+        let ip = stream.peer_addr()?.ip();
+        if self.is_banned(&ip) {
+            return Err(NetworkError::Banned { ip });
+        }
+        self.shared.add_peer(stream, false, Some(key))
+    }
+
+    /// Public key of the configured node identity, which peers can pin.
+    pub fn node_key(&self) -> Option<PublicKey> {
+        self.shared.config.node_identity.as_ref().map(NodeIdentity::public_key)
+    }
+
+    /// Bans `ip` for the configured duration, disconnecting its peers.
+    pub fn ban(&self, ip: IpAddr) {
+        self.shared
+            .bans
+            .lock()
+            .unwrap()
+            .ban(ip, self.shared.config.ban_duration, Instant::now());
+
+        for peer in self.shared.peers.lock().unwrap().values() {
+            if peer.connection.remote_addr().ip() == ip {
+                peer.connection.disconnect();
+            }
+        }
+    }
+
+    pub fn unban(&self, ip: &IpAddr) -> bool {
+        self.shared.bans.lock().unwrap().unban(ip)
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.shared.bans.lock().unwrap().is_banned(ip, Instant::now())
+    }
+
+    /// Adds `addr` to the address book, to be connected to when more
+    /// outbound peers are needed.
+    pub fn add_address(&self, addr: SocketAddr) -> bool {
+        let now = Utc::now().timestamp();
+        self.shared
+            .addresses
+            .lock()
+            .unwrap()
+            .add(PeerAddress { addr, last_seen: now }, now)
+    }
+
+    pub fn known_addresses(&self) -> Vec<KnownAddress> {
+        self.shared.addresses.lock().unwrap().addresses().cloned().collect()
+    }
+
+    pub fn save_address_book<P: AsRef<Path>>(&self, path: P) -> Result<(), NetworkError> {
+        self.shared.addresses.lock().unwrap().save(path)
+    }
+
+    /// Adds `tx` to the local mempool and announces it to every peer.
+    pub fn submit_tx(&self, tx: Transaction) -> Result<(), MemPoolError> {
+        let id = tx.id.clone();
+        self.shared.node.lock().unwrap().submit_tx(tx)?;
+        self.shared.announce(&[InventoryItem::Tx(id)]);
+        Ok(())
+    }
+
+    /// Appends `block` to the local chain and announces it to every peer.
+    pub fn submit_block(&self, block: Block) -> Result<bool, AppendToHistoryError> {
+        let hash = block.hash.clone();
+        let appended = self.shared.node.lock().unwrap().submit_block(block)?;
+        self.shared.announce(&[InventoryItem::Block(hash)]);
+        Ok(appended)
+    }
+
+    /// Runs `f` with the local node locked.
+    pub fn with_node<R, F: FnOnce(&mut Node) -> R>(&self, f: F) -> R {
+        f(&mut self.shared.node.lock().unwrap())
+    }
+
+    pub fn sync_status(&self) -> SyncStatus {
+        let node = self.shared.node.lock().unwrap();
+        self.shared.sync.lock().unwrap().status(node.get_history())
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.shared.peers.lock().unwrap().len()
+    }
+
+    /// Address and version of every connected peer.
+    pub fn peers(&self) -> Vec<(SocketAddr, VersionMessage)> {
+        self.shared
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|peer| (peer.connection.remote_addr(), peer.connection.remote_version().clone()))
+            .collect()
+    }
+
+    /// Address and node key of every connected peer, empty when connections
+    /// are not encrypted.
+    pub fn peer_keys(&self) -> Vec<(SocketAddr, PublicKey)> {
+        self.shared
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|peer| Some((peer.connection.remote_addr(), *peer.connection.remote_key()?)))
+            .collect()
+    }
+
+    /// Disconnects every peer and stops accepting new ones. The address
+    /// book is saved to `config.address_book_path` if set.
+    pub fn shutdown(&self) {
+        if !self.shared.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(path) = &self.shared.config.address_book_path {
+            // Nothing more can be done on failure, this may run from `drop`
+            let _ = self.save_address_book(path);
+        }
+
+        for peer in self.shared.peers.lock().unwrap().values() {
+            peer.connection.disconnect();
+        }
+        // Wakes the accepting thread up so that it sees the node stopped
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+impl Drop for P2pNode {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Shared {
+    fn local_version(&self) -> VersionMessage {
+        let node = self.node.lock().unwrap();
+        let history = node.get_history();
+        VersionMessage {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: history.get_params().chain_id,
+            genesis_hash: history
+                .get_chain()
+                .first()
+                .map(|genesis| genesis.hash.clone())
+                .unwrap_or_default(),
+            best_height: history.get_last_block().map_or(0, |tip| tip.height),
+            listen_port: self.local_addr.port(),
+            nonce: self.nonce,
+        }
+    }
+
+    /// Whether a connection from the address of `stream` can be accepted.
+    /// Connections still running the handshake take a slot, so that a
+    /// flood of connections cannot start more threads than the cap.
+    fn accepts_inbound(&self, stream: &TcpStream) -> bool {
+        let banned = stream.peer_addr().map_or(true, |addr| {
+            self.bans.lock().unwrap().is_banned(&addr.ip(), Instant::now())
+        });
+        let pending = self.pending_inbound.load(Ordering::SeqCst);
+        !banned && self.count_peers(true) + pending < self.config.max_inbound
+    }
+
+    fn check_outbound_slot(&self) -> Result<(), NetworkError> {
+        if self.count_peers(false) >= self.config.max_outbound {
+            return Err(NetworkError::TooManyConnections {
+                max: self.config.max_outbound,
+            });
+        }
+        Ok(())
+    }
+
+    fn count_peers(&self, inbound: bool) -> usize {
+        self.peers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|peer| peer.inbound == inbound)
+            .count()
+    }
+
+    /// Runs the handshake on `stream` and serves the peer on a new thread.
+    /// Headers are asked to peers claiming a longer chain, and addresses to
+    /// outbound peers. The listening address of inbound peers is relayed
+    /// to the other peers if it is new. With a node identity configured the
+    /// connection is encrypted, and the peer must own `expected_key` if set.
+    fn add_peer(
+        self: &Arc<Self>,
+        stream: TcpStream,
+        inbound: bool,
+        expected_key: Option<&PublicKey>,
+    ) -> Result<SocketAddr, NetworkError> {
+        let peer = match &self.config.node_identity {
+            Some(identity) => PeerConnection::handshake_encrypted(
+                stream,
+                &self.local_version(),
+                self.config.max_message_size,
+                self.config.handshake_timeout,
+                identity,
+                expected_key,
+            )?,
+            None => PeerConnection::handshake(
+                stream,
+                &self.local_version(),
+                self.config.max_message_size,
+                self.config.handshake_timeout,
+            )?,
+        };
+        if peer.remote_version().nonce == self.nonce {
+            peer.disconnect();
+            return Err(NetworkError::SelfConnection);
+        }
+
+        let remote_addr = peer.remote_addr();
+        let listen_addr = if inbound {
+            let port = peer.remote_version().listen_port;
+            (port != 0).then(|| SocketAddr::new(remote_addr.ip(), port))
+        } else {
+            Some(remote_addr)
+        };
+        let rate_limiter = RateLimiter::new(
+            self.config.max_messages_per_sec,
+            self.config.max_bytes_per_sec,
+            self.config.max_message_size,
+            Instant::now(),
+        );
+        let peer = Arc::new(Peer {
+            connection: peer,
+            inbound,
+            listen_addr,
+            known_inventory: Mutex::new(KnownInventory::new(self.config.known_inventory_capacity)),
+            misbehavior: AtomicU32::new(0),
+            rate_limiter: Mutex::new(rate_limiter),
+            partial_blocks: Mutex::new(HashMap::new()),
+        });
+
+        // Checked again now that the handshake is done, other peers may
+        // have connected in the meantime
+        let max = if inbound {
+            self.config.max_inbound
+        } else {
+            self.config.max_outbound
+        };
+        let peer_id = {
+            let mut peers = self.peers.lock().unwrap();
+            if peers.values().filter(|peer| peer.inbound == inbound).count() >= max {
+                peer.connection.disconnect();
+                return Err(NetworkError::TooManyConnections { max });
+            }
+            let peer_id = self.next_peer_id.fetch_add(1, Ordering::SeqCst);
+            peers.insert(peer_id, Arc::clone(&peer));
+            peer_id
+        };
+
+        let shared = Arc::clone(self);
+        let reader = Arc::clone(&peer);
+        thread::spawn(move || shared.serve_peer(peer_id, reader));
+
+        let locator = {
+            let node = self.node.lock().unwrap();
+            let height = node.get_history().get_last_block().map_or(0, |tip| tip.height);
+            (peer.connection.remote_version().best_height > height).then(|| block_locator(node.get_history()))
+        };
+        if let Some(locator) = locator {
+            peer.connection.send(&Message::GetHeaders(locator))?;
+        }
+
+        let now = Utc::now().timestamp();
+        if !inbound {
+            self.addresses.lock().unwrap().mark_success(remote_addr, now);
+            peer.connection.send(&Message::GetAddr)?;
+        } else if let Some(listen_addr) = listen_addr {
+            let address = PeerAddress {
+                addr: listen_addr,
+                last_seen: now,
+            };
+            if self.addresses.lock().unwrap().add(address.clone(), now) {
+                self.relay_addresses(peer_id, vec![address]);
+            }
+        }
+
+        Ok(remote_addr)
+    }
+
+    fn serve_peer(&self, peer_id: u64, peer: Arc<Peer>) {
+        while self.running.load(Ordering::SeqCst) {
+            let handled = peer.connection.receive_with_size().and_then(|(message, size)| {
+                // Messages over the rate limit are dropped unprocessed
+                if !peer.rate_limiter.lock().unwrap().allow(size, Instant::now()) {
+                    return self.penalize(&peer, RATE_LIMIT_MISBEHAVIOR);
+                }
+                self.handle_message(peer_id, &peer, message)
+            });
+            if let Err(err) = handled {
+                let _ = self.penalize(&peer, network_misbehavior(&err));
+                break;
+            }
+        }
+
+        peer.connection.disconnect();
+        self.peers.lock().unwrap().remove(&peer_id);
+        self.sync.lock().unwrap().remove_peer(peer_id);
+    }
+
+    fn handle_message(&self, peer_id: u64, peer: &Peer, message: Message) -> Result<(), NetworkError> {
+        match message {
+            Message::Inv(items) => {
+                for item in &items {
+                    peer.mark_known(item.clone());
+                }
+
+                // Items already requested from another peer are only asked
+                // again if that peer did not deliver them in time
+                let node = self.node.lock().unwrap();
+                let mut requested = self.requested.lock().unwrap();
+                let now = Instant::now();
+                let missing: Vec<InventoryItem> = items
+                    .into_iter()
+                    .filter(|item| !has_item(&node, item))
+                    .filter(|item| {
+                        requested.get(item).is_none_or(|requested_at| {
+                            now.duration_since(*requested_at) > self.config.inventory_request_timeout
+                        })
+                    })
+                    .collect();
+                for item in &missing {
+                    requested.insert(item.clone(), now);
+                }
+                drop(requested);
+                drop(node);
+
+                if !missing.is_empty() {
+                    peer.connection.send(&Message::GetData(missing))?;
+                }
+            }
+            Message::GetData(items) => {
+                let node = self.node.lock().unwrap();
+                let mut found = Vec::new();
+                let mut not_found = Vec::new();
+                for item in items {
+                    let object = match &item {
+                        InventoryItem::Block(hash) => node.get_history().get_block(hash).cloned().map(Message::Block),
+                        InventoryItem::Tx(id) => node.get_mempool().get_tx(id).cloned().map(Message::Tx),
+                    };
+                    match object {
+                        Some(message) => found.push((item, message)),
+                        None => not_found.push(item),
+                    }
+                }
+                drop(node);
+
+                for (item, message) in found {
+                    peer.mark_known(item);
+                    peer.connection.send(&message)?;
+                }
+                if !not_found.is_empty() {
+                    peer.connection.send(&Message::NotFound(not_found))?;
+                }
+            }
+            Message::GetHeaders(locator) => {
+                let node = self.node.lock().unwrap();
+                let headers = headers_after(node.get_history(), &locator, self.config.sync.max_headers_per_message);
+                drop(node);
+
+                peer.connection.send(&Message::Headers(headers))?;
+            }
+            Message::Headers(headers) => {
+                let node = self.node.lock().unwrap();
+                let mut sync = self.sync.lock().unwrap();
+                let has_more = match sync.on_headers(peer_id, headers, node.get_history()) {
+                    Ok(has_more) => has_more,
+                    Err(err) => {
+                        drop(sync);
+                        drop(node);
+                        return self.penalize(peer, network_misbehavior(&err));
+                    }
+                };
+                let locator = if has_more {
+                    sync.continuation_locator(peer_id)
+                } else {
+                    None
+                };
+                drop(sync);
+                drop(node);
+
+                if let Some(locator) = locator {
+                    peer.connection.send(&Message::GetHeaders(locator))?;
+                }
+                self.request_blocks();
+            }
+            Message::Block(block) => self.on_block(peer, block)?,
+            Message::CompactBlock(compact) => {
+                let hash = compact.header.hash();
+                let item = InventoryItem::Block(hash.clone());
+                peer.mark_known(item.clone());
+
+                let partial = {
+                    let node = self.node.lock().unwrap();
+                    let history = node.get_history();
+                    if history.get_block(&hash).is_some() {
+                        return Ok(());
+                    }
+                    let parent = match history.get_block(&compact.header.previous_hash) {
+                        Some(parent) => parent,
+                        // We are missing blocks between our tip and this one
+                        None => {
+                            let locator = block_locator(history);
+                            drop(node);
+                            return peer.connection.send(&Message::GetHeaders(locator));
+                        }
+                    };
+                    check_compact_header(&compact.header, parent, history).map_err(|source| {
+                        NetworkError::InvalidHeader {
+                            hash: hash.clone(),
+                            source,
+                        }
+                    })?;
+                    PartialBlock::new(&compact, node.get_mempool(), history.get_params())?
+                };
+
+                let missing = partial.missing();
+                if missing.is_empty() {
+                    return match partial.complete(Vec::new()) {
+                        Some(block) => self.on_block(peer, block),
+                        None => peer.connection.send(&Message::GetData(vec![item])),
+                    };
+                }
+
+                let mut partial_blocks = peer.partial_blocks.lock().unwrap();
+                if partial_blocks.len() >= MAX_PARTIAL_BLOCKS {
+                    drop(partial_blocks);
+                    return peer.connection.send(&Message::GetData(vec![item]));
+                }
+                partial_blocks.insert(hash.clone(), partial);
+                drop(partial_blocks);
+                peer.connection.send(&Message::GetBlockTxn {
+                    block_hash: hash,
+                    indexes: missing,
+                })?;
+            }
+            Message::GetBlockTxn { block_hash, indexes } => {
+                let txs: Option<Vec<Transaction>> = {
+                    let node = self.node.lock().unwrap();
+                    node.get_history()
+                        .get_block(&block_hash)
+                        .and_then(|block| indexes.iter().map(|index| block.txs.get(*index).cloned()).collect())
+                };
+                match txs {
+                    Some(txs) => peer.connection.send(&Message::BlockTxn { block_hash, txs })?,
+                    None => peer
+                        .connection
+                        .send(&Message::NotFound(vec![InventoryItem::Block(block_hash)]))?,
+                }
+            }
+            Message::BlockTxn { block_hash, txs } => {
+                // Transactions that were not asked for are ignored
+                let partial = peer.partial_blocks.lock().unwrap().remove(&block_hash);
+                if let Some(partial) = partial {
+                    match partial.complete(txs) {
+                        Some(block) => self.on_block(peer, block)?,
+                        None => peer
+                            .connection
+                            .send(&Message::GetData(vec![InventoryItem::Block(block_hash)]))?,
+                    }
+                }
+            }
+            Message::Tx(tx) => {
+                let item = InventoryItem::Tx(tx.id.clone());
+                peer.mark_known(item.clone());
+                self.requested.lock().unwrap().remove(&item);
+
+                let res = self.node.lock().unwrap().submit_tx(tx);
+                match res {
+                    Ok(()) => self.announce(&[item]),
+                    Err(err) => self.penalize(peer, tx_misbehavior(&err))?,
+                }
+            }
+            Message::NotFound(items) => {
+                let mut requested = self.requested.lock().unwrap();
+                for item in &items {
+                    requested.remove(item);
+                }
+            }
+            Message::GetAddr => {
+                let addresses = self.addresses.lock().unwrap().sample(MAX_ADDR_PER_MESSAGE);
+                peer.connection.send(&Message::Addr(addresses))?;
+            }
+            Message::Addr(addresses) => {
+                if addresses.len() > MAX_ADDR_PER_MESSAGE {
+                    return Err(NetworkError::TooManyAddresses {
+                        count: addresses.len(),
+                        max: MAX_ADDR_PER_MESSAGE,
+                    });
+                }
+
+                let relayed = addresses.len() <= MAX_RELAYED_ADDRESSES;
+                let now = Utc::now().timestamp();
+                let new: Vec<PeerAddress> = {
+                    let mut book = self.addresses.lock().unwrap();
+                    addresses
+                        .into_iter()
+                        .filter(|address| address.addr != self.local_addr)
+                        .filter(|address| book.add(address.clone(), now))
+                        .collect()
+                };
+                if relayed && !new.is_empty() {
+                    self.relay_addresses(peer_id, new);
+                }
+            }
+            Message::Ping(nonce) => peer.connection.send(&Message::Pong(nonce))?,
+            Message::Pong(_) => {}
+            Message::Version(_) | Message::VerAck => {
+                return Err(NetworkError::UnexpectedMessage {
+                    expected: "message after handshake".to_string(),
+                    found: message.name().to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a block received in full or rebuilt from a compact block,
+    /// either downloaded by the sync or relayed as a new tip.
+    fn on_block(&self, peer: &Peer, block: Block) -> Result<(), NetworkError> {
+        let item = InventoryItem::Block(block.hash.clone());
+        peer.mark_known(item.clone());
+        self.requested.lock().unwrap().remove(&item);
+        peer.partial_blocks.lock().unwrap().remove(&block.hash);
+
+        if self.sync.lock().unwrap().on_block(&block) {
+            self.connect_downloaded();
+            self.request_blocks();
+            return Ok(());
+        }
+
+        let res = self.node.lock().unwrap().submit_block(block);
+        match res {
+            Ok(_) => self.announce(&[item]),
+            // We are missing blocks between our tip and this one
+            Err(AppendToHistoryError::InvalidBlock(BlockValidationError::BadParent { .. })) => {
+                let locator = block_locator(self.node.lock().unwrap().get_history());
+                peer.connection.send(&Message::GetHeaders(locator))?;
+            }
+            Err(err) => self.penalize(peer, block_misbehavior(&err))?,
+        }
+        Ok(())
+    }
+
+    /// Connects the blocks downloaded by the sync and announces the new tip.
+    /// A peer whose blocks do not connect has its headers discarded, and is
+    /// punished if the blocks are invalid.
+    fn connect_downloaded(&self) {
+        let mut penalties = Vec::new();
+        let (old_tip, new_tip) = {
+            let mut node = self.node.lock().unwrap();
+            let mut sync = self.sync.lock().unwrap();
+            let old_tip = node.get_history().get_last_block().map(|tip| tip.hash.clone());
+
+            while let Some((source, step)) = sync.next_step(node.get_history()) {
+                let connected = match step {
+                    SyncStep::Extend(blocks) => match blocks
+                        .into_iter()
+                        .try_for_each(|block| node.submit_block(block).map(|_| ()))
+                    {
+                        Ok(()) => true,
+                        Err(err) => {
+                            penalties.push((source, block_misbehavior(&err)));
+                            false
+                        }
+                    },
+                    SyncStep::Reorg(chain) => match node.reorganize(&chain) {
+                        Ok(switched) => switched,
+                        Err(err) => {
+                            penalties.push((source, block_misbehavior(&err)));
+                            false
+                        }
+                    },
+                };
+                if !connected {
+                    sync.discard_headers(source);
+                }
+            }
+
+            let new_tip = node.get_history().get_last_block().map(|tip| tip.hash.clone());
+            (old_tip, new_tip)
+        };
+
+        for (peer_id, score) in penalties {
+            let peer = self.peers.lock().unwrap().get(&peer_id).cloned();
+            if let Some(peer) = peer {
+                if self.penalize(&peer, score).is_err() {
+                    peer.connection.disconnect();
+                }
+            }
+        }
+
+        if let Some(new_tip) = new_tip.filter(|new_tip| Some(new_tip) != old_tip.as_ref()) {
+            self.announce(&[InventoryItem::Block(new_tip)]);
+        }
+    }
+
+    /// Adds `score` to the misbehavior of `peer`. Once it reaches
+    /// `BAN_THRESHOLD` the address of the peer is banned and an error is
+    /// returned, so that the connection gets closed.
+    fn penalize(&self, peer: &Peer, score: u32) -> Result<(), NetworkError> {
+        if score == 0 {
+            return Ok(());
+        }
+
+        let total = peer
+            .misbehavior
+            .fetch_add(score, Ordering::SeqCst)
+            .saturating_add(score);
+        if total < BAN_THRESHOLD {
+            return Ok(());
+        }
+
+        self.bans.lock().unwrap().ban(
+            peer.connection.remote_addr().ip(),
+            self.config.ban_duration,
+            Instant::now(),
+        );
+        Err(NetworkError::Misbehaving { score: total })
+    }
+
+    /// Asks every peer for the next blocks of the best header chain.
+    fn request_blocks(&self) {
+        let peers: Vec<(u64, Arc<Peer>)> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer_id, peer)| (*peer_id, Arc::clone(peer)))
+            .collect();
+
+        let requests: Vec<(Arc<Peer>, Vec<String>)> = {
+            let node = self.node.lock().unwrap();
+            let mut sync = self.sync.lock().unwrap();
+            let now = Instant::now();
+            peers
+                .into_iter()
+                .map(|(peer_id, peer)| (peer, sync.blocks_to_request(peer_id, node.get_history(), now)))
+                .filter(|(_, hashes)| !hashes.is_empty())
+                .collect()
+        };
+
+        for (peer, hashes) in requests {
+            let items = hashes.into_iter().map(InventoryItem::Block).collect();
+            if peer.connection.send(&Message::GetData(items)).is_err() {
+                peer.connection.disconnect();
+            }
+        }
+    }
+
+    /// Disconnects the peers that stall the download and gives their
+    /// blocks to the other peers. Requests of announced items that timed
+    /// out and ended bans are forgotten.
+    fn on_tick(self: &Arc<Self>) {
+        let now = Instant::now();
+        let stalled = self.sync.lock().unwrap().stalled_peers(now);
+        for peer_id in stalled {
+            if let Some(peer) = self.peers.lock().unwrap().remove(&peer_id) {
+                peer.connection.disconnect();
+            }
+            self.sync.lock().unwrap().remove_peer(peer_id);
+        }
+
+        let timeout = self.config.inventory_request_timeout;
+        self.requested
+            .lock()
+            .unwrap()
+            .retain(|_, requested_at| now.duration_since(*requested_at) <= timeout);
+        self.bans.lock().unwrap().remove_expired(now);
+
+        self.request_blocks();
+        self.connect_to_more_peers();
+    }
+
+    /// Connects to addresses of the address book until `target_outbound`
+    /// outbound peers are connected or being connected to, each on its own
+    /// thread. Addresses in network groups without outbound peers yet are
+    /// preferred.
+    fn connect_to_more_peers(self: &Arc<Self>) {
+        let target = self.config.target_outbound.min(self.config.max_outbound);
+        let (mut excluded, mut used_groups, outbound) = {
+            let peers = self.peers.lock().unwrap();
+            let excluded: HashSet<SocketAddr> = peers.values().filter_map(|peer| peer.listen_addr).collect();
+            let used_groups: HashSet<Vec<u8>> = peers
+                .values()
+                .filter(|peer| !peer.inbound)
+                .map(|peer| net_group(&peer.connection.remote_addr().ip()))
+                .collect();
+            let outbound = peers.values().filter(|peer| !peer.inbound).count();
+            (excluded, used_groups, outbound)
+        };
+
+        let mut connecting = self.connecting.lock().unwrap();
+        excluded.insert(self.local_addr);
+        excluded.extend(connecting.iter());
+        used_groups.extend(connecting.iter().map(|addr| net_group(&addr.ip())));
+
+        let now = Utc::now().timestamp();
+        let retry_interval = self.config.connect_retry_interval.as_secs() as i64;
+        let mut book = self.addresses.lock().unwrap();
+        let bans = self.bans.lock().unwrap();
+        while outbound + connecting.len() < target {
+            let selected = book.select(&used_groups, retry_interval, now, |known| {
+                !excluded.contains(&known.addr) && !bans.is_banned(&known.addr.ip(), Instant::now())
+            });
+            let addr = match selected {
+                Some(addr) => addr,
+                None => break,
+            };
+
+            book.mark_attempt(&addr, now);
+            excluded.insert(addr);
+            used_groups.insert(net_group(&addr.ip()));
+            connecting.insert(addr);
+            let shared = Arc::clone(self);
+            thread::spawn(move || shared.connect_outbound(addr));
+        }
+    }
+
+    /// Connects to `addr` for the connection manager and records the
+    /// outcome in the address book.
+    fn connect_outbound(self: &Arc<Self>, addr: SocketAddr) {
+        let connected = TcpStream::connect_timeout(&addr, self.config.handshake_timeout)
+            .map_err(NetworkError::from)
+            .and_then(|stream| self.add_peer(stream, false, None));
+        match connected {
+            Ok(_) => {}
+            Err(NetworkError::SelfConnection) => {
+                self.addresses.lock().unwrap().remove(&addr);
+            }
+            Err(_) => self.addresses.lock().unwrap().mark_failure(&addr),
+        }
+        self.connecting.lock().unwrap().remove(&addr);
+    }
+
+    /// Sends addresses new to us to a few random peers other than the one
+    /// they come from. Each node relays an address once, which stops the
+    /// gossip.
+    fn relay_addresses(&self, source_id: u64, addresses: Vec<PeerAddress>) {
+        let mut peers: Vec<Arc<Peer>> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(peer_id, _)| **peer_id != source_id)
+            .map(|(_, peer)| Arc::clone(peer))
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+
+        let message = Message::Addr(addresses);
+        for peer in peers.into_iter().take(ADDR_RELAY_FANOUT) {
+            if peer.connection.send(&message).is_err() {
+                peer.connection.disconnect();
+            }
+        }
+    }
+
+    /// Announces `items` to every peer that does not know them yet. Blocks
+    /// are sent as compact blocks to the peers supporting them.
+    fn announce(&self, items: &[InventoryItem]) {
+        let peers: Vec<Arc<Peer>> = self.peers.lock().unwrap().values().cloned().collect();
+
+        let compact_blocks: HashMap<&InventoryItem, CompactBlock> =
+            if peers.iter().any(|peer| peer.supports_compact_blocks()) {
+                let node = self.node.lock().unwrap();
+                items
+                    .iter()
+                    .filter_map(|item| match item {
+                        InventoryItem::Block(hash) => node
+                            .get_history()
+                            .get_block(hash)
+                            .map(|block| (item, CompactBlock::from_block(block))),
+                        InventoryItem::Tx(_) => None,
+                    })
+                    .collect()
+            } else {
+                HashMap::new()
+            };
+
+        for peer in peers {
+            let mut messages = Vec::new();
+            let mut inventory = Vec::new();
+            for item in peer.filter_unknown(items) {
+                match compact_blocks.get(&item).filter(|_| peer.supports_compact_blocks()) {
+                    Some(compact) => messages.push(Message::CompactBlock(compact.clone())),
+                    None => inventory.push(item),
+                }
+            }
+            if !inventory.is_empty() {
+                messages.push(Message::Inv(inventory));
+            }
+
+            if messages.iter().any(|message| peer.connection.send(message).is_err()) {
+                peer.connection.disconnect();
+            }
+        }
+    }
+}
+
+/// Checks the header of a compact block against its parent in our chain,
+/// before anything is allocated for its transactions.
+fn check_compact_header(header: &BlockHeader, parent: &Block, history: &History) -> Result<(), BlockValidationError> {
+    header.check_proof_of_work(history.get_params())?;
+
+    if header.height != parent.height + 1 {
+        return Err(BlockValidationError::BadHeight {
+            expected: parent.height + 1,
+            found: header.height,
+        });
+    }
+
+    let expected_difficulty = history
+        .next_difficulty_at_height(parent.height)
+        .expect("The parent was found in the chain");
+    if header.difficulty != expected_difficulty {
+        return Err(BlockValidationError::BadDifficulty {
+            expected: expected_difficulty,
+            found: header.difficulty,
+        });
+    }
+
+    Ok(())
+}
+
+fn has_item(node: &Node, item: &InventoryItem) -> bool {
+    match item {
+        InventoryItem::Block(hash) => node.get_history().get_block(hash).is_some(),
+        InventoryItem::Tx(id) => node.get_mempool().get_tx(id).is_some(),
+    }
+}
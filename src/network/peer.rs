@@ -0,0 +1,207 @@
+use std::{
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::Mutex,
+    time::Duration,
+};
+
+use secp256k1::PublicKey;
+
+use super::{
+    framing::{read_encrypted_message_with_size, read_message_with_size, write_encrypted_message, write_message},
+    transport, Message, NetworkError, NodeIdentity, SessionCipher, VersionMessage, MIN_PROTOCOL_VERSION,
+};
+
+/// One direction of a connection, encrypted once the transport handshake
+/// is done.
+struct Channel {
+    stream: TcpStream,
+    cipher: Option<SessionCipher>,
+}
+
+impl Channel {
+    fn send(&mut self, message: &Message, max_message_size: usize) -> Result<(), NetworkError> {
+        match &mut self.cipher {
+            Some(cipher) => write_encrypted_message(&mut self.stream, message, max_message_size, cipher),
+            None => write_message(&mut self.stream, message, max_message_size),
+        }
+    }
+
+    fn receive_with_size(&mut self, max_message_size: usize) -> Result<(Message, usize), NetworkError> {
+        match &mut self.cipher {
+            Some(cipher) => read_encrypted_message_with_size(&mut self.stream, max_message_size, cipher),
+            None => read_message_with_size(&mut self.stream, max_message_size),
+        }
+    }
+
+    fn receive(&mut self, max_message_size: usize) -> Result<Message, NetworkError> {
+        self.receive_with_size(max_message_size).map(|(message, _)| message)
+    }
+}
+
+/// A TCP connection to a peer that completed the handshake. Messages can
+/// be sent from any thread, while a single thread is expected to receive.
+pub struct PeerConnection {
+    writer: Mutex<Channel>,
+    reader: Mutex<Channel>,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    remote_version: VersionMessage,
+    remote_key: Option<PublicKey>,
+    max_message_size: usize,
+}
+
+impl PeerConnection {
+    /// Runs the handshake on a freshly opened stream: both sides send their
+    /// version, check the one of the other side and acknowledge it. Peers on
+    /// another chain or speaking a too old protocol are rejected. Writes
+    /// keep timing out after `timeout` once connected, so that a peer that
+    /// stops reading cannot block the threads sending to it.
+    pub fn handshake(
+        stream: TcpStream,
+        local_version: &VersionMessage,
+        max_message_size: usize,
+        timeout: Duration,
+    ) -> Result<PeerConnection, NetworkError> {
+        PeerConnection::open(stream, local_version, max_message_size, timeout, None)
+    }
+
+    /// Same as `handshake`, first running the transport handshake so that
+    /// everything after it is encrypted and the peer proved it owns its
+    /// node key. A peer whose node key is not `expected_key` is rejected.
+    pub fn handshake_encrypted(
+        stream: TcpStream,
+        local_version: &VersionMessage,
+        max_message_size: usize,
+        timeout: Duration,
+        identity: &NodeIdentity,
+        expected_key: Option<&PublicKey>,
+    ) -> Result<PeerConnection, NetworkError> {
+        PeerConnection::open(
+            stream,
+            local_version,
+            max_message_size,
+            timeout,
+            Some((identity, expected_key)),
+        )
+    }
+
+    fn open(
+        stream: TcpStream,
+        local_version: &VersionMessage,
+        max_message_size: usize,
+        timeout: Duration,
+        identity: Option<(&NodeIdentity, Option<&PublicKey>)>,
+    ) -> Result<PeerConnection, NetworkError> {
+        let remote_addr = stream.peer_addr()?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut reader = Channel {
+            stream: stream.try_clone()?,
+            cipher: None,
+        };
+        let mut writer = Channel {
+            stream: stream.try_clone()?,
+            cipher: None,
+        };
+
+        let mut remote_key = None;
+        if let Some((identity, expected_key)) = identity {
+            let session = transport::handshake(&mut reader.stream, &mut writer.stream, identity, expected_key)?;
+            reader.cipher = Some(session.receive);
+            writer.cipher = Some(session.send);
+            remote_key = Some(session.remote_key);
+        }
+
+        writer.send(&Message::Version(local_version.clone()), max_message_size)?;
+        let remote_version = match reader.receive(max_message_size)? {
+            Message::Version(version) => version,
+            other => {
+                return Err(NetworkError::UnexpectedMessage {
+                    expected: "version".to_string(),
+                    found: other.name().to_string(),
+                })
+            }
+        };
+        check_version(local_version, &remote_version)?;
+
+        writer.send(&Message::VerAck, max_message_size)?;
+        match reader.receive(max_message_size)? {
+            Message::VerAck => {}
+            other => {
+                return Err(NetworkError::UnexpectedMessage {
+                    expected: "verack".to_string(),
+                    found: other.name().to_string(),
+                })
+            }
+        }
+
+        stream.set_read_timeout(None)?;
+        Ok(PeerConnection {
+            writer: Mutex::new(writer),
+            reader: Mutex::new(reader),
+            stream,
+            remote_addr,
+            remote_version,
+            remote_key,
+            max_message_size,
+        })
+    }
+
+    pub fn send(&self, message: &Message) -> Result<(), NetworkError> {
+        self.writer.lock().unwrap().send(message, self.max_message_size)
+    }
+
+    /// Blocks until the next message from the peer arrives.
+    pub fn receive(&self) -> Result<Message, NetworkError> {
+        self.reader.lock().unwrap().receive(self.max_message_size)
+    }
+
+    /// Same as `receive`, also returning the size of the message in bytes,
+    /// once decrypted.
+    pub fn receive_with_size(&self) -> Result<(Message, usize), NetworkError> {
+        self.reader.lock().unwrap().receive_with_size(self.max_message_size)
+    }
+
+    /// Closes the connection, which also unblocks `receive`.
+    pub fn disconnect(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    pub fn remote_version(&self) -> &VersionMessage {
+        &self.remote_version
+    }
+
+    /// Node key the peer proved it owns, if the connection is encrypted.
+    pub fn remote_key(&self) -> Option<&PublicKey> {
+        self.remote_key.as_ref()
+    }
+}
+
+fn check_version(local: &VersionMessage, remote: &VersionMessage) -> Result<(), NetworkError> {
+    if remote.protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(NetworkError::UnsupportedProtocolVersion {
+            found: remote.protocol_version,
+            min_supported: MIN_PROTOCOL_VERSION,
+        });
+    }
+
+    if remote.chain_id != local.chain_id {
+        return Err(NetworkError::WrongChain {
+            expected: local.chain_id,
+            found: remote.chain_id,
+        });
+    }
+
+    if remote.genesis_hash != local.genesis_hash {
+        return Err(NetworkError::WrongGenesis {
+            expected: local.genesis_hash.clone(),
+            found: remote.genesis_hash.clone(),
+        });
+    }
+
+    Ok(())
+}
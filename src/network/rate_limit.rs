@@ -0,0 +1,98 @@
+use std::time::Instant;
+
+/// Token bucket: holds up to `capacity` tokens, refilled at `rate` per second.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+/// Limits the messages and bytes received from a peer per second. Short
+/// bursts are allowed up to one second worth of traffic, or one message of
+/// the largest allowed size.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(
+        max_messages_per_sec: u32,
+        max_bytes_per_sec: usize,
+        max_message_size: usize,
+        now: Instant,
+    ) -> RateLimiter {
+        let bytes_rate = max_bytes_per_sec as f64;
+        RateLimiter {
+            messages: TokenBucket::new(max_messages_per_sec as f64, max_messages_per_sec as f64, now),
+            bytes: TokenBucket::new(bytes_rate, bytes_rate.max(max_message_size as f64), now),
+        }
+    }
+
+    /// Accounts for a message of `size` bytes received at `now`. Returns
+    /// `false`, consuming nothing, if it goes over the limits.
+    pub fn allow(&mut self, size: usize, now: Instant) -> bool {
+        self.messages.refill(now);
+        self.bytes.refill(now);
+
+        let size = size as f64;
+        if self.messages.tokens < 1.0 || self.bytes.tokens < size {
+            return false;
+        }
+
+        self.messages.tokens -= 1.0;
+        self.bytes.tokens -= size;
+        true
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_test {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[test]
+    fn messages_over_the_rate_are_refused_until_the_bucket_refills() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(2, 1000, 100, now);
+
+        assert!(limiter.allow(10, now));
+        assert!(limiter.allow(10, now));
+        assert!(!limiter.allow(10, now));
+
+        assert!(limiter.allow(10, now + Duration::from_millis(500)));
+        assert!(!limiter.allow(10, now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn bytes_over_the_rate_are_refused() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(100, 1000, 100, now);
+
+        assert!(limiter.allow(900, now));
+        assert!(!limiter.allow(200, now));
+        assert!(limiter.allow(100, now));
+        assert!(limiter.allow(200, now + Duration::from_millis(200)));
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::core::{
+    mine_new_block, Block, Clock, ConsensusParams, History, ManualClock, MemPool, Node, ReorgChainStrategy, Transaction,
+};
+
+#[derive(Debug, Clone)]
+pub struct SimulatorConfig {
+    /// Every random choice of the simulation derives from it, so the same
+    /// config and scenario always give the same report.
+    pub seed: u64,
+    /// Delay of each message, picked uniformly between the two bounds.
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// Probability for a message to be lost, between 0 and 1.
+    pub loss_rate: f64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            seed: 0,
+            min_latency: Duration::from_millis(50),
+            max_latency: Duration::from_millis(200),
+            loss_rate: 0.0,
+        }
+    }
+}
+
+/// Outcome of a simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    pub blocks_mined: usize,
+    /// Mined blocks that are not part of the best chain at the end.
+    pub stale_blocks: usize,
+    /// Number of blocks disconnected by each reorganization of any node,
+    /// in the order they happened.
+    pub reorg_depths: Vec<u64>,
+    /// Tip of every node at the end, by node index.
+    pub tips: Vec<String>,
+    /// Height of the chain with the most work at the end.
+    pub best_height: u64,
+    pub messages_sent: u64,
+    /// Messages dropped, at random or by a partition.
+    pub messages_lost: u64,
+}
+
+impl SimulationReport {
+    /// Share of the mined blocks that ended up stale.
+    pub fn orphan_rate(&self) -> f64 {
+        if self.blocks_mined == 0 {
+            return 0.0;
+        }
+        self.stale_blocks as f64 / self.blocks_mined as f64
+    }
+
+    pub fn max_reorg_depth(&self) -> u64 {
+        self.reorg_depths.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Whether every node ended on the same tip.
+    pub fn consensus(&self) -> bool {
+        self.tips.windows(2).all(|tips| tips[0] == tips[1])
+    }
+}
+
+#[derive(Debug, Clone)]
+enum SimMessage {
+    Block(Block),
+    GetBlock(String),
+}
+
+#[derive(Debug)]
+enum Event {
+    Mine(usize),
+    Deliver {
+        from: usize,
+        to: usize,
+        message: SimMessage,
+    },
+    /// Group of every node, messages between groups are lost.
+    Partition(Vec<usize>),
+    Heal,
+}
+
+/// An event due at `at` milliseconds. Events due at the same time run in
+/// the order they were scheduled.
+#[derive(Debug)]
+struct Scheduled {
+    at: u64,
+    sequence: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Reversed, so that the earliest event is at the top of the heap.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.sequence).cmp(&(self.at, self.sequence))
+    }
+}
+
+struct SimNode {
+    node: Node,
+    /// Share of the hash rate of the network is the hash rate of the node
+    /// over the sum of all of them.
+    hash_rate: f64,
+    peers: Vec<usize>,
+    /// Every valid block received or mined, on the active chain or not.
+    blocks: HashMap<String, Block>,
+    /// Blocks waiting for their parent, by parent hash.
+    orphans: HashMap<String, Vec<Block>>,
+}
+
+/// Deterministic discrete-event simulation of nodes relaying blocks to
+/// each other, all in one process and without real sockets or time.
+///
+/// Nodes find blocks at random, at the pace of `target_block_time` for the
+/// whole network, and relay the blocks joining their active chain to their
+/// peers. A block whose parent is unknown is kept aside and the parent is
+/// asked to the peer that sent it. Forks are resolved by the reorg strategy
+/// of the nodes, which makes the simulator a way to test fork choice under
+/// latency, message loss and partitions.
+///
+/// Mining is real, so `params` should have a low difficulty.
+pub struct Simulator {
+    params: ConsensusParams,
+    strategy: Box<dyn ReorgChainStrategy>,
+    config: SimulatorConfig,
+    rng: StdRng,
+    clock: ManualClock,
+    nodes: Vec<SimNode>,
+    events: BinaryHeap<Scheduled>,
+    /// Simulated time, in milliseconds since the start.
+    now: u64,
+    next_sequence: u64,
+    /// Group of every node while the network is partitioned.
+    groups: Option<Vec<usize>>,
+    mined: Vec<String>,
+    reorg_depths: Vec<u64>,
+    messages_sent: u64,
+    messages_lost: u64,
+}
+
+impl Simulator {
+    pub fn new(params: ConsensusParams, strategy: Box<dyn ReorgChainStrategy>, config: SimulatorConfig) -> Simulator {
+        Simulator {
+            clock: ManualClock::new(params.genesis_timestamp),
+            rng: StdRng::seed_from_u64(config.seed),
+            params,
+            strategy,
+            config,
+            nodes: Vec::new(),
+            events: BinaryHeap::new(),
+            now: 0,
+            next_sequence: 0,
+            groups: None,
+            mined: Vec::new(),
+            reorg_depths: Vec::new(),
+            messages_sent: 0,
+            messages_lost: 0,
+        }
+    }
+
+    /// Adds a node with `hash_rate`, relative to the other nodes, and
+    /// returns its index. A node with no hash rate only relays blocks.
+    pub fn add_node(&mut self, hash_rate: f64) -> usize {
+        let history = History::with_clock(self.params.clone(), self.strategy.clone(), Box::new(self.clock.clone()));
+        let genesis = history.get_last_block().unwrap().clone();
+        self.nodes.push(SimNode {
+            node: Node::new(history, MemPool::new(1_000_000, self.params.clone())),
+            hash_rate,
+            peers: Vec::new(),
+            blocks: HashMap::from([(genesis.hash.clone(), genesis)]),
+            orphans: HashMap::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn connect(&mut self, first: usize, second: usize) {
+        if first == second || self.nodes[first].peers.contains(&second) {
+            return;
+        }
+        self.nodes[first].peers.push(second);
+        self.nodes[second].peers.push(first);
+    }
+
+    pub fn connect_all(&mut self) {
+        for first in 0..self.nodes.len() {
+            for second in first + 1..self.nodes.len() {
+                self.connect(first, second);
+            }
+        }
+    }
+
+    /// Splits the network into `groups` at `at`, nodes in none of them
+    /// forming one more group. Messages between groups are lost, including
+    /// the ones already sent. Every node must be added before.
+    pub fn partition(&mut self, at: Duration, groups: &[Vec<usize>]) {
+        let mut node_groups = vec![groups.len(); self.nodes.len()];
+        for (group, nodes) in groups.iter().enumerate() {
+            for node in nodes {
+                node_groups[*node] = group;
+            }
+        }
+        self.schedule(at.as_millis() as u64, Event::Partition(node_groups));
+    }
+
+    /// Ends the partition at `at`. Every node then sends its tip to its
+    /// peers, as it would to newly connected ones.
+    pub fn heal(&mut self, at: Duration) {
+        self.schedule(at.as_millis() as u64, Event::Heal);
+    }
+
+    /// Lets the nodes mine for `duration`, then stops mining and delivers
+    /// the messages still in flight before reporting.
+    pub fn run(mut self, duration: Duration) -> SimulationReport {
+        for index in 0..self.nodes.len() {
+            self.schedule_mining(index);
+        }
+
+        let end = duration.as_millis() as u64;
+        while let Some(Scheduled { at, event, .. }) = self.events.pop() {
+            if at > end && matches!(event, Event::Mine(_)) {
+                continue;
+            }
+            self.now = at;
+            self.clock.set(self.params.genesis_timestamp + (self.now / 1000) as i64);
+            self.handle(event);
+        }
+
+        self.report()
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Mine(index) => {
+                let block = self.mine(index);
+                self.mined.push(block.hash.clone());
+                self.receive_block(index, block, None);
+                self.schedule_mining(index);
+            }
+            Event::Deliver { from, to, message } => {
+                let partitioned = self.groups.as_ref().is_some_and(|groups| groups[from] != groups[to]);
+                if partitioned {
+                    self.messages_lost += 1;
+                    return;
+                }
+                match message {
+                    SimMessage::Block(block) => self.receive_block(to, block, Some(from)),
+                    SimMessage::GetBlock(hash) => {
+                        if let Some(block) = self.nodes[to].blocks.get(&hash).cloned() {
+                            self.send(to, from, SimMessage::Block(block));
+                        }
+                    }
+                }
+            }
+            Event::Partition(groups) => self.groups = Some(groups),
+            Event::Heal => {
+                self.groups = None;
+                for index in 0..self.nodes.len() {
+                    let tip = self.nodes[index].node.get_history().get_last_block().unwrap().clone();
+                    if tip.height == 0 {
+                        continue;
+                    }
+                    for peer in self.nodes[index].peers.clone() {
+                        self.send(index, peer, SimMessage::Block(tip.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mines a block with only a coinbase on top of the tip of the node.
+    fn mine(&self, index: usize) -> Block {
+        let history = self.nodes[index].node.get_history();
+        let prev_block = history.get_last_block().unwrap();
+        let height = prev_block.height + 1;
+        let timestamp = self.clock.now().max(history.median_time_past() + 1);
+        let difficulty = history.next_difficulty();
+        let reward = Transaction::coinbase(
+            format!("node-{}", index),
+            self.params.block_reward(height),
+            height,
+            self.params.chain_id,
+        );
+        let txs = vec![reward];
+
+        let (nonce, hash) = mine_new_block(height, timestamp, &prev_block.hash, &txs, difficulty);
+        Block::new(prev_block, hash, timestamp, txs, difficulty, nonce)
+    }
+
+    /// Connects `block` and the orphans waiting for it, relaying those that
+    /// join the active chain to every peer but `from`.
+    fn receive_block(&mut self, index: usize, block: Block, from: Option<usize>) {
+        let mut outgoing = Vec::new();
+        let mut pending = vec![block];
+        while let Some(block) = pending.pop() {
+            let sim_node = &mut self.nodes[index];
+            if sim_node.blocks.contains_key(&block.hash) || block.check_context_free(&self.params).is_err() {
+                continue;
+            }
+            if !sim_node.blocks.contains_key(&block.previous_hash) {
+                if let Some(from) = from {
+                    outgoing.push((from, SimMessage::GetBlock(block.previous_hash.clone())));
+                }
+                sim_node
+                    .orphans
+                    .entry(block.previous_hash.clone())
+                    .or_default()
+                    .push(block);
+                continue;
+            }
+
+            if !self.connect_block(index, &block) {
+                continue;
+            }
+            let sim_node = &mut self.nodes[index];
+            if sim_node.node.get_history().get_block(&block.hash).is_some() {
+                for peer in sim_node.peers.iter().filter(|peer| Some(**peer) != from) {
+                    outgoing.push((*peer, SimMessage::Block(block.clone())));
+                }
+            }
+            pending.extend(sim_node.orphans.remove(&block.hash).unwrap_or_default());
+            sim_node.blocks.insert(block.hash.clone(), block);
+        }
+
+        for (to, message) in outgoing {
+            self.send(index, to, message);
+        }
+    }
+
+    /// Appends `block` if it extends the tip, otherwise offers its branch to
+    /// the reorg strategy. Returns `false` if the block is invalid.
+    fn connect_block(&mut self, index: usize, block: &Block) -> bool {
+        let sim_node = &mut self.nodes[index];
+        let history = sim_node.node.get_history();
+        if history.get_last_block().unwrap().hash == block.previous_hash {
+            return sim_node.node.submit_block(block.clone()).is_ok();
+        }
+
+        let mut branch = vec![block.clone()];
+        while let Some(parent) = sim_node.blocks.get(&branch.last().unwrap().previous_hash) {
+            branch.push(parent.clone());
+        }
+        branch.reverse();
+
+        let old_height = history.get_height();
+        let fork_index = history
+            .get_chain()
+            .iter()
+            .zip(&branch)
+            .take_while(|(old_block, new_block)| old_block.hash == new_block.hash)
+            .count();
+        match sim_node.node.reorganize(&branch) {
+            Ok(true) if old_height > fork_index => self.reorg_depths.push((old_height - fork_index) as u64),
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+        true
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: SimMessage) {
+        self.messages_sent += 1;
+        if self.rng.gen::<f64>() < self.config.loss_rate {
+            self.messages_lost += 1;
+            return;
+        }
+
+        let min_latency = self.config.min_latency.as_millis() as u64;
+        let max_latency = (self.config.max_latency.as_millis() as u64).max(min_latency);
+        let latency = self.rng.gen_range(min_latency..=max_latency);
+        self.schedule(self.now + latency, Event::Deliver { from, to, message });
+    }
+
+    /// Schedules the next block of the node. Finding a block is memoryless,
+    /// so the delay is drawn from an exponential distribution.
+    fn schedule_mining(&mut self, index: usize) {
+        let total_hash_rate: f64 = self.nodes.iter().map(|sim_node| sim_node.hash_rate).sum();
+        let hash_rate = self.nodes[index].hash_rate;
+        if hash_rate <= 0.0 {
+            return;
+        }
+
+        let mean_interval = (self.params.target_block_time * 1000) as f64 * total_hash_rate / hash_rate;
+        let delay = -(1.0 - self.rng.gen::<f64>()).ln() * mean_interval;
+        self.schedule(self.now + delay as u64, Event::Mine(index));
+    }
+
+    fn schedule(&mut self, at: u64, event: Event) {
+        self.events.push(Scheduled {
+            at,
+            sequence: self.next_sequence,
+            event,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// Stale blocks are counted against the chain with the most work, the
+    /// one of the first node on ties.
+    fn report(&self) -> SimulationReport {
+        let best = self
+            .nodes
+            .iter()
+            .map(|sim_node| sim_node.node.get_history())
+            .reduce(|best, history| {
+                if history.chain_work() > best.chain_work() {
+                    history
+                } else {
+                    best
+                }
+            });
+        let best_chain: HashSet<&str> = best
+            .map(|history| history.get_chain().iter().map(|block| block.hash.as_str()).collect())
+            .unwrap_or_default();
+
+        SimulationReport {
+            blocks_mined: self.mined.len(),
+            stale_blocks: self
+                .mined
+                .iter()
+                .filter(|hash| !best_chain.contains(hash.as_str()))
+                .count(),
+            reorg_depths: self.reorg_depths.clone(),
+            tips: self
+                .nodes
+                .iter()
+                .map(|sim_node| sim_node.node.get_history().get_last_block().unwrap().hash.clone())
+                .collect(),
+            best_height: best
+                .and_then(|history| history.get_last_block())
+                .map_or(0, |tip| tip.height),
+            messages_sent: self.messages_sent,
+            messages_lost: self.messages_lost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod simulator_test {
+    use std::time::Duration;
+
+    use crate::core::{ConsensusParams, NaiveReorgStrategy};
+
+    use super::{Simulator, SimulatorConfig};
+
+    fn params() -> ConsensusParams {
+        ConsensusParams {
+            initial_difficulty: 1,
+            retarget_interval: 0,
+            ..ConsensusParams::default()
+        }
+    }
+
+    fn simulator(config: SimulatorConfig) -> Simulator {
+        let mut simulator = Simulator::new(params(), Box::new(NaiveReorgStrategy {}), config);
+        for _ in 0..4 {
+            simulator.add_node(1.0);
+        }
+        simulator.connect_all();
+        simulator
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_report() {
+        let config = SimulatorConfig {
+            seed: 7,
+            loss_rate: 0.1,
+            ..SimulatorConfig::default()
+        };
+
+        let first = simulator(config.clone()).run(Duration::from_secs(30 * 60));
+        let second = simulator(config).run(Duration::from_secs(30 * 60));
+
+        assert!(first.blocks_mined > 0);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn nodes_on_both_sides_of_a_partition_do_not_hear_from_each_other() {
+        let mut simulator = simulator(SimulatorConfig::default());
+        simulator.partition(Duration::ZERO, &[vec![0, 1]]);
+
+        let report = simulator.run(Duration::from_secs(60 * 60));
+
+        assert!(!report.consensus());
+        assert_eq!(report.tips[0], report.tips[1]);
+        assert_eq!(report.tips[2], report.tips[3]);
+        assert!(report.messages_lost > 0);
+    }
+}
@@ -0,0 +1,644 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::core::{Block, BlockHeader, BlockValidationError, History};
+
+use super::NetworkError;
+
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Most headers sent in a single `Headers` message. Receiving a full
+    /// message means the peer has more to send.
+    pub max_headers_per_message: usize,
+    /// Most blocks requested from a single peer at once.
+    pub max_blocks_in_flight_per_peer: usize,
+    /// How far past the first missing block downloads may go, which bounds
+    /// the number of blocks waiting to be connected.
+    pub download_window: usize,
+    /// A peer not delivering a requested block within this delay is stalling.
+    pub block_timeout: Duration,
+    /// Most headers a peer may announce past the height of our tip while
+    /// its chain has no more work than ours.
+    pub max_headers_without_work: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            max_headers_per_message: 2000,
+            max_blocks_in_flight_per_peer: 16,
+            download_window: 1024,
+            block_timeout: Duration::from_secs(10),
+            max_headers_without_work: 2000,
+        }
+    }
+}
+
+/// Blocks ready to be handed to the node.
+#[derive(Debug)]
+pub enum SyncStep {
+    /// Blocks extending the tip of the chain, in order.
+    Extend(Vec<Block>),
+    /// A whole chain with more work than ours, forking below our tip.
+    Reorg(Vec<Block>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// Height of the best header chain announced by a peer, if it has more
+    /// work than our chain.
+    pub best_header_height: Option<u64>,
+    pub blocks_in_flight: usize,
+    /// Blocks received since the start, whether they connected or not.
+    pub blocks_downloaded: u64,
+}
+
+/// Header chain announced by a peer. It forks from our chain after the
+/// block at `fork_height` and is validated, except for the transactions.
+struct PeerHeaders {
+    fork_height: u64,
+    headers: Vec<(String, BlockHeader)>,
+    hashes: HashSet<String>,
+    work: u128,
+}
+
+/// Headers-first synchronization. Header chains are collected from every
+/// peer and the one with the most work is downloaded, spreading the block
+/// requests over all the peers that announced it.
+pub struct ChainSync {
+    config: SyncConfig,
+    peers: HashMap<u64, PeerHeaders>,
+    in_flight: HashMap<String, (u64, Instant)>,
+    downloaded: HashMap<String, Block>,
+    downloaded_count: u64,
+}
+
+impl ChainSync {
+    pub fn new(config: SyncConfig) -> ChainSync {
+        ChainSync {
+            config,
+            peers: HashMap::new(),
+            in_flight: HashMap::new(),
+            downloaded: HashMap::new(),
+            downloaded_count: 0,
+        }
+    }
+
+    pub fn get_config(&self) -> &SyncConfig {
+        &self.config
+    }
+
+    /// Validates the headers sent by `peer` and records them. They must
+    /// either extend the headers previously sent by the peer or build on a
+    /// block of our chain. A peer whose chain grows past our tip by more than
+    /// `max_headers_without_work` without having more work than ours has its
+    /// headers dropped. Returns whether the peer has more headers to send,
+    /// see `continuation_locator`.
+    pub fn on_headers(
+        &mut self,
+        peer: u64,
+        headers: Vec<BlockHeader>,
+        history: &History,
+    ) -> Result<bool, NetworkError> {
+        if headers.len() > self.config.max_headers_per_message {
+            return Err(NetworkError::TooManyHeaders {
+                count: headers.len(),
+                max: self.config.max_headers_per_message,
+            });
+        }
+        let first = match headers.first() {
+            Some(first) => first,
+            None => return Ok(false),
+        };
+
+        // Headers previously sent can only be extended while the block they
+        // fork from is still in our chain
+        let extends_known = self.peers.get(&peer).is_some_and(|chain| {
+            chain.headers.last().is_some_and(|(hash, _)| *hash == first.previous_hash)
+                && is_in_chain(history, chain.fork_height, &chain.headers[0].1.previous_hash)
+        });
+        let mut chain = match self.peers.remove(&peer) {
+            Some(chain) if extends_known => chain,
+            _ => {
+                let parent =
+                    history
+                        .get_block(&first.previous_hash)
+                        .ok_or_else(|| NetworkError::UnconnectedHeaders {
+                            previous_hash: first.previous_hash.clone(),
+                        })?;
+                PeerHeaders {
+                    fork_height: parent.height,
+                    headers: Vec::new(),
+                    hashes: HashSet::new(),
+                    work: 0,
+                }
+            }
+        };
+
+        let fork_work = history.chain_work_at_height(chain.fork_height).unwrap_or(0);
+        let max_height = history.get_height() as u64 - 1 + self.config.max_headers_without_work;
+        let has_more = headers.len() == self.config.max_headers_per_message;
+        for header in headers {
+            let hash = header.hash();
+            check_header(&header, &chain, history).map_err(|source| NetworkError::InvalidHeader {
+                hash: hash.clone(),
+                source,
+            })?;
+
+            chain.work += header.work();
+            if header.height > max_height && fork_work + chain.work <= history.chain_work() {
+                return Err(NetworkError::HeadersWithoutWork {
+                    height: header.height,
+                    max_height,
+                });
+            }
+            chain.hashes.insert(hash.clone());
+            chain.headers.push((hash, header));
+        }
+
+        self.peers.insert(peer, chain);
+        Ok(has_more)
+    }
+
+    /// Locator asking `peer` for the headers following the last one it sent.
+    pub fn continuation_locator(&self, peer: u64) -> Option<Vec<String>> {
+        let (hash, _) = self.peers.get(&peer)?.headers.last()?;
+        Some(vec![hash.clone()])
+    }
+
+    /// Peer whose header chain has the most work, if it has more than ours.
+    /// Ties go to the peer that connected first.
+    pub fn best_peer(&self, history: &History) -> Option<u64> {
+        let mut peer_ids: Vec<u64> = self.peers.keys().copied().collect();
+        peer_ids.sort_unstable();
+
+        let mut best = None;
+        let mut best_work = history.chain_work();
+        for peer in peer_ids {
+            let chain = &self.peers[&peer];
+            let work = history.chain_work_at_height(chain.fork_height).unwrap_or(0) + chain.work;
+            if work > best_work {
+                best = Some(peer);
+                best_work = work;
+            }
+        }
+        best
+    }
+
+    /// Next blocks of the best header chain to request from `peer`, among
+    /// the ones it announced. They are recorded as in flight from `now`.
+    pub fn blocks_to_request(&mut self, peer: u64, history: &History, now: Instant) -> Vec<String> {
+        let best = match self.best_peer(history) {
+            Some(best) => best,
+            None => return Vec::new(),
+        };
+        let peer_chain = match self.peers.get(&peer) {
+            Some(chain) => chain,
+            None => return Vec::new(),
+        };
+
+        let in_flight_from_peer = self
+            .in_flight
+            .values()
+            .filter(|(requested_from, _)| *requested_from == peer)
+            .count();
+        let budget = self
+            .config
+            .max_blocks_in_flight_per_peer
+            .saturating_sub(in_flight_from_peer);
+
+        let mut requested = Vec::new();
+        let missing = self.peers[&best]
+            .headers
+            .iter()
+            .filter(|(hash, header)| !is_in_chain(history, header.height, hash))
+            .take(self.config.download_window);
+        for (hash, _) in missing {
+            if requested.len() >= budget {
+                break;
+            }
+            if self.in_flight.contains_key(hash)
+                || self.downloaded.contains_key(hash)
+                || !peer_chain.hashes.contains(hash)
+            {
+                continue;
+            }
+            requested.push(hash.clone());
+        }
+
+        for hash in &requested {
+            self.in_flight.insert(hash.clone(), (peer, now));
+        }
+        requested
+    }
+
+    /// Records a block received from a peer. Returns `false` if the block
+    /// was not requested by the sync.
+    pub fn on_block(&mut self, block: &Block) -> bool {
+        if self.in_flight.remove(&block.hash).is_none() {
+            return false;
+        }
+
+        self.downloaded_count += 1;
+        self.downloaded.insert(block.hash.clone(), block.clone());
+        true
+    }
+
+    /// Takes the downloaded blocks that can be connected to our chain,
+    /// along with the peer whose header chain they belong to. A branch
+    /// forking below our tip is only returned once fully downloaded.
+    pub fn next_step(&mut self, history: &History) -> Option<(u64, SyncStep)> {
+        let best = self.best_peer(history)?;
+        let headers = &self.peers[&best].headers;
+
+        let start = headers
+            .iter()
+            .position(|(hash, header)| !is_in_chain(history, header.height, hash))?;
+        let ready = headers[start..]
+            .iter()
+            .take_while(|(hash, _)| self.downloaded.contains_key(hash))
+            .count();
+        if ready == 0 {
+            return None;
+        }
+
+        let fork_height = headers[start].1.height - 1;
+        let extends_tip = history.get_last_block().is_some_and(|tip| tip.height == fork_height);
+        if !extends_tip && start + ready < headers.len() {
+            return None;
+        }
+
+        let hashes: Vec<String> = headers[start..start + ready]
+            .iter()
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        let blocks: Vec<Block> = hashes.iter().filter_map(|hash| self.downloaded.remove(hash)).collect();
+
+        if extends_tip {
+            return Some((best, SyncStep::Extend(blocks)));
+        }
+
+        let mut chain = history.get_chain()[..=fork_height as usize].to_vec();
+        chain.extend(blocks);
+        Some((best, SyncStep::Reorg(chain)))
+    }
+
+    /// Forgets the headers of `peer`, for instance because one of its
+    /// blocks turned out invalid. Its requests stay in flight.
+    pub fn discard_headers(&mut self, peer: u64) {
+        self.peers.remove(&peer);
+
+        let peers = &self.peers;
+        self.downloaded
+            .retain(|hash, _| peers.values().any(|chain| chain.hashes.contains(hash)));
+    }
+
+    /// Forgets everything about a disconnected peer. The blocks it was
+    /// asked for can be requested from the other peers.
+    pub fn remove_peer(&mut self, peer: u64) {
+        self.discard_headers(peer);
+        self.in_flight.retain(|_, (requested_from, _)| *requested_from != peer);
+    }
+
+    /// Peers that did not deliver a block requested more than
+    /// `block_timeout` before `now`.
+    pub fn stalled_peers(&self, now: Instant) -> Vec<u64> {
+        let mut stalled: Vec<u64> = self
+            .in_flight
+            .values()
+            .filter(|(_, requested_at)| now.saturating_duration_since(*requested_at) > self.config.block_timeout)
+            .map(|(peer, _)| *peer)
+            .collect();
+        stalled.sort_unstable();
+        stalled.dedup();
+        stalled
+    }
+
+    pub fn status(&self, history: &History) -> SyncStatus {
+        SyncStatus {
+            best_header_height: self
+                .best_peer(history)
+                .and_then(|best| self.peers[&best].headers.last())
+                .map(|(_, header)| header.height),
+            blocks_in_flight: self.in_flight.len(),
+            blocks_downloaded: self.downloaded_count,
+        }
+    }
+}
+
+/// Hashes describing our chain to a peer: the last ten blocks, then
+/// exponentially sparser ones back to genesis.
+pub fn block_locator(history: &History) -> Vec<String> {
+    let chain = history.get_chain();
+    let mut locator = Vec::new();
+    let mut index = match chain.len().checked_sub(1) {
+        Some(index) => index,
+        None => return locator,
+    };
+
+    let mut step = 1;
+    loop {
+        locator.push(chain[index].hash.clone());
+        if index == 0 {
+            break;
+        }
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        index = index.saturating_sub(step);
+    }
+    locator
+}
+
+/// Headers following the first block of `locator` that is in our chain,
+/// starting after genesis if none is.
+pub fn headers_after(history: &History, locator: &[String], max: usize) -> Vec<BlockHeader> {
+    let start = locator
+        .iter()
+        .find_map(|hash| history.get_block(hash))
+        .map_or(1, |block| block.height as usize + 1);
+
+    history
+        .get_chain()
+        .iter()
+        .skip(start)
+        .take(max)
+        .map(Block::header)
+        .collect()
+}
+
+fn is_in_chain(history: &History, height: u64, hash: &str) -> bool {
+    usize::try_from(height)
+        .ok()
+        .and_then(|index| history.get_chain().get(index))
+        .is_some_and(|block| block.hash == hash)
+}
+
+/// Checks that `header` extends `chain` following the consensus rules that
+/// do not depend on the transactions of the block.
+fn check_header(header: &BlockHeader, chain: &PeerHeaders, history: &History) -> Result<(), BlockValidationError> {
+    let previous = match chain.headers.last() {
+        Some((hash, previous)) => (hash.as_str(), previous.height, previous.difficulty),
+        None => {
+            let fork = &history.get_chain()[chain.fork_height as usize];
+            (fork.hash.as_str(), fork.height, fork.difficulty)
+        }
+    };
+    let (previous_hash, previous_height, previous_difficulty) = previous;
+
+    if header.previous_hash != previous_hash {
+        return Err(BlockValidationError::BadParent {
+            expected: previous_hash.to_string(),
+            found: header.previous_hash.clone(),
+        });
+    }
+
+    if header.height != previous_height + 1 {
+        return Err(BlockValidationError::BadHeight {
+            expected: previous_height + 1,
+            found: header.height,
+        });
+    }
+
+    let params = history.get_params();
+    header.check_proof_of_work(params)?;
+
+    // Our chain up to the fork, followed by the headers of the peer
+    let timestamp_at = |height: u64| match height.checked_sub(chain.fork_height + 1) {
+        Some(offset) => chain.headers[offset as usize].1.timestamp,
+        None => history.get_chain()[height as usize].timestamp,
+    };
+
+    let expected_difficulty = params.next_difficulty(previous_height, previous_difficulty, timestamp_at);
+    if header.difficulty != expected_difficulty {
+        return Err(BlockValidationError::BadDifficulty {
+            expected: expected_difficulty,
+            found: header.difficulty,
+        });
+    }
+
+    let median_time_past = params.median_time_past(previous_height, timestamp_at);
+    if header.timestamp <= median_time_past {
+        return Err(BlockValidationError::TimestampTooOld {
+            timestamp: header.timestamp,
+            median_time_past,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod sync_test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        core::{
+            mine_new_block,
+            test_utils::{mine_on_top, mine_on_top_at, test_params},
+            Block, BlockValidationError, ConsensusParams, History, NaiveReorgStrategy,
+        },
+        network::NetworkError,
+    };
+
+    use super::{block_locator, headers_after, ChainSync, SyncConfig, SyncStep};
+
+    fn history_with_blocks(count: usize) -> History {
+        let mut hs = History::new(test_params(), Box::new(NaiveReorgStrategy {}));
+        for _ in 0..count {
+            let block = mine_on_top(&hs, Vec::new());
+            assert!(hs.try_to_append(block).is_ok());
+        }
+        hs
+    }
+
+    #[test]
+    fn locator_is_dense_near_the_tip_and_ends_at_genesis() {
+        let hs = history_with_blocks(30);
+
+        let locator = block_locator(&hs);
+
+        assert_eq!(14, locator.len());
+        assert_eq!(hs.get_chain()[30].hash, locator[0]);
+        assert_eq!(hs.get_chain()[21].hash, locator[9]);
+        assert_eq!(hs.get_chain()[0].hash, locator[13]);
+    }
+
+    #[test]
+    fn headers_not_building_on_our_chain_or_without_work_are_rejected() {
+        let source = history_with_blocks(3);
+        let local = history_with_blocks(0);
+        let mut sync = ChainSync::new(SyncConfig::default());
+
+        let headers = headers_after(&source, &[source.get_chain()[1].hash.clone()], 10);
+        let res = sync.on_headers(1, headers, &local);
+        assert!(matches!(res, Err(NetworkError::UnconnectedHeaders { .. })));
+
+        let mut headers = headers_after(&source, &block_locator(&local), 10);
+        headers[2].difficulty = 64;
+        let res = sync.on_headers(1, headers, &local);
+        assert!(matches!(
+            res,
+            Err(NetworkError::InvalidHeader {
+                source: BlockValidationError::InsufficientWork { required: 64, .. },
+                ..
+            })
+        ));
+        assert_eq!(None, sync.best_peer(&local));
+    }
+
+    #[test]
+    fn headers_with_an_unexpected_difficulty_or_timestamp_are_rejected() {
+        let source = history_with_blocks(3);
+        let local = history_with_blocks(0);
+        let mut sync = ChainSync::new(SyncConfig::default());
+        let tip = source.get_last_block().unwrap();
+
+        let timestamp = source.median_time_past() + 1;
+        let (nonce, hash) = mine_new_block(tip.height + 1, timestamp, &tip.hash, &[], 2);
+        let harder = Block::new(tip, hash, timestamp, Vec::new(), 2, nonce);
+        let mut headers = headers_after(&source, &block_locator(&local), 10);
+        headers.push(harder.header());
+        let res = sync.on_headers(1, headers, &local);
+        assert!(matches!(
+            res,
+            Err(NetworkError::InvalidHeader {
+                source: BlockValidationError::BadDifficulty { expected: 1, found: 2 },
+                ..
+            })
+        ));
+
+        let too_old = mine_on_top_at(&source, source.median_time_past(), Vec::new());
+        let mut headers = headers_after(&source, &block_locator(&local), 10);
+        headers.push(too_old.header());
+        let res = sync.on_headers(1, headers, &local);
+        assert!(matches!(
+            res,
+            Err(NetworkError::InvalidHeader {
+                source: BlockValidationError::TimestampTooOld { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn long_header_chains_without_more_work_are_dropped() {
+        let params = ConsensusParams {
+            retarget_interval: 2,
+            ..test_params()
+        };
+        // Blocks mined faster than the target raise the difficulty, slower
+        // ones keep it at the minimum
+        let mut local = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        for _ in 0..6 {
+            let block = mine_on_top(&local, Vec::new());
+            assert!(local.try_to_append(block).is_ok());
+        }
+        let mut source = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+        for _ in 0..10 {
+            let timestamp = source.get_last_block().unwrap().timestamp + 10 * params.target_block_time;
+            let block = mine_on_top_at(&source, timestamp, Vec::new());
+            assert!(source.try_to_append(block).is_ok());
+        }
+        assert!(source.chain_work() < local.chain_work());
+
+        let mut sync = ChainSync::new(SyncConfig {
+            max_headers_without_work: 2,
+            ..SyncConfig::default()
+        });
+        let headers = headers_after(&source, &[source.get_chain()[0].hash.clone()], 10);
+        assert!(sync.on_headers(1, headers[..8].to_vec(), &local).is_ok());
+
+        let res = sync.on_headers(1, headers[8..].to_vec(), &local);
+        assert!(matches!(
+            res,
+            Err(NetworkError::HeadersWithoutWork {
+                height: 9,
+                max_height: 8
+            })
+        ));
+        assert_eq!(None, sync.continuation_locator(1));
+    }
+
+    #[test]
+    fn blocks_of_the_chain_with_most_work_are_spread_over_peers() {
+        let source = history_with_blocks(6);
+        let local = history_with_blocks(0);
+        let mut sync = ChainSync::new(SyncConfig {
+            max_blocks_in_flight_per_peer: 2,
+            ..SyncConfig::default()
+        });
+
+        let locator = block_locator(&local);
+        assert!(matches!(
+            sync.on_headers(1, headers_after(&source, &locator, 3), &local),
+            Ok(false)
+        ));
+        assert!(matches!(
+            sync.on_headers(2, headers_after(&source, &locator, 10), &local),
+            Ok(false)
+        ));
+        assert_eq!(Some(2), sync.best_peer(&local));
+
+        let now = Instant::now();
+        let from_first = sync.blocks_to_request(1, &local, now);
+        let from_second = sync.blocks_to_request(2, &local, now);
+        let chain = source.get_chain();
+
+        assert_eq!(vec![chain[1].hash.clone(), chain[2].hash.clone()], from_first);
+        assert_eq!(vec![chain[3].hash.clone(), chain[4].hash.clone()], from_second);
+        assert!(sync.blocks_to_request(1, &local, now).is_empty());
+    }
+
+    #[test]
+    fn blocks_received_out_of_order_connect_in_order() {
+        let source = history_with_blocks(3);
+        let mut local = history_with_blocks(0);
+        let mut sync = ChainSync::new(SyncConfig::default());
+        let headers = headers_after(&source, &block_locator(&local), 10);
+        assert!(sync.on_headers(1, headers, &local).is_ok());
+        assert_eq!(3, sync.blocks_to_request(1, &local, Instant::now()).len());
+
+        let chain = source.get_chain();
+        assert!(sync.on_block(&chain[3]));
+        assert!(sync.on_block(&chain[2]));
+        assert!(sync.next_step(&local).is_none());
+
+        assert!(sync.on_block(&chain[1]));
+        let blocks = match sync.next_step(&local) {
+            Some((1, SyncStep::Extend(blocks))) => blocks,
+            other => panic!("unexpected step {:?}", other),
+        };
+        for block in blocks {
+            assert!(local.try_to_append(block).is_ok());
+        }
+
+        assert_eq!(chain[3].hash, local.get_last_block().unwrap().hash);
+        assert_eq!(None, sync.status(&local).best_header_height);
+    }
+
+    #[test]
+    fn requests_of_a_stalled_peer_go_to_the_other_peers() {
+        let source = history_with_blocks(2);
+        let local = history_with_blocks(0);
+        let mut sync = ChainSync::new(SyncConfig {
+            block_timeout: Duration::from_secs(5),
+            ..SyncConfig::default()
+        });
+        let locator = block_locator(&local);
+        assert!(sync.on_headers(1, headers_after(&source, &locator, 10), &local).is_ok());
+        assert!(sync.on_headers(2, headers_after(&source, &locator, 10), &local).is_ok());
+
+        let start = Instant::now();
+        assert_eq!(2, sync.blocks_to_request(1, &local, start).len());
+        assert!(sync.blocks_to_request(2, &local, start).is_empty());
+        assert!(sync.stalled_peers(start + Duration::from_secs(1)).is_empty());
+
+        assert_eq!(vec![1], sync.stalled_peers(start + Duration::from_secs(6)));
+        sync.remove_peer(1);
+
+        assert_eq!(2, sync.blocks_to_request(2, &local, start).len());
+    }
+}
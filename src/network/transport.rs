@@ -0,0 +1,303 @@
+use std::{
+    fmt,
+    io::{ErrorKind, Read, Write},
+};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use secp256k1::{ecdh::SharedSecret, ecdsa::Signature, rand::rngs::OsRng, Message, PublicKey, SecretKey, SECP256K1};
+use sha2::{Digest, Sha256};
+
+use super::{
+    framing::{read_frame, write_frame},
+    NetworkError,
+};
+
+/// Size of the authentication tag added to every encrypted frame.
+pub const TAG_SIZE: usize = 16;
+
+/// Mixed into every derived key, so that they cannot be reused by another
+/// protocol.
+const PROTOCOL_NAME: &[u8] = b"rust-chain/transport/1";
+const PUBLIC_KEY_SIZE: usize = 33;
+const PROOF_SIZE: usize = PUBLIC_KEY_SIZE + 64;
+
+/// Long term secp256k1 key pair identifying a node. Peers can pin the
+/// public key to make sure they talk to the node they expect.
+#[derive(Clone)]
+pub struct NodeIdentity {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+}
+
+impl NodeIdentity {
+    pub fn generate() -> NodeIdentity {
+        NodeIdentity::from_secret_key(SecretKey::new(&mut OsRng))
+    }
+
+    pub fn from_secret_key(secret_key: SecretKey) -> NodeIdentity {
+        NodeIdentity {
+            public_key: PublicKey::from_secret_key(SECP256K1, &secret_key),
+            secret_key,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+}
+
+/// Only shows the public key, so that the secret one does not end up in logs.
+impl fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeIdentity")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts one direction of a session with ChaCha20-Poly1305. The nonce
+/// is the number of frames already sent, so frames cannot be replayed,
+/// dropped or reordered without failing to decrypt.
+pub struct SessionCipher {
+    cipher: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl SessionCipher {
+    pub fn new(key: [u8; 32]) -> SessionCipher {
+        SessionCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            nonce: 0,
+        }
+    }
+
+    /// Encrypts `plaintext`, also authenticating `aad` which is sent in clear.
+    pub fn encrypt(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .expect("Encryption only fails on plaintexts larger than 256 GiB")
+    }
+
+    pub fn decrypt(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, NetworkError> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(&nonce, Payload { msg: ciphertext, aad })
+            .map_err(|_| NetworkError::DecryptionFailed)
+    }
+
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        *Nonce::from_slice(&nonce)
+    }
+}
+
+/// Ciphers of an encrypted session and the node key of the peer.
+pub struct Session {
+    pub send: SessionCipher,
+    pub receive: SessionCipher,
+    pub remote_key: PublicKey,
+}
+
+/// Agrees on session keys with the peer and authenticates both sides. Each
+/// side sends an ephemeral public key, and the ECDH secret of the two keys
+/// derives one key per direction. Then each side proves, encrypted, that it
+/// owns its node key by signing the ephemeral keys. The ephemeral keys give
+/// forward secrecy: recorded traffic stays private even if node keys leak.
+///
+/// A peer whose node key is not `expected_key` is rejected.
+pub fn handshake<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    identity: &NodeIdentity,
+    expected_key: Option<&PublicKey>,
+) -> Result<Session, NetworkError> {
+    let ephemeral_secret = SecretKey::new(&mut OsRng);
+    let local_ephemeral = PublicKey::from_secret_key(SECP256K1, &ephemeral_secret).serialize();
+    writer.write_all(&local_ephemeral)?;
+    writer.flush()?;
+
+    let mut remote_ephemeral = [0u8; PUBLIC_KEY_SIZE];
+    if let Err(err) = reader.read_exact(&mut remote_ephemeral) {
+        return Err(match err.kind() {
+            ErrorKind::UnexpectedEof => NetworkError::ConnectionClosed,
+            _ => NetworkError::Io(err),
+        });
+    }
+    // A peer sending our own key back would make both directions share a key
+    if remote_ephemeral == local_ephemeral {
+        return Err(NetworkError::AuthenticationFailed);
+    }
+    let remote_ephemeral_key =
+        PublicKey::from_slice(&remote_ephemeral).map_err(|_| NetworkError::AuthenticationFailed)?;
+    let shared_secret = SharedSecret::new(&remote_ephemeral_key, &ephemeral_secret);
+
+    // Both sides order the keys the same way to derive the same transcript
+    let local_first = local_ephemeral < remote_ephemeral;
+    let (first, second) = if local_first {
+        (&local_ephemeral, &remote_ephemeral)
+    } else {
+        (&remote_ephemeral, &local_ephemeral)
+    };
+    let transcript: [u8; 32] = Sha256::new()
+        .chain_update(PROTOCOL_NAME)
+        .chain_update(first)
+        .chain_update(second)
+        .finalize()
+        .into();
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&transcript), shared_secret.as_ref());
+    let mut first_key = [0u8; 32];
+    let mut second_key = [0u8; 32];
+    hkdf.expand(b"first to second", &mut first_key)
+        .expect("32 bytes is a valid HKDF output length");
+    hkdf.expand(b"second to first", &mut second_key)
+        .expect("32 bytes is a valid HKDF output length");
+    let (mut send, mut receive) = if local_first {
+        (SessionCipher::new(first_key), SessionCipher::new(second_key))
+    } else {
+        (SessionCipher::new(second_key), SessionCipher::new(first_key))
+    };
+
+    let signature = SECP256K1.sign_ecdsa(&proof_message(&transcript, &local_ephemeral), &identity.secret_key);
+    let proof = [
+        identity.public_key.serialize().as_slice(),
+        signature.serialize_compact().as_slice(),
+    ]
+    .concat();
+    write_frame(writer, &proof, Some(&mut send))?;
+
+    let remote_proof = read_frame(reader, PROOF_SIZE, Some(&mut receive))?;
+    if remote_proof.len() != PROOF_SIZE {
+        return Err(NetworkError::AuthenticationFailed);
+    }
+    let remote_key =
+        PublicKey::from_slice(&remote_proof[..PUBLIC_KEY_SIZE]).map_err(|_| NetworkError::AuthenticationFailed)?;
+    let remote_signature =
+        Signature::from_compact(&remote_proof[PUBLIC_KEY_SIZE..]).map_err(|_| NetworkError::AuthenticationFailed)?;
+    SECP256K1
+        .verify_ecdsa(
+            &proof_message(&transcript, &remote_ephemeral),
+            &remote_signature,
+            &remote_key,
+        )
+        .map_err(|_| NetworkError::AuthenticationFailed)?;
+
+    if let Some(expected_key) = expected_key.filter(|expected_key| **expected_key != remote_key) {
+        return Err(NetworkError::UnexpectedPeerKey {
+            expected: expected_key.to_string(),
+            found: remote_key.to_string(),
+        });
+    }
+
+    Ok(Session {
+        send,
+        receive,
+        remote_key,
+    })
+}
+
+/// What a side signs to prove it owns its node key: the transcript and its
+/// own ephemeral key, so that a signature cannot be sent back by the peer.
+fn proof_message(transcript: &[u8; 32], ephemeral: &[u8]) -> Message {
+    let digest: [u8; 32] = Sha256::new()
+        .chain_update(transcript)
+        .chain_update(ephemeral)
+        .finalize()
+        .into();
+    Message::from_digest(digest)
+}
+
+#[cfg(test)]
+mod transport_test {
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use crate::network::NetworkError;
+
+    use super::{handshake, NodeIdentity, Session, SessionCipher};
+
+    /// Runs the handshake between two connected streams.
+    fn handshake_pair(
+        first: &NodeIdentity,
+        second: NodeIdentity,
+        expected_by_second: Option<NodeIdentity>,
+    ) -> (Result<Session, NetworkError>, Result<Session, NetworkError>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepting = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let expected_key = expected_by_second.map(|identity| identity.public_key());
+            handshake(&mut &stream, &mut &stream, &second, expected_key.as_ref())
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let res = handshake(&mut &stream, &mut &stream, first, None);
+        (res, accepting.join().unwrap())
+    }
+
+    #[test]
+    fn both_sides_agree_on_keys_and_learn_the_node_key_of_the_other() {
+        let first = NodeIdentity::generate();
+        let second = NodeIdentity::generate();
+
+        let (first_session, second_session) = handshake_pair(&first, second.clone(), Some(first.clone()));
+        let mut first_session = first_session.unwrap();
+        let mut second_session = second_session.unwrap();
+
+        assert_eq!(second.public_key(), first_session.remote_key);
+        assert_eq!(first.public_key(), second_session.remote_key);
+        let ciphertext = first_session.send.encrypt(b"aad", b"hello");
+        assert_eq!(
+            b"hello".to_vec(),
+            second_session.receive.decrypt(b"aad", &ciphertext).unwrap()
+        );
+        let ciphertext = second_session.send.encrypt(b"aad", b"world");
+        assert_eq!(
+            b"world".to_vec(),
+            first_session.receive.decrypt(b"aad", &ciphertext).unwrap()
+        );
+    }
+
+    #[test]
+    fn peer_with_another_key_than_the_pinned_one_is_rejected() {
+        let first = NodeIdentity::generate();
+
+        let (_, second_session) = handshake_pair(&first, NodeIdentity::generate(), Some(NodeIdentity::generate()));
+
+        assert!(matches!(
+            second_session,
+            Err(NetworkError::UnexpectedPeerKey { found, .. }) if found == first.public_key().to_string()
+        ));
+    }
+
+    #[test]
+    fn tampered_or_replayed_frames_do_not_decrypt() {
+        let mut sender = SessionCipher::new([7u8; 32]);
+        let first = sender.encrypt(b"length", b"first");
+        let mut second = sender.encrypt(b"length", b"second");
+
+        let mut replaying = SessionCipher::new([7u8; 32]);
+        assert!(replaying.decrypt(b"length", &first).is_ok());
+        assert!(matches!(
+            replaying.decrypt(b"length", &first),
+            Err(NetworkError::DecryptionFailed)
+        ));
+
+        let mut tampering = SessionCipher::new([7u8; 32]);
+        assert!(tampering.decrypt(b"length", &first).is_ok());
+        second[0] ^= 1;
+        assert!(matches!(
+            tampering.decrypt(b"length", &second),
+            Err(NetworkError::DecryptionFailed)
+        ));
+    }
+}
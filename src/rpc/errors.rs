@@ -0,0 +1,101 @@
+use std::{error::Error, fmt, net::SocketAddr};
+
+use crate::core::{AppendToHistoryError, MemPoolError};
+
+use super::{
+    BLOCK_REJECTED, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, NOT_FOUND, PARSE_ERROR,
+    TX_REJECTED,
+};
+
+#[derive(Debug)]
+pub enum RpcError {
+    Io(std::io::Error),
+    NonLocalAddress { addr: SocketAddr },
+    MalformedHttpRequest(String),
+    RequestTooLarge { size: usize, max: usize },
+    MalformedFrame(String),
+    ConnectionClosed,
+    Parse(serde_json::Error),
+    InvalidRequest(String),
+    MethodNotFound { method: String },
+    InvalidParams(String),
+    NotFound(String),
+    TxRejected(MemPoolError),
+    BlockRejected(AppendToHistoryError),
+}
+
+impl RpcError {
+    /// JSON-RPC error code sent to the client.
+    pub fn code(&self) -> i64 {
+        match self {
+            RpcError::Parse(_) => PARSE_ERROR,
+            RpcError::InvalidRequest(_) => INVALID_REQUEST,
+            RpcError::MethodNotFound { .. } => METHOD_NOT_FOUND,
+            RpcError::InvalidParams(_) => INVALID_PARAMS,
+            RpcError::NotFound(_) => NOT_FOUND,
+            RpcError::TxRejected(_) => TX_REJECTED,
+            RpcError::BlockRejected(_) => BLOCK_REJECTED,
+            _ => INTERNAL_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Io(err) => write!(f, "RPC server error: {}", err),
+            RpcError::NonLocalAddress { addr } => {
+                write!(f, "RPC server can only listen on localhost, not on {}", addr)
+            }
+            RpcError::MalformedHttpRequest(reason) => write!(f, "Malformed HTTP request: {}", reason),
+            RpcError::RequestTooLarge { size, max } => {
+                write!(f, "Request of {} bytes is larger than the maximum of {}", size, max)
+            }
+            RpcError::MalformedFrame(reason) => write!(f, "Malformed WebSocket frame: {}", reason),
+            RpcError::ConnectionClosed => write!(f, "Connection closed by the client"),
+            RpcError::Parse(err) => write!(f, "Cannot parse request: {}", err),
+            RpcError::InvalidRequest(reason) => write!(f, "Invalid request: {}", reason),
+            RpcError::MethodNotFound { method } => write!(f, "Method {} not found", method),
+            RpcError::InvalidParams(reason) => write!(f, "Invalid params: {}", reason),
+            RpcError::NotFound(what) => write!(f, "{} not found", what),
+            RpcError::TxRejected(err) => write!(f, "{}", err),
+            RpcError::BlockRejected(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RpcError::Io(err) => Some(err),
+            RpcError::Parse(err) => Some(err),
+            RpcError::TxRejected(err) => Some(err),
+            RpcError::BlockRejected(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RpcError {
+    fn from(err: std::io::Error) -> RpcError {
+        RpcError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> RpcError {
+        RpcError::Parse(err)
+    }
+}
+
+impl From<MemPoolError> for RpcError {
+    fn from(err: MemPoolError) -> RpcError {
+        RpcError::TxRejected(err)
+    }
+}
+
+impl From<AppendToHistoryError> for RpcError {
+    fn from(err: AppendToHistoryError) -> RpcError {
+        RpcError::BlockRejected(err)
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{BufRead, Read, Write};
+
+use super::RpcError;
+
+/// Longest request line or header line accepted, in bytes.
+const MAX_LINE_SIZE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// The parts of an HTTP/1.1 request the RPC server looks at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Value of the header `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads a request with a body of at most `max_body_size` bytes, given by
+/// its `Content-Length`. Chunked bodies are not supported.
+pub fn read_request<R: BufRead>(reader: &mut R, max_body_size: usize) -> Result<HttpRequest, RpcError> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => (method, path),
+        _ => {
+            return Err(RpcError::MalformedHttpRequest(format!(
+                "bad request line {:?}",
+                request_line
+            )))
+        }
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(RpcError::MalformedHttpRequest("too many headers".to_string()));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| RpcError::MalformedHttpRequest(format!("bad header {:?}", line)))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    if request.header("transfer-encoding").is_some() {
+        return Err(RpcError::MalformedHttpRequest(
+            "chunked bodies are not supported".to_string(),
+        ));
+    }
+    let size = match request.header("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| RpcError::MalformedHttpRequest(format!("bad content length {:?}", length)))?,
+        None => 0,
+    };
+    if size > max_body_size {
+        return Err(RpcError::RequestTooLarge {
+            size,
+            max: max_body_size,
+        });
+    }
+
+    request.body = vec![0u8; size];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Writes a response closing the connection, the server handles a single
+/// request per connection.
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), RpcError> {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status));
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+
+    writer.write_all(response.as_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a line without its line ending, refusing lines longer than
+/// `MAX_LINE_SIZE`.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, RpcError> {
+    let mut line = Vec::new();
+    reader.take(MAX_LINE_SIZE as u64 + 1).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        let reason = if line.len() > MAX_LINE_SIZE {
+            "line too long"
+        } else {
+            "connection closed in the middle of the request"
+        };
+        return Err(RpcError::MalformedHttpRequest(reason.to_string()));
+    }
+
+    let line = String::from_utf8(line).map_err(|_| RpcError::MalformedHttpRequest("not UTF-8".to_string()))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod http_test {
+    use std::io::BufReader;
+
+    use crate::rpc::RpcError;
+
+    use super::{read_request, write_response};
+
+    #[test]
+    fn request_is_read_up_to_its_content_length() {
+        let raw =
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer abc\r\nContent-Length: 4\r\n\r\nbodyextra";
+
+        let request = read_request(&mut BufReader::new(&raw[..]), 100).unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/", request.path);
+        assert_eq!(Some("Bearer abc"), request.header("AUTHORIZATION"));
+        assert_eq!(b"body".to_vec(), request.body);
+    }
+
+    #[test]
+    fn oversized_or_malformed_requests_are_rejected() {
+        let too_large = b"POST / HTTP/1.1\r\nContent-Length: 101\r\n\r\n";
+        let no_version = b"POST /\r\n\r\n";
+        let truncated = b"POST / HTTP/1.1\r\nContent-";
+
+        assert!(matches!(
+            read_request(&mut BufReader::new(&too_large[..]), 100),
+            Err(RpcError::RequestTooLarge { size: 101, max: 100 })
+        ));
+        assert!(matches!(
+            read_request(&mut BufReader::new(&no_version[..]), 100),
+            Err(RpcError::MalformedHttpRequest(_))
+        ));
+        assert!(matches!(
+            read_request(&mut BufReader::new(&truncated[..]), 100),
+            Err(RpcError::MalformedHttpRequest(_))
+        ));
+    }
+
+    #[test]
+    fn response_has_a_content_length() {
+        let mut response = Vec::new();
+
+        write_response(&mut response, 200, &[("Content-Type", "application/json")], b"{}").unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}",
+            String::from_utf8(response).unwrap()
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::RpcError;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Error codes defined by JSON-RPC 2.0.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Error codes of the methods, the same as the ones of Bitcoin Core.
+pub const NOT_FOUND: i64 = -5;
+pub const BLOCK_REJECTED: i64 = -25;
+pub const TX_REJECTED: i64 = -26;
+
+/// A call to `method`. Requests without an `id` are notifications, which
+/// get no response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcRequest {
+    pub method: String,
+    /// Positional parameters as an array, or named ones as an object.
+    pub params: Value,
+    pub id: Option<Value>,
+}
+
+impl RpcRequest {
+    /// Reads a request object. An `id` set to `null` is kept, unlike a
+    /// missing one.
+    pub fn from_value(value: &Value) -> Result<RpcRequest, RpcError> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| RpcError::InvalidRequest("request is not an object".to_string()))?;
+
+        if object.get("jsonrpc").and_then(Value::as_str) != Some(JSONRPC_VERSION) {
+            return Err(RpcError::InvalidRequest(format!(
+                "jsonrpc must be \"{}\"",
+                JSONRPC_VERSION
+            )));
+        }
+        let method = object
+            .get("method")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::InvalidRequest("method must be a string".to_string()))?;
+        let params = object.get("params").cloned().unwrap_or(Value::Null);
+        if !matches!(params, Value::Null | Value::Array(_) | Value::Object(_)) {
+            return Err(RpcError::InvalidRequest(
+                "params must be an array or an object".to_string(),
+            ));
+        }
+        let id = object.get("id").cloned();
+        if !matches!(id, None | Some(Value::Null | Value::Number(_) | Value::String(_))) {
+            return Err(RpcError::InvalidRequest("id must be a number or a string".to_string()));
+        }
+
+        Ok(RpcRequest {
+            method: method.to_string(),
+            params,
+            id,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+impl From<&RpcError> for ErrorObject {
+    fn from(err: &RpcError) -> ErrorObject {
+        ErrorObject {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Answer to a request, holding either a `result` or an `error`. The `id`
+/// is the one of the request, `null` if it could not be read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorObject>,
+    pub id: Value,
+}
+
+impl RpcResponse {
+    pub fn new(id: Value, res: Result<Value, RpcError>) -> RpcResponse {
+        let (result, error) = match res {
+            Ok(result) => (Some(result), None),
+            Err(err) => (None, Some(ErrorObject::from(&err))),
+        };
+
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            result,
+            error,
+            id,
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    core::{AppendToHistoryError, Block, MemPoolError, Node, Transaction},
+    network::P2pNode,
+};
+
+use super::RpcError;
+
+/// What the RPC methods run against: a bare `Node`, or a `P2pNode` which
+/// also announces the submitted blocks and transactions to its peers.
+pub trait RpcBackend: Send + Sync {
+    fn with_node<R, F: FnOnce(&mut Node) -> R>(&self, f: F) -> R;
+    fn submit_tx(&self, tx: Transaction) -> Result<(), MemPoolError>;
+    fn submit_block(&self, block: Block) -> Result<bool, AppendToHistoryError>;
+}
+
+impl RpcBackend for Mutex<Node> {
+    fn with_node<R, F: FnOnce(&mut Node) -> R>(&self, f: F) -> R {
+        f(&mut self.lock().unwrap())
+    }
+
+    fn submit_tx(&self, tx: Transaction) -> Result<(), MemPoolError> {
+        self.lock().unwrap().submit_tx(tx)
+    }
+
+    fn submit_block(&self, block: Block) -> Result<bool, AppendToHistoryError> {
+        self.lock().unwrap().submit_block(block)
+    }
+}
+
+impl RpcBackend for P2pNode {
+    fn with_node<R, F: FnOnce(&mut Node) -> R>(&self, f: F) -> R {
+        P2pNode::with_node(self, f)
+    }
+
+    fn submit_tx(&self, tx: Transaction) -> Result<(), MemPoolError> {
+        P2pNode::submit_tx(self, tx)
+    }
+
+    fn submit_block(&self, block: Block) -> Result<bool, AppendToHistoryError> {
+        P2pNode::submit_block(self, block)
+    }
+}
+
+/// Runs the RPC method `method` with `params`, given by position or by name.
+pub fn call<B: RpcBackend>(backend: &B, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "getblockcount" => Ok(backend.with_node(|node| json!(tip(node).height))),
+        "getbestblockhash" => Ok(backend.with_node(|node| json!(tip(node).hash))),
+        "getblockhash" => {
+            let height: u64 = param(params, 0, "height")?;
+            backend.with_node(|node| {
+                let block = usize::try_from(height)
+                    .ok()
+                    .and_then(|index| node.get_history().get_chain().get(index));
+                match block {
+                    Some(block) => Ok(json!(block.hash)),
+                    None => Err(RpcError::NotFound(format!("Block at height {}", height))),
+                }
+            })
+        }
+        "getblock" => {
+            let hash: String = param(params, 0, "hash")?;
+            backend.with_node(|node| match node.get_history().get_block(&hash) {
+                Some(block) => Ok(serde_json::to_value(block)?),
+                None => Err(RpcError::NotFound(format!("Block {}", hash))),
+            })
+        }
+        "getbalance" => {
+            let address: String = param(params, 0, "address")?;
+            Ok(backend.with_node(|node| json!(node.get_history().get_balance(&address))))
+        }
+        "getmempoolinfo" => {
+            let stats = backend.with_node(|node| node.get_mempool().stats());
+            Ok(json!({
+                "tx_count": stats.tx_count,
+                "sender_count": stats.sender_count,
+                "total_bytes": stats.total_bytes,
+                "max_bytes": stats.max_bytes,
+                "total_fees": stats.total_fees,
+                "min_fee_per_kb": stats.min_fee_per_kb,
+            }))
+        }
+        "sendrawtransaction" => {
+            let tx: Transaction = param(params, 0, "tx")?;
+            let id = tx.id.clone();
+            backend.submit_tx(tx)?;
+            Ok(json!(id))
+        }
+        "submitblock" => {
+            let block: Block = param(params, 0, "block")?;
+            backend.submit_block(block)?;
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::MethodNotFound {
+            method: method.to_string(),
+        }),
+    }
+}
+
+fn tip(node: &Node) -> &Block {
+    node.get_history()
+        .get_last_block()
+        .expect("The chain always has its genesis block")
+}
+
+/// Parameter at `index` if `params` is an array, or named `name` if it is
+/// an object.
+pub(super) fn param<T: DeserializeOwned>(params: &Value, index: usize, name: &str) -> Result<T, RpcError> {
+    let value = match params {
+        Value::Array(values) => values.get(index),
+        Value::Object(values) => values.get(name),
+        _ => None,
+    };
+    let value = value.ok_or_else(|| RpcError::InvalidParams(format!("missing parameter {}", name)))?;
+    serde_json::from_value(value.clone())
+        .map_err(|err| RpcError::InvalidParams(format!("invalid parameter {}: {}", name, err)))
+}
+
+#[cfg(test)]
+mod methods_test {
+    use std::sync::Mutex;
+
+    use serde_json::{json, Value};
+
+    use crate::{
+        core::{
+            test_utils::{mine_on_top, test_params},
+            Block, ConsensusParams, History, MemPool, NaiveReorgStrategy, Node, Transaction,
+        },
+        rpc::RpcError,
+    };
+
+    use super::call;
+
+    fn backend(params: &ConsensusParams) -> Mutex<Node> {
+        Mutex::new(Node::new(
+            History::new(params.clone(), Box::new(NaiveReorgStrategy {})),
+            MemPool::new(1_000_000, params.clone()),
+        ))
+    }
+
+    fn reward_block(node: &Mutex<Node>, params: &ConsensusParams, address: &str) -> Block {
+        let reward = Transaction::coinbase(address.to_string(), params.block_reward(1), 1, params.chain_id);
+        mine_on_top(node.lock().unwrap().get_history(), vec![reward])
+    }
+
+    #[test]
+    fn submitted_block_becomes_the_best_block() {
+        let params = test_params();
+        let node = backend(&params);
+        let block = reward_block(&node, &params, "miner");
+
+        assert_eq!(Value::Null, call(&node, "submitblock", &json!([block])).unwrap());
+
+        assert_eq!(json!(1), call(&node, "getblockcount", &Value::Null).unwrap());
+        assert_eq!(
+            json!(block.hash),
+            call(&node, "getbestblockhash", &Value::Null).unwrap()
+        );
+        assert_eq!(
+            json!(block.hash),
+            call(&node, "getblockhash", &json!({ "height": 1 })).unwrap()
+        );
+        assert_eq!(
+            json!(params.block_reward(1)),
+            call(&node, "getbalance", &json!(["miner"])).unwrap()
+        );
+        let fetched: Block = serde_json::from_value(call(&node, "getblock", &json!([block.hash])).unwrap()).unwrap();
+        assert_eq!(block.merkle_root, fetched.merkle_root);
+    }
+
+    #[test]
+    fn bad_calls_are_reported_with_their_error() {
+        let params = test_params();
+        let node = backend(&params);
+
+        assert!(matches!(
+            call(&node, "getblock", &json!(["unknown"])),
+            Err(RpcError::NotFound(_))
+        ));
+        assert!(matches!(
+            call(&node, "getblock", &json!([])),
+            Err(RpcError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            call(&node, "getblockhash", &json!(["one"])),
+            Err(RpcError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            call(&node, "stop", &Value::Null),
+            Err(RpcError::MethodNotFound { .. })
+        ));
+        assert!(matches!(
+            call(&node, "submitblock", &json!([Block::genesis(&params)])),
+            Err(RpcError::BlockRejected(_))
+        ));
+    }
+}
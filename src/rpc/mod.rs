@@ -0,0 +1,26 @@
+mod errors;
+mod http;
+mod message;
+mod methods;
+mod server;
+mod subscriptions;
+mod websocket;
+
+pub type RpcError = errors::RpcError;
+pub type HttpRequest = http::HttpRequest;
+pub type RpcRequest = message::RpcRequest;
+pub type RpcResponse = message::RpcResponse;
+pub type ErrorObject = message::ErrorObject;
+pub type RpcConfig = server::RpcConfig;
+pub type RpcServer = server::RpcServer;
+pub type Topic = subscriptions::Topic;
+pub type Frame = websocket::Frame;
+pub type FrameReader = websocket::FrameReader;
+
+pub use http::{read_request, write_response};
+pub use message::{
+    BLOCK_REJECTED, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, JSONRPC_VERSION, METHOD_NOT_FOUND, NOT_FOUND,
+    PARSE_ERROR, TX_REJECTED,
+};
+pub use methods::{call, RpcBackend};
+pub use websocket::{accept_key, accept_upgrade, is_upgrade, write_frame};
@@ -0,0 +1,305 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use super::{
+    accept_upgrade, call, is_upgrade, read_request, subscriptions::serve_subscriptions, write_response, RpcBackend,
+    RpcError, RpcRequest, RpcResponse,
+};
+
+#[derive(Debug, Clone)]
+pub struct RpcConfig {
+    /// Clients must send it in an `Authorization: Bearer <token>` header.
+    /// Random by default, see `cookie_path` to hand it to local tools.
+    pub auth_token: String,
+    /// File the token is written to at start and removed from at shutdown.
+    pub cookie_path: Option<PathBuf>,
+    /// Largest request body accepted, in bytes.
+    pub max_request_size: usize,
+    /// How long a client has to send its whole request.
+    pub request_timeout: Duration,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            auth_token: hex::encode(rand::random::<[u8; 32]>()),
+            cookie_path: None,
+            max_request_size: 4 * 1024 * 1024,
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// JSON-RPC 2.0 server over HTTP, listening on localhost only. Every
+/// connection is served by its own thread and carries a single request,
+/// which may be a batch. Requests must be `POST`ed with the auth token.
+///
+/// A `GET` with the auth token can also switch the connection to WebSocket,
+/// to send any number of requests and subscribe to chain events, see
+/// `Topic`.
+///
+/// On top of the methods of the node, `stop` shuts the server down once
+/// answered, see `wait`.
+pub struct RpcServer {
+    state: Arc<ServerState>,
+}
+
+/// Shared by the server and its connections, any of which may stop it.
+pub(super) struct ServerState {
+    running: Mutex<bool>,
+    stopped: Condvar,
+    local_addr: SocketAddr,
+    cookie_path: Option<PathBuf>,
+}
+
+impl ServerState {
+    pub(super) fn shutdown(&self) {
+        let mut running = self.running.lock().unwrap();
+        if !*running {
+            return;
+        }
+        *running = false;
+        drop(running);
+
+        if let Some(path) = &self.cookie_path {
+            let _ = fs::remove_file(path);
+        }
+        // Wakes the accepting thread up so that it sees the server stopped
+        let _ = TcpStream::connect(self.local_addr);
+        self.stopped.notify_all();
+    }
+
+    fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+}
+
+impl RpcServer {
+    /// Starts listening on `listen_addr`, which must be a loopback address.
+    /// Use port 0 to let the system pick a free one, see `local_addr`.
+    pub fn start<B: RpcBackend + 'static, A: ToSocketAddrs>(
+        backend: Arc<B>,
+        listen_addr: A,
+        config: RpcConfig,
+    ) -> Result<RpcServer, RpcError> {
+        let listener = TcpListener::bind(listen_addr)?;
+        let local_addr = listener.local_addr()?;
+        if !local_addr.ip().is_loopback() {
+            return Err(RpcError::NonLocalAddress { addr: local_addr });
+        }
+        if let Some(path) = &config.cookie_path {
+            write_cookie(path, &config.auth_token)?;
+        }
+
+        let state = Arc::new(ServerState {
+            running: Mutex::new(true),
+            stopped: Condvar::new(),
+            local_addr,
+            cookie_path: config.cookie_path.clone(),
+        });
+        let server = RpcServer {
+            state: Arc::clone(&state),
+        };
+        let config = Arc::new(config);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !state.is_running() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let backend = Arc::clone(&backend);
+                let config = Arc::clone(&config);
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    // The client is gone if the response cannot be written
+                    let _ = serve_connection(stream, &*backend, &config, &state);
+                });
+            }
+        });
+
+        Ok(server)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.state.local_addr
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.is_running()
+    }
+
+    /// Stops accepting connections and removes the cookie file. Requests
+    /// already received are still answered.
+    pub fn shutdown(&self) {
+        self.state.shutdown();
+    }
+
+    /// Blocks until the server is shut down, by `shutdown` or by a client
+    /// calling the `stop` method.
+    pub fn wait(&self) {
+        let running = self.state.running.lock().unwrap();
+        let _stopped = self.state.stopped.wait_while(running, |running| *running).unwrap();
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Writes the auth token to `path`. On Unix only the current user can read
+/// it, other local users could call the server otherwise.
+fn write_cookie(path: &Path, token: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    // The mode is only set on creation, a cookie left by a crash keeps its own
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(token.as_bytes())
+}
+
+fn serve_connection<B: RpcBackend>(
+    stream: TcpStream,
+    backend: &B,
+    config: &RpcConfig,
+    state: &ServerState,
+) -> Result<(), RpcError> {
+    stream.set_read_timeout(Some(config.request_timeout))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader, config.max_request_size) {
+        Ok(request) => request,
+        Err(err @ RpcError::RequestTooLarge { .. }) => {
+            return write_response(&mut writer, 413, &[], err.to_string().as_bytes())
+        }
+        Err(err) => return write_response(&mut writer, 400, &[], err.to_string().as_bytes()),
+    };
+
+    let websocket = is_upgrade(&request);
+    if request.method != "POST" && !websocket {
+        return write_response(&mut writer, 405, &[("Allow", "POST")], b"");
+    }
+    if !is_authorized(request.header("authorization"), &config.auth_token) {
+        return write_response(&mut writer, 401, &[("WWW-Authenticate", "Bearer")], b"");
+    }
+    if websocket {
+        if let Err(err) = accept_upgrade(&mut writer, &request) {
+            return write_response(&mut writer, 400, &[], err.to_string().as_bytes());
+        }
+        return serve_subscriptions(reader, writer, backend, config, state);
+    }
+
+    let mut stop_requested = false;
+    let response = handle_body(&request.body, |method, params| {
+        dispatch(backend, &mut stop_requested, method, params)
+    });
+    let res = match response {
+        Some(response) => write_response(
+            &mut writer,
+            200,
+            &[("Content-Type", "application/json")],
+            &serde_json::to_vec(&response)?,
+        ),
+        None => write_response(&mut writer, 204, &[], b""),
+    };
+    if stop_requested {
+        state.shutdown();
+    }
+    res
+}
+
+/// Runs `method` on `backend`, except `stop`, which only records that the
+/// server must be shut down once the response is sent.
+pub(super) fn dispatch<B: RpcBackend>(
+    backend: &B,
+    stop_requested: &mut bool,
+    method: &str,
+    params: &Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "stop" => {
+            *stop_requested = true;
+            Ok(Value::Null)
+        }
+        _ => call(backend, method, params),
+    }
+}
+
+/// Answers a request or a batch of requests. Returns `None` if there is
+/// nothing to answer, when only notifications were sent. Each request is
+/// run by `dispatch` with its method and params.
+pub(super) fn handle_body<F>(body: &[u8], mut dispatch: F) -> Option<Value>
+where
+    F: FnMut(&str, &Value) -> Result<Value, RpcError>,
+{
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(err) => return Some(to_value(RpcResponse::new(Value::Null, Err(RpcError::Parse(err))))),
+    };
+
+    match value {
+        Value::Array(requests) if requests.is_empty() => {
+            let err = RpcError::InvalidRequest("empty batch".to_string());
+            Some(to_value(RpcResponse::new(Value::Null, Err(err))))
+        }
+        Value::Array(requests) => {
+            let responses: Vec<Value> = requests
+                .iter()
+                .filter_map(|request| handle_request(&mut dispatch, request))
+                .map(to_value)
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => handle_request(&mut dispatch, &request).map(to_value),
+    }
+}
+
+fn handle_request<F>(dispatch: &mut F, request: &Value) -> Option<RpcResponse>
+where
+    F: FnMut(&str, &Value) -> Result<Value, RpcError>,
+{
+    let request = match RpcRequest::from_value(request) {
+        Ok(request) => request,
+        Err(err) => return Some(RpcResponse::new(Value::Null, Err(err))),
+    };
+
+    let res = dispatch(&request.method, &request.params);
+    request.id.map(|id| RpcResponse::new(id, res))
+}
+
+fn to_value(response: RpcResponse) -> Value {
+    serde_json::to_value(response).expect("Responses only hold JSON values and strings")
+}
+
+/// Compares the tokens in constant time, so that the time taken does not
+/// tell how much of the token was guessed right.
+fn is_authorized(authorization: Option<&str>, auth_token: &str) -> bool {
+    let token = match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(token) => token.trim(),
+        None => return false,
+    };
+
+    token.len() == auth_token.len()
+        && token
+            .bytes()
+            .zip(auth_token.bytes())
+            .fold(0u8, |diff, (first, second)| diff | (first ^ second))
+            == 0
+}
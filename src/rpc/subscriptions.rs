@@ -0,0 +1,339 @@
+use std::{
+    io::BufReader,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::core::{Block, ChainEvent, Transaction};
+
+use super::{
+    methods::param,
+    server::{dispatch, handle_body, ServerState},
+    write_frame, Frame, FrameReader, RpcBackend, RpcConfig, RpcError, JSONRPC_VERSION,
+};
+
+/// Most subscriptions a single connection can hold.
+const MAX_SUBSCRIPTIONS: usize = 64;
+/// How often the notifying thread checks whether the connection is over.
+const CLOSED_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a WebSocket client can subscribe to, with the `subscribe` method
+/// and the topic name as first parameter: `["blocks"]`, `["txs"]`,
+/// `["reorgs"]` or `["address", "<address>"]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topic {
+    /// Header and hash of every block joining the chain.
+    Blocks,
+    /// Every transaction entering the mempool.
+    Txs,
+    /// Switches of the chain to another branch.
+    Reorgs,
+    /// Transactions sending to or from the address, as they enter the
+    /// mempool (`pending`), are mined (`confirmed`) or leave the chain in a
+    /// reorganization (`unconfirmed`).
+    Address(String),
+}
+
+impl Topic {
+    fn from_params(params: &Value) -> Result<Topic, RpcError> {
+        let name: String = param(params, 0, "topic")?;
+        match name.as_str() {
+            "blocks" => Ok(Topic::Blocks),
+            "txs" => Ok(Topic::Txs),
+            "reorgs" => Ok(Topic::Reorgs),
+            "address" => Ok(Topic::Address(param(params, 1, "address")?)),
+            _ => Err(RpcError::InvalidParams(format!("unknown topic {}", name))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Topic::Blocks => "blocks",
+            Topic::Txs => "txs",
+            Topic::Reorgs => "reorgs",
+            Topic::Address(_) => "address",
+        }
+    }
+
+    /// Results sent to the subscribers of the topic about `event`, none if
+    /// it does not concern them.
+    pub fn notifications(&self, event: &ChainEvent) -> Vec<Value> {
+        match (self, event) {
+            (Topic::Blocks, ChainEvent::BlockConnected(block)) => {
+                let mut header = json!(block.header());
+                header["hash"] = json!(block.hash);
+                vec![header]
+            }
+            (Topic::Txs, ChainEvent::TxAccepted(tx)) => vec![json!(tx)],
+            (
+                Topic::Reorgs,
+                ChainEvent::Reorganized {
+                    fork_height,
+                    old_tip,
+                    new_tip,
+                    disconnected,
+                    connected,
+                },
+            ) => vec![json!({
+                "fork_height": fork_height,
+                "old_tip": old_tip,
+                "new_tip": new_tip,
+                "disconnected": disconnected,
+                "connected": connected,
+            })],
+            (Topic::Address(address), ChainEvent::TxAccepted(tx)) if involves(tx, address) => {
+                vec![json!({ "status": "pending", "tx": tx })]
+            }
+            (Topic::Address(address), ChainEvent::BlockConnected(block)) => block_activity(block, address, "confirmed"),
+            (Topic::Address(address), ChainEvent::BlockDisconnected(block)) => {
+                block_activity(block, address, "unconfirmed")
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn involves(tx: &Transaction, address: &str) -> bool {
+    tx.from == address || tx.to == address
+}
+
+fn block_activity(block: &Block, address: &str, status: &str) -> Vec<Value> {
+    block
+        .txs
+        .iter()
+        .filter(|tx| involves(tx, address))
+        .map(|tx| {
+            json!({
+                "status": status,
+                "block_hash": block.hash,
+                "height": block.height,
+                "tx": tx,
+            })
+        })
+        .collect()
+}
+
+/// Subscriptions of a connection, by id.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    next_id: u64,
+    topics: Vec<(u64, Topic)>,
+}
+
+/// Serves a connection switched to WebSocket. Every text message carries a
+/// request or a batch, answered in a text message, as over HTTP. On top of
+/// the usual methods, `subscribe` returns the id of a new subscription and
+/// `unsubscribe` cancels one, telling whether it existed. Events are sent
+/// as notifications:
+///
+/// `{"jsonrpc":"2.0","method":"subscription","params":{"subscription":1,"topic":"blocks","result":{...}}}`
+pub(super) fn serve_subscriptions<B: RpcBackend>(
+    mut reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    backend: &B,
+    config: &RpcConfig,
+    state: &ServerState,
+) -> Result<(), RpcError> {
+    // Subscribers wait for events as long as they want, but a client not
+    // reading its notifications is closed rather than blocking them
+    reader.get_ref().set_read_timeout(None)?;
+    writer.set_write_timeout(Some(config.request_timeout))?;
+    let events = backend.with_node(|node| node.subscribe());
+    let writer = Mutex::new(writer);
+    let subscriptions = Mutex::new(Subscriptions::default());
+    let closed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        scope.spawn(|| notify(events, &writer, &subscriptions, &closed));
+
+        let res = serve_requests(&mut reader, &writer, backend, &subscriptions, config, state);
+        closed.store(true, Ordering::SeqCst);
+        let _ = reader.get_ref().shutdown(Shutdown::Both);
+        res
+    })
+}
+
+fn serve_requests<B: RpcBackend>(
+    reader: &mut BufReader<TcpStream>,
+    writer: &Mutex<TcpStream>,
+    backend: &B,
+    subscriptions: &Mutex<Subscriptions>,
+    config: &RpcConfig,
+    state: &ServerState,
+) -> Result<(), RpcError> {
+    let mut frames = FrameReader::new(config.max_request_size, true);
+    loop {
+        let frame = match frames.read(reader) {
+            Ok(frame) => frame,
+            Err(RpcError::ConnectionClosed) => return Ok(()),
+            Err(err) => {
+                let _ = write_frame(&mut *writer.lock().unwrap(), &Frame::Close, None);
+                return Err(err);
+            }
+        };
+
+        let body = match &frame {
+            Frame::Text(text) => text.as_bytes(),
+            Frame::Binary(data) => data.as_slice(),
+            Frame::Ping(data) => {
+                write_frame(&mut *writer.lock().unwrap(), &Frame::Pong(data.clone()), None)?;
+                continue;
+            }
+            Frame::Pong(_) => continue,
+            Frame::Close => return write_frame(&mut *writer.lock().unwrap(), &Frame::Close, None),
+        };
+
+        let mut stop_requested = false;
+        let response = handle_body(body, |method, params| match method {
+            "subscribe" => subscribe(subscriptions, params),
+            "unsubscribe" => {
+                let id: u64 = param(params, 0, "subscription")?;
+                let topics = &mut subscriptions.lock().unwrap().topics;
+                let count = topics.len();
+                topics.retain(|(subscription, _)| *subscription != id);
+                Ok(json!(topics.len() != count))
+            }
+            _ => dispatch(backend, &mut stop_requested, method, params),
+        });
+        if let Some(response) = response {
+            write_frame(&mut *writer.lock().unwrap(), &Frame::Text(response.to_string()), None)?;
+        }
+        if stop_requested {
+            state.shutdown();
+        }
+    }
+}
+
+fn subscribe(subscriptions: &Mutex<Subscriptions>, params: &Value) -> Result<Value, RpcError> {
+    let topic = Topic::from_params(params)?;
+    let mut subscriptions = subscriptions.lock().unwrap();
+    if subscriptions.topics.len() >= MAX_SUBSCRIPTIONS {
+        return Err(RpcError::InvalidParams(format!(
+            "at most {} subscriptions per connection",
+            MAX_SUBSCRIPTIONS
+        )));
+    }
+
+    subscriptions.next_id += 1;
+    let id = subscriptions.next_id;
+    subscriptions.topics.push((id, topic));
+    Ok(json!(id))
+}
+
+/// Sends the notifications of every event until the connection is over.
+/// Closes it if the client cannot be written to anymore, or if it fell so
+/// far behind that the node dropped its subscription.
+fn notify(
+    events: Receiver<ChainEvent>,
+    writer: &Mutex<TcpStream>,
+    subscriptions: &Mutex<Subscriptions>,
+    closed: &AtomicBool,
+) {
+    while !closed.load(Ordering::SeqCst) {
+        let event = match events.recv_timeout(CLOSED_CHECK_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+                return;
+            }
+        };
+
+        let notifications: Vec<Value> = subscriptions
+            .lock()
+            .unwrap()
+            .topics
+            .iter()
+            .flat_map(|(id, topic)| {
+                topic.notifications(&event).into_iter().map(move |result| {
+                    json!({
+                        "jsonrpc": JSONRPC_VERSION,
+                        "method": "subscription",
+                        "params": { "subscription": id, "topic": topic.name(), "result": result },
+                    })
+                })
+            })
+            .collect();
+
+        let mut writer = writer.lock().unwrap();
+        for notification in notifications {
+            if write_frame(&mut *writer, &Frame::Text(notification.to_string()), None).is_err() {
+                let _ = writer.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod subscriptions_test {
+    use serde_json::json;
+
+    use crate::core::{Block, BlockHeader, ChainEvent, Transaction};
+
+    use super::Topic;
+
+    fn block_with(txs: Vec<Transaction>) -> Block {
+        let header = BlockHeader {
+            height: 3,
+            previous_hash: "previous".to_string(),
+            merkle_root: Block::calculate_merkle_root(&txs),
+            timestamp: 0,
+            difficulty: 1,
+            nonce: 0,
+        };
+        Block::from_header(header, txs)
+    }
+
+    #[test]
+    fn topics_are_read_from_positional_or_named_params() {
+        assert_eq!(Topic::Blocks, Topic::from_params(&json!(["blocks"])).unwrap());
+        assert_eq!(
+            Topic::Address("alice".to_string()),
+            Topic::from_params(&json!({ "topic": "address", "address": "alice" })).unwrap()
+        );
+        assert!(Topic::from_params(&json!(["address"])).is_err());
+        assert!(Topic::from_params(&json!(["unknown"])).is_err());
+    }
+
+    #[test]
+    fn address_topic_follows_the_transactions_of_the_address() {
+        let payment = Transaction::new("alice".to_string(), "bob".to_string(), 5, 1, 0, 1);
+        let other = Transaction::new("carol".to_string(), "dave".to_string(), 5, 1, 0, 1);
+        let block = block_with(vec![payment.clone(), other.clone()]);
+        let topic = Topic::Address("bob".to_string());
+
+        assert_eq!(
+            vec![json!({ "status": "pending", "tx": payment })],
+            topic.notifications(&ChainEvent::TxAccepted(payment.clone()))
+        );
+        assert!(topic.notifications(&ChainEvent::TxAccepted(other)).is_empty());
+        let confirmed = topic.notifications(&ChainEvent::BlockConnected(block.clone()));
+        assert_eq!(1, confirmed.len());
+        assert_eq!(json!("confirmed"), confirmed[0]["status"]);
+        assert_eq!(json!(block.hash), confirmed[0]["block_hash"]);
+        let unconfirmed = topic.notifications(&ChainEvent::BlockDisconnected(block));
+        assert_eq!(json!("unconfirmed"), unconfirmed[0]["status"]);
+    }
+
+    #[test]
+    fn blocks_topic_sends_the_header_with_its_hash() {
+        let block = block_with(Vec::new());
+
+        let notifications = Topic::Blocks.notifications(&ChainEvent::BlockConnected(block.clone()));
+
+        assert_eq!(json!(block.hash), notifications[0]["hash"]);
+        assert_eq!(json!(3), notifications[0]["height"]);
+        assert!(Topic::Blocks
+            .notifications(&ChainEvent::BlockDisconnected(block))
+            .is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use std::io::{ErrorKind, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use super::{HttpRequest, RpcError};
+
+/// Appended to the key of the client to compute the accept key, see RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WEBSOCKET_VERSION: &str = "13";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// A WebSocket message, or a control frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// Whether `request` asks to switch the connection to WebSocket.
+pub fn is_upgrade(request: &HttpRequest) -> bool {
+    request.method == "GET"
+        && request
+            .header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Value of the `Sec-WebSocket-Accept` header answering `key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key.as_bytes())
+        .chain_update(WEBSOCKET_GUID.as_bytes())
+        .finalize();
+    STANDARD.encode(digest)
+}
+
+/// Checks the upgrade request and switches the connection to WebSocket.
+/// Nothing is written if the request is invalid.
+pub fn accept_upgrade<W: Write>(writer: &mut W, request: &HttpRequest) -> Result<(), RpcError> {
+    if request.header("sec-websocket-version") != Some(WEBSOCKET_VERSION) {
+        return Err(RpcError::MalformedHttpRequest(format!(
+            "WebSocket version {} is required",
+            WEBSOCKET_VERSION
+        )));
+    }
+    let key = request
+        .header("sec-websocket-key")
+        .ok_or_else(|| RpcError::MalformedHttpRequest("missing Sec-WebSocket-Key".to_string()))?;
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    writer.write_all(response.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `frame` in a single frame, masked with `mask` if set, which only
+/// clients do.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame, mask: Option<[u8; 4]>) -> Result<(), RpcError> {
+    let (opcode, payload) = match frame {
+        Frame::Text(text) => (TEXT, text.as_bytes()),
+        Frame::Binary(data) => (BINARY, data.as_slice()),
+        Frame::Ping(data) => (PING, data.as_slice()),
+        Frame::Pong(data) => (PONG, data.as_slice()),
+        Frame::Close => (CLOSE, &[][..]),
+    };
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut bytes = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => bytes.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            bytes.push(mask_bit | 126);
+            bytes.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            bytes.push(mask_bit | 127);
+            bytes.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            bytes.extend_from_slice(&mask);
+            bytes.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        }
+        None => bytes.extend_from_slice(payload),
+    }
+
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the frames of a connection, putting fragmented messages back
+/// together. Control frames are returned as they arrive, even in the middle
+/// of a fragmented message.
+#[derive(Debug)]
+pub struct FrameReader {
+    max_size: usize,
+    /// Whether frames must be masked, as the ones sent by clients are.
+    masked: bool,
+    /// Opcode and payload of the fragmented message being received.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl FrameReader {
+    pub fn new(max_size: usize, masked: bool) -> FrameReader {
+        FrameReader {
+            max_size,
+            masked,
+            partial: None,
+        }
+    }
+
+    /// Blocks until the next message or control frame. Messages larger than
+    /// `max_size` bytes are refused.
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Frame, RpcError> {
+        loop {
+            let (fin, opcode, payload) = self.read_raw(reader)?;
+            match opcode {
+                CLOSE => return Ok(Frame::Close),
+                PING => return Ok(Frame::Ping(payload)),
+                PONG => return Ok(Frame::Pong(payload)),
+                TEXT | BINARY if self.partial.is_none() => self.partial = Some((opcode, payload)),
+                CONTINUATION if self.partial.is_some() => {
+                    let (_, message) = self.partial.as_mut().unwrap();
+                    if message.len() + payload.len() > self.max_size {
+                        return Err(RpcError::RequestTooLarge {
+                            size: message.len() + payload.len(),
+                            max: self.max_size,
+                        });
+                    }
+                    message.extend_from_slice(&payload);
+                }
+                _ => return Err(malformed(format!("unexpected opcode {}", opcode))),
+            }
+
+            if fin {
+                let (opcode, message) = self.partial.take().unwrap();
+                return match opcode {
+                    TEXT => String::from_utf8(message)
+                        .map(Frame::Text)
+                        .map_err(|_| malformed("text message is not UTF-8".to_string())),
+                    _ => Ok(Frame::Binary(message)),
+                };
+            }
+        }
+    }
+
+    /// Reads a single frame, returning whether it is the last of its
+    /// message, its opcode and its unmasked payload.
+    fn read_raw<R: Read>(&self, reader: &mut R) -> Result<(bool, u8, Vec<u8>), RpcError> {
+        let mut header = [0u8; 2];
+        read_exact(reader, &mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        if header[0] & 0x70 != 0 {
+            return Err(malformed("reserved bits are set".to_string()));
+        }
+        if (header[1] & 0x80 != 0) != self.masked {
+            let reason = if self.masked {
+                "frames must be masked"
+            } else {
+                "frames must not be masked"
+            };
+            return Err(malformed(reason.to_string()));
+        }
+
+        let size = match header[1] & 0x7F {
+            126 => {
+                let mut size = [0u8; 2];
+                read_exact(reader, &mut size)?;
+                u16::from_be_bytes(size) as u64
+            }
+            127 => {
+                let mut size = [0u8; 8];
+                read_exact(reader, &mut size)?;
+                u64::from_be_bytes(size)
+            }
+            size => size as u64,
+        };
+        if opcode >= CLOSE && (!fin || size > 125) {
+            return Err(malformed(
+                "control frames cannot be fragmented or longer than 125 bytes".to_string(),
+            ));
+        }
+        if size > self.max_size as u64 {
+            return Err(RpcError::RequestTooLarge {
+                size: usize::try_from(size).unwrap_or(usize::MAX),
+                max: self.max_size,
+            });
+        }
+
+        let mut mask = [0u8; 4];
+        if self.masked {
+            read_exact(reader, &mut mask)?;
+        }
+        let mut payload = vec![0u8; size as usize];
+        read_exact(reader, &mut payload)?;
+        if self.masked {
+            payload
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, byte)| *byte ^= mask[i % 4]);
+        }
+        Ok((fin, opcode, payload))
+    }
+}
+
+fn malformed(reason: String) -> RpcError {
+    RpcError::MalformedFrame(reason)
+}
+
+/// Same as `Read::read_exact`, reporting a connection closed by the other
+/// side as such.
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), RpcError> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => RpcError::ConnectionClosed,
+        _ => RpcError::Io(err),
+    })
+}
+
+#[cfg(test)]
+mod websocket_test {
+    use crate::rpc::RpcError;
+
+    use super::{accept_key, write_frame, Frame, FrameReader};
+
+    #[test]
+    fn accept_key_matches_the_one_of_the_rfc() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn masked_frames_are_read_back_and_fragments_joined() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &Frame::Text("x".repeat(300)), Some([1, 2, 3, 4])).unwrap();
+        // "Hello" sent in two fragments, with a ping in between
+        bytes.extend_from_slice(&[0x01, 0x83, 0, 0, 0, 0, b'H', b'e', b'l']);
+        write_frame(&mut bytes, &Frame::Ping(b"?".to_vec()), Some([9, 9, 9, 9])).unwrap();
+        bytes.extend_from_slice(&[0x80, 0x82, 0, 0, 0, 0, b'l', b'o']);
+
+        let mut reader = FrameReader::new(1000, true);
+        let mut input = &bytes[..];
+
+        assert_eq!(Frame::Text("x".repeat(300)), reader.read(&mut input).unwrap());
+        assert_eq!(Frame::Ping(b"?".to_vec()), reader.read(&mut input).unwrap());
+        assert_eq!(Frame::Text("Hello".to_string()), reader.read(&mut input).unwrap());
+        assert!(matches!(reader.read(&mut input), Err(RpcError::ConnectionClosed)));
+    }
+
+    #[test]
+    fn unmasked_or_oversized_client_frames_are_refused() {
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, &Frame::Text("hi".to_string()), None).unwrap();
+        let mut oversized = Vec::new();
+        write_frame(&mut oversized, &Frame::Binary(vec![0; 200]), Some([1, 1, 1, 1])).unwrap();
+
+        assert!(matches!(
+            FrameReader::new(100, true).read(&mut &unmasked[..]),
+            Err(RpcError::MalformedFrame(_))
+        ));
+        assert!(matches!(
+            FrameReader::new(100, true).read(&mut &oversized[..]),
+            Err(RpcError::RequestTooLarge { size: 200, max: 100 })
+        ));
+    }
+}
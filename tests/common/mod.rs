@@ -0,0 +1,76 @@
+//! Helpers shared by the integration tests, each of which only uses some.
+#![allow(dead_code)]
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use rust_chain::{
+    core::{mine_new_block, Block, ConsensusParams, History, MemPool, NaiveReorgStrategy, Node, Transaction},
+    network::{P2pConfig, P2pNode, VersionMessage, PROTOCOL_VERSION},
+};
+
+/// Params of a chain where blocks are mined at once and rewards can be
+/// spent right away.
+pub fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        initial_difficulty: 1,
+        coinbase_maturity: 0,
+        ..ConsensusParams::default()
+    }
+}
+
+pub fn new_node(params: &ConsensusParams) -> Node {
+    Node::new(
+        History::new(params.clone(), Box::new(NaiveReorgStrategy {})),
+        MemPool::new(1_000_000, params.clone()),
+    )
+}
+
+/// Starts a fresh node listening on a free port of localhost.
+pub fn start_p2p(params: &ConsensusParams, config: P2pConfig) -> P2pNode {
+    P2pNode::start(new_node(params), "127.0.0.1:0", config).unwrap()
+}
+
+/// Mines a block with `txs` on top of `hs`, one second after its median
+/// time past.
+pub fn mine_on_top(hs: &History, txs: Vec<Transaction>) -> Block {
+    let prev_block = hs.get_last_block().unwrap();
+    let timestamp = hs.median_time_past() + 1;
+    let difficulty = hs.next_difficulty();
+
+    let (nonce, hash) = mine_new_block(prev_block.height + 1, timestamp, &prev_block.hash, &txs, difficulty);
+    Block::new(prev_block, hash, timestamp, txs, difficulty, nonce)
+}
+
+/// Same as `mine_on_top`, the block first paying its reward to `miner`.
+pub fn mine_reward_on_top(hs: &History, params: &ConsensusParams, miner: String, txs: Vec<Transaction>) -> Block {
+    let height = hs.get_last_block().unwrap().height + 1;
+    let coinbase = Transaction::coinbase(miner, params.block_reward(height), height, params.chain_id);
+    mine_on_top(hs, [vec![coinbase], txs].concat())
+}
+
+/// Version of a peer on the chain of `params`, without any block.
+pub fn local_version(params: &ConsensusParams) -> VersionMessage {
+    VersionMessage {
+        protocol_version: PROTOCOL_VERSION,
+        chain_id: params.chain_id,
+        genesis_hash: Block::genesis(params).hash,
+        best_height: 0,
+        listen_port: 0,
+        nonce: 0,
+    }
+}
+
+/// Polls `condition` until it holds, giving up after 10 seconds.
+pub fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    condition()
+}
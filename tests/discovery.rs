@@ -0,0 +1,104 @@
+mod common;
+
+use std::{env, fs, thread, time::Duration};
+
+use rust_chain::{
+    core::ChainSpec,
+    network::{P2pConfig, P2pNode},
+};
+
+use common::{start_p2p, test_params, wait_until};
+
+fn config() -> P2pConfig {
+    P2pConfig {
+        tick_interval: Duration::from_millis(50),
+        ..P2pConfig::default()
+    }
+}
+
+#[test]
+fn nodes_find_each_other_through_address_gossip() {
+    let params = test_params();
+    let hub = start_p2p(&params, config());
+    let first = start_p2p(&params, config());
+    let second = start_p2p(&params, config());
+
+    assert!(first.connect(hub.local_addr()).is_ok());
+    assert!(second.connect(hub.local_addr()).is_ok());
+
+    // The hub relays the address of the second node to the first one, and
+    // gives the address of the first one to the second one when asked for
+    // addresses, so they may both connect to the other
+    assert!(wait_until(|| first.peer_count() >= 2 && second.peer_count() >= 2));
+    assert!(first
+        .known_addresses()
+        .iter()
+        .any(|known| known.addr == second.local_addr()));
+}
+
+#[test]
+fn seed_nodes_are_connected_to_at_start() {
+    let params = test_params();
+    let seed = start_p2p(&params, config());
+    let spec = ChainSpec {
+        params,
+        seed_nodes: vec![seed.local_addr().to_string()],
+    };
+
+    let node = start_p2p(
+        &spec.params,
+        P2pConfig {
+            tick_interval: config().tick_interval,
+            ..P2pConfig::for_chain(&spec)
+        },
+    );
+
+    assert!(wait_until(|| node.peer_count() == 1 && seed.peer_count() == 1));
+}
+
+#[test]
+fn outbound_connections_stop_at_the_target() {
+    let params = test_params();
+    let others: Vec<P2pNode> = (0..3).map(|_| start_p2p(&params, config())).collect();
+    let node = start_p2p(
+        &params,
+        P2pConfig {
+            target_outbound: 2,
+            ..config()
+        },
+    );
+
+    for other in &others {
+        assert!(node.add_address(other.local_addr()));
+    }
+
+    assert!(wait_until(|| node.peer_count() == 2));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(2, node.peer_count());
+}
+
+#[test]
+fn address_book_is_kept_across_restarts() {
+    let params = test_params();
+    let path = env::temp_dir().join(format!("rust-chain-addresses-{}.json", std::process::id()));
+    let other = start_p2p(&params, config());
+    let config = P2pConfig {
+        address_book_path: Some(path.clone()),
+        connect_retry_interval: Duration::ZERO,
+        ..config()
+    };
+
+    let node = start_p2p(&params, config.clone());
+    assert!(node.connect(other.local_addr()).is_ok());
+    node.shutdown();
+    assert!(wait_until(|| other.peer_count() == 0));
+
+    let restarted = start_p2p(&params, config);
+    fs::remove_file(&path).unwrap();
+
+    assert!(restarted
+        .known_addresses()
+        .iter()
+        .any(|known| known.addr == other.local_addr() && known.last_success.is_some()));
+    assert!(wait_until(|| restarted.peer_count() == 1));
+}
@@ -0,0 +1,439 @@
+mod common;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use rust_chain::{
+    core::{mine_new_block, Block, ConsensusParams, History, NaiveReorgStrategy, Transaction, WalletKeyPair},
+    network::{
+        short_tx_id, CompactBlock, InventoryItem, Message, NetworkError, P2pConfig, P2pNode, PeerConnection,
+        VersionMessage, PROTOCOL_VERSION,
+    },
+};
+
+use common::{local_version, mine_on_top, mine_reward_on_top, start_p2p, test_params, wait_until};
+
+fn reward_block(p2p: &P2pNode, params: &ConsensusParams, address: String) -> Block {
+    p2p.with_node(|node| mine_reward_on_top(node.get_history(), params, address, Vec::new()))
+}
+
+/// Connects to `addr` without running a node, to look at the messages it sends.
+fn connect_raw_peer(params: &ConsensusParams, addr: SocketAddr) -> PeerConnection {
+    try_connect_raw_peer(params, addr).unwrap()
+}
+
+fn try_connect_raw_peer(params: &ConsensusParams, addr: SocketAddr) -> Result<PeerConnection, NetworkError> {
+    try_connect_raw_peer_with_version(params, addr, PROTOCOL_VERSION)
+}
+
+fn try_connect_raw_peer_with_version(
+    params: &ConsensusParams,
+    addr: SocketAddr,
+    protocol_version: u32,
+) -> Result<PeerConnection, NetworkError> {
+    let version = VersionMessage {
+        protocol_version,
+        ..local_version(params)
+    };
+    let stream = TcpStream::connect(addr)?;
+    PeerConnection::handshake(stream, &version, 4 * 1024 * 1024, Duration::from_secs(5))
+}
+
+fn funded_payment(p2p: &P2pNode, params: &ConsensusParams) -> Transaction {
+    let sender = WalletKeyPair::new();
+    let block = reward_block(p2p, params, sender.address());
+    assert!(p2p.submit_block(block).is_ok());
+
+    let mut tx = Transaction::new(
+        sender.address(),
+        WalletKeyPair::new().address(),
+        10,
+        1,
+        0,
+        params.chain_id,
+    );
+    tx.sign(&sender.secret_key);
+    tx
+}
+
+#[test]
+fn nodes_on_the_same_chain_complete_the_handshake() {
+    let params = test_params();
+    let a = start_p2p(&params, P2pConfig::default());
+    let b = start_p2p(&params, P2pConfig::default());
+
+    assert!(a.connect(b.local_addr()).is_ok());
+
+    assert!(wait_until(|| a.peer_count() == 1 && b.peer_count() == 1));
+    assert_eq!(params.chain_id, a.peers()[0].1.chain_id);
+}
+
+#[test]
+fn node_of_another_chain_is_rejected() {
+    let params = test_params();
+    let other_params = ConsensusParams {
+        chain_id: params.chain_id + 1,
+        ..test_params()
+    };
+    let a = start_p2p(&params, P2pConfig::default());
+    let b = start_p2p(&other_params, P2pConfig::default());
+
+    let res = a.connect(b.local_addr());
+
+    assert!(matches!(res, Err(NetworkError::WrongChain { .. })));
+    assert_eq!(0, a.peer_count());
+    assert!(wait_until(|| b.peer_count() == 0));
+}
+
+#[test]
+fn block_propagates_through_the_network() {
+    let params = test_params();
+    let a = start_p2p(&params, P2pConfig::default());
+    let b = start_p2p(&params, P2pConfig::default());
+    let c = start_p2p(&params, P2pConfig::default());
+    assert!(a.connect(b.local_addr()).is_ok());
+    assert!(b.connect(c.local_addr()).is_ok());
+
+    let block = reward_block(&a, &params, WalletKeyPair::new().address());
+    let hash = block.hash.clone();
+    assert!(a.submit_block(block).is_ok());
+
+    assert!(wait_until(
+        || b.with_node(|node| node.get_history().get_block(&hash).is_some())
+    ));
+    assert!(wait_until(
+        || c.with_node(|node| node.get_history().get_block(&hash).is_some())
+    ));
+}
+
+#[test]
+fn tx_propagates_to_connected_nodes() {
+    let params = test_params();
+    let a = start_p2p(&params, P2pConfig::default());
+    let b = start_p2p(&params, P2pConfig::default());
+    assert!(a.connect(b.local_addr()).is_ok());
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+
+    let block = reward_block(&a, &params, sender.address());
+    let hash = block.hash.clone();
+    assert!(a.submit_block(block).is_ok());
+    assert!(wait_until(
+        || b.with_node(|node| node.get_history().get_block(&hash).is_some())
+    ));
+
+    let mut tx = Transaction::new(sender.address(), receiver.address(), 10, 1, 0, params.chain_id);
+    tx.sign(&sender.secret_key);
+    let id = tx.id.clone();
+    assert!(a.submit_tx(tx).is_ok());
+
+    assert!(wait_until(
+        || b.with_node(|node| node.get_mempool().get_tx(&id).is_some())
+    ));
+}
+
+#[test]
+fn relayed_tx_is_not_announced_back_to_its_sender() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let tx = funded_payment(&node, &params);
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+
+    assert!(peer.send(&Message::Inv(vec![InventoryItem::Tx(tx.id.clone())])).is_ok());
+    assert!(matches!(
+        peer.receive(),
+        Ok(Message::GetData(items)) if items == vec![InventoryItem::Tx(tx.id.clone())]
+    ));
+    assert!(peer.send(&Message::Tx(tx.clone())).is_ok());
+    assert!(peer.send(&Message::Ping(7)).is_ok());
+
+    // An announcement of the tx would have been sent before the answer to the ping
+    assert!(matches!(peer.receive(), Ok(Message::Pong(7))));
+    assert!(node.with_node(|node| node.get_mempool().get_tx(&tx.id).is_some()));
+}
+
+#[test]
+fn item_announced_by_several_peers_is_requested_once_and_relayed_to_the_others() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let other_node = start_p2p(&params, P2pConfig::default());
+    let tx = funded_payment(&node, &params);
+    assert!(node.connect(other_node.local_addr()).is_ok());
+    let block_hash = node.with_node(|node| node.get_history().get_last_block().unwrap().hash.clone());
+    assert!(wait_until(
+        || other_node.with_node(|node| node.get_history().get_block(&block_hash).is_some())
+    ));
+
+    let first = connect_raw_peer(&params, node.local_addr());
+    let second = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 3));
+    let announcement = Message::Inv(vec![InventoryItem::Tx(tx.id.clone())]);
+
+    assert!(first.send(&announcement).is_ok());
+    assert!(matches!(first.receive(), Ok(Message::GetData(_))));
+    assert!(second.send(&announcement).is_ok());
+    assert!(second.send(&Message::Ping(1)).is_ok());
+    assert!(matches!(second.receive(), Ok(Message::Pong(1))));
+
+    assert!(first.send(&Message::Tx(tx.clone())).is_ok());
+    assert!(wait_until(
+        || other_node.with_node(|node| node.get_mempool().get_tx(&tx.id).is_some())
+    ));
+    assert!(second.send(&Message::Ping(2)).is_ok());
+    assert!(matches!(second.receive(), Ok(Message::Pong(2))));
+}
+
+#[test]
+fn peer_sending_an_invalid_block_is_banned() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+
+    let mut block = reward_block(&node, &params, WalletKeyPair::new().address());
+    block.merkle_root = "forged".to_string();
+    assert!(peer.send(&Message::Block(block)).is_ok());
+
+    assert!(wait_until(|| node.peer_count() == 0));
+    assert!(node.is_banned(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    assert!(try_connect_raw_peer(&params, node.local_addr()).is_err());
+
+    assert!(node.unban(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    assert!(try_connect_raw_peer(&params, node.local_addr()).is_ok());
+}
+
+#[test]
+fn peer_flooding_the_node_is_disconnected() {
+    let params = test_params();
+    let config = P2pConfig {
+        max_messages_per_sec: 5,
+        ..P2pConfig::default()
+    };
+    let node = start_p2p(&params, config);
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+
+    for nonce in 0..50 {
+        if peer.send(&Message::Ping(nonce)).is_err() {
+            break;
+        }
+    }
+
+    assert!(wait_until(|| node.peer_count() == 0));
+    assert!(node.is_banned(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+}
+
+#[test]
+fn connections_over_the_caps_are_refused() {
+    let params = test_params();
+    let config = P2pConfig {
+        max_inbound: 1,
+        max_outbound: 0,
+        ..P2pConfig::default()
+    };
+    let node = start_p2p(&params, config);
+    let other_node = start_p2p(&params, P2pConfig::default());
+
+    let _peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+    assert!(try_connect_raw_peer(&params, node.local_addr()).is_err());
+
+    assert!(matches!(
+        node.connect(other_node.local_addr()),
+        Err(NetworkError::TooManyConnections { max: 0 })
+    ));
+    assert_eq!(1, node.peer_count());
+}
+
+#[test]
+fn connections_still_in_the_handshake_count_against_the_inbound_cap() {
+    let params = test_params();
+    let config = P2pConfig {
+        max_inbound: 2,
+        handshake_timeout: Duration::from_secs(30),
+        ..P2pConfig::default()
+    };
+    let node = start_p2p(&params, config);
+
+    let silent: Vec<TcpStream> = (0..2).map(|_| TcpStream::connect(node.local_addr()).unwrap()).collect();
+    assert!(try_connect_raw_peer(&params, node.local_addr()).is_err());
+    assert_eq!(0, node.peer_count());
+
+    drop(silent);
+    assert!(wait_until(|| try_connect_raw_peer(&params, node.local_addr()).is_ok()));
+}
+
+#[test]
+fn new_block_is_sent_as_a_compact_block_to_peers_supporting_them() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let tx = funded_payment(&node, &params);
+    let peer = connect_raw_peer(&params, node.local_addr());
+    let old_peer = try_connect_raw_peer_with_version(&params, node.local_addr(), 1).unwrap();
+    assert!(wait_until(|| node.peer_count() == 2));
+
+    assert!(node.submit_tx(tx.clone()).is_ok());
+    assert!(matches!(peer.receive(), Ok(Message::Inv(_))));
+    assert!(matches!(old_peer.receive(), Ok(Message::Inv(_))));
+    let coinbase = Transaction::coinbase(
+        WalletKeyPair::new().address(),
+        params.block_reward(2),
+        2,
+        params.chain_id,
+    );
+    let block = node.with_node(|node| mine_on_top(node.get_history(), vec![coinbase, tx.clone()]));
+    assert!(node.submit_block(block.clone()).is_ok());
+
+    match peer.receive() {
+        Ok(Message::CompactBlock(compact)) => {
+            assert_eq!(block.hash, compact.header.hash());
+            assert_eq!(vec![short_tx_id(&block.hash, &tx.id)], compact.short_ids);
+            assert_eq!(1, compact.prefilled_txs.len());
+        }
+        other => panic!("Expected a compact block, received {:?}", other),
+    }
+    assert!(matches!(
+        old_peer.receive(),
+        Ok(Message::Inv(items)) if items == vec![InventoryItem::Block(block.hash.clone())]
+    ));
+}
+
+#[test]
+fn compact_block_is_rebuilt_fetching_only_the_missing_transactions() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let known_tx = funded_payment(&node, &params);
+    let missing_tx = funded_payment(&node, &params);
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+
+    // Sent by the peer, so that it is not announced back to it
+    assert!(peer.send(&Message::Tx(known_tx.clone())).is_ok());
+    assert!(wait_until(
+        || node.with_node(|node| node.get_mempool().get_tx(&known_tx.id).is_some())
+    ));
+    let height = node.with_node(|node| node.get_history().get_height() as u64);
+    let coinbase = Transaction::coinbase(
+        WalletKeyPair::new().address(),
+        params.block_reward(height),
+        height,
+        params.chain_id,
+    );
+    let block =
+        node.with_node(|node| mine_on_top(node.get_history(), vec![coinbase, known_tx.clone(), missing_tx.clone()]));
+
+    assert!(peer
+        .send(&Message::CompactBlock(CompactBlock::from_block(&block)))
+        .is_ok());
+    match peer.receive() {
+        Ok(Message::GetBlockTxn { block_hash, indexes }) => {
+            assert_eq!(block.hash, block_hash);
+            assert_eq!(vec![2], indexes);
+        }
+        other => panic!("Expected a request of the missing transactions, received {:?}", other),
+    }
+    assert!(peer
+        .send(&Message::BlockTxn {
+            block_hash: block.hash.clone(),
+            txs: vec![missing_tx],
+        })
+        .is_ok());
+
+    assert!(wait_until(|| node.with_node(|node| node
+        .get_history()
+        .get_last_block()
+        .unwrap()
+        .hash
+        == block.hash)));
+}
+
+#[test]
+fn peer_syncing_an_invalid_fork_is_banned_and_the_chain_kept() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let victim = WalletKeyPair::new();
+    let block = reward_block(&node, &params, victim.address());
+    assert!(node.submit_block(block.clone()).is_ok());
+
+    // A fork from genesis, longer than our chain, whose second block moves
+    // the coins of the victim without its signature
+    let mut fork = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    let first = mine_on_top(&fork, Vec::new());
+    assert!(fork.try_to_append(first.clone()).is_ok());
+    let theft = Transaction::new(victim.address(), "attacker".to_string(), 50, 0, 0, params.chain_id);
+    let second = mine_on_top(&fork, vec![theft]);
+
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+    assert!(peer
+        .send(&Message::Headers(vec![first.header(), second.header()]))
+        .is_ok());
+    let mut requested = 0;
+    while requested < 2 {
+        match peer.receive() {
+            Ok(Message::GetData(items)) => {
+                for item in items {
+                    let block = [&first, &second]
+                        .into_iter()
+                        .find(|block| item == InventoryItem::Block(block.hash.clone()));
+                    if let Some(block) = block {
+                        assert!(peer.send(&Message::Block(block.clone())).is_ok());
+                        requested += 1;
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(err) => panic!("Connection failed before the fork was sent: {}", err),
+        }
+    }
+
+    assert!(wait_until(|| node.peer_count() == 0));
+    assert!(node.is_banned(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    assert_eq!(
+        block.hash,
+        node.with_node(|node| node.get_history().get_last_block().unwrap().hash.clone())
+    );
+    assert_eq!(0, node.with_node(|node| node.get_history().get_balance("attacker")));
+}
+
+#[test]
+fn compact_block_is_checked_against_its_parent_before_being_rebuilt() {
+    let params = test_params();
+    let node = start_p2p(&params, P2pConfig::default());
+    let peer = connect_raw_peer(&params, node.local_addr());
+    assert!(wait_until(|| node.peer_count() == 1));
+
+    // Builds on a block the node does not have: its headers are asked for
+    let mut other_chain = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    assert!(other_chain.try_to_append(mine_on_top(&other_chain, Vec::new())).is_ok());
+    let orphan = mine_on_top(&other_chain, Vec::new());
+    assert!(peer
+        .send(&Message::CompactBlock(CompactBlock::from_block(&orphan)))
+        .is_ok());
+    loop {
+        match peer.receive() {
+            Ok(Message::GetHeaders(_)) => break,
+            Ok(_) => {}
+            Err(err) => panic!("Expected the headers to be asked for: {}", err),
+        }
+    }
+    assert_eq!(1, node.peer_count());
+
+    let harder = node.with_node(|node| {
+        let hs = node.get_history();
+        let prev_block = hs.get_last_block().unwrap();
+        let difficulty = hs.next_difficulty() + 1;
+        let timestamp = hs.median_time_past() + 1;
+        let (nonce, hash) = mine_new_block(prev_block.height + 1, timestamp, &prev_block.hash, &[], difficulty);
+        Block::new(prev_block, hash, timestamp, Vec::new(), difficulty, nonce)
+    });
+    assert!(peer
+        .send(&Message::CompactBlock(CompactBlock::from_block(&harder)))
+        .is_ok());
+
+    assert!(wait_until(|| node.peer_count() == 0));
+    assert!(node.is_banned(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+}
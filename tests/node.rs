@@ -0,0 +1,238 @@
+mod common;
+
+use std::{env, fs};
+
+use rust_chain::core::{
+    ChainEvent, ConsensusParams, History, MemPool, MemPoolError, NaiveReorgStrategy, Node, StateTransitionError,
+    Transaction, WalletKeyPair,
+};
+
+use common::{mine_on_top, mine_reward_on_top, new_node, test_params};
+
+fn fund(node: &mut Node, params: &ConsensusParams, address: String) {
+    let block = mine_reward_on_top(node.get_history(), params, address, Vec::new());
+    assert!(node.submit_block(block).is_ok());
+}
+
+fn signed_payment(from: &WalletKeyPair, to: &WalletKeyPair, sequence: u64, params: &ConsensusParams) -> Transaction {
+    let mut tx = Transaction::new(from.address(), to.address(), 10, 1, sequence, params.chain_id);
+    tx.sign(&from.secret_key);
+    tx
+}
+
+#[test]
+fn submitted_block_removes_its_txs_from_the_mempool() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    fund(&mut node, &params, sender.address());
+
+    let first = signed_payment(&sender, &receiver, 0, &params);
+    let second = signed_payment(&sender, &receiver, 1, &params);
+    assert!(node.submit_tx(first.clone()).is_ok());
+    assert!(node.submit_tx(second.clone()).is_ok());
+
+    let block = mine_on_top(node.get_history(), vec![first.clone()]);
+    assert!(node.submit_block(block).is_ok());
+
+    assert_eq!(1, node.get_mempool().len());
+    assert!(node.get_mempool().get_tx(&first.id).is_none());
+    assert!(node.get_mempool().get_tx(&second.id).is_some());
+}
+
+#[test]
+fn reorg_gives_txs_of_the_abandoned_branch_back_to_the_mempool() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    fund(&mut node, &params, sender.address());
+
+    let mut fork = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    assert!(fork.try_to_append(node.get_history().get_last_block().unwrap().clone()).is_ok());
+
+    let payment = signed_payment(&sender, &receiver, 0, &params);
+    assert!(node.submit_tx(payment.clone()).is_ok());
+    let block = mine_on_top(node.get_history(), vec![payment.clone()]);
+    assert!(node.submit_block(block).is_ok());
+    assert!(node.get_mempool().is_empty());
+
+    for _ in 0..2 {
+        let block = mine_on_top(&fork, Vec::new());
+        assert!(fork.try_to_append(block).is_ok());
+    }
+
+    assert!(node.reorganize(fork.get_chain()).unwrap());
+    assert_eq!(fork.get_height(), node.get_history().get_height());
+    assert!(node.get_mempool().get_tx(&payment.id).is_some());
+    assert!(!node.reorganize(fork.get_chain()).unwrap());
+}
+
+#[test]
+fn submitted_tx_is_checked_against_the_chain_state() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+
+    let payment = signed_payment(&sender, &receiver, 0, &params);
+    assert!(matches!(
+        node.submit_tx(payment.clone()),
+        Err(MemPoolError::InvalidForState(StateTransitionError::InsufficientBalance { .. }))
+    ));
+
+    fund(&mut node, &params, sender.address());
+    assert!(node.submit_tx(payment).is_ok());
+    assert!(matches!(
+        node.submit_tx(signed_payment(&sender, &receiver, 2, &params)),
+        Err(MemPoolError::InvalidForState(StateTransitionError::BadSequence { expected: 1, found: 2, .. }))
+    ));
+}
+
+#[test]
+fn reloaded_mempool_drops_txs_confirmed_in_the_meantime() {
+    let params = test_params();
+    let path = env::temp_dir().join(format!("rust-chain-node-mempool-{}.json", std::process::id()));
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    fund(&mut node, &params, sender.address());
+
+    let confirmed = signed_payment(&sender, &receiver, 0, &params);
+    let pending = signed_payment(&sender, &receiver, 1, &params);
+    assert!(node.submit_tx(confirmed.clone()).is_ok());
+    assert!(node.submit_tx(pending.clone()).is_ok());
+    assert!(node.dump_mempool(&path).is_ok());
+
+    let block = mine_on_top(node.get_history(), vec![confirmed.clone()]);
+    assert!(node.submit_block(block).is_ok());
+    let history = History::new(params.clone(), Box::new(NaiveReorgStrategy {}))
+        .choose_chain(node.get_history().get_chain())
+        .unwrap();
+    let mut restarted = Node::new(history, MemPool::new(1_000_000, params.clone()));
+
+    let dropped = restarted.load_mempool(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(dropped, Ok(dropped) if dropped == vec![confirmed]));
+    assert_eq!(1, restarted.get_mempool().len());
+    assert!(restarted.get_mempool().get_tx(&pending.id).is_some());
+}
+
+#[test]
+fn confirmed_txs_feed_the_fee_estimate() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    for _ in 0..2 {
+        fund(&mut node, &params, sender.address());
+    }
+    assert!(node.estimate_fee(1, 0.9).is_none());
+
+    for sequence in 0..5 {
+        let payment = signed_payment(&sender, &receiver, sequence, &params);
+        assert!(node.submit_tx(payment).is_ok());
+
+        let txs = node.take_txs_for_block(10);
+        let block = mine_on_top(node.get_history(), txs);
+        assert!(node.submit_block(block).is_ok());
+    }
+
+    let estimate = node.estimate_fee(1, 0.9).unwrap();
+    assert_eq!(1, estimate.target);
+    assert!(estimate.fee_per_kb <= signed_payment(&sender, &receiver, 0, &params).fee_per_kb());
+}
+
+#[test]
+fn subscribers_are_told_about_every_change_in_order() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    fund(&mut node, &params, sender.address());
+    let events = node.subscribe();
+
+    let mut fork = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    assert!(fork.try_to_append(node.get_history().get_last_block().unwrap().clone()).is_ok());
+    let payment = signed_payment(&sender, &receiver, 0, &params);
+    assert!(node.submit_tx(payment.clone()).is_ok());
+    let block = mine_on_top(node.get_history(), vec![payment.clone()]);
+    assert!(node.submit_block(block.clone()).is_ok());
+    for _ in 0..2 {
+        let block = mine_on_top(&fork, Vec::new());
+        assert!(fork.try_to_append(block).is_ok());
+    }
+    assert!(node.reorganize(fork.get_chain()).unwrap());
+
+    let events: Vec<ChainEvent> = events.try_iter().collect();
+    assert_eq!(7, events.len());
+    assert!(matches!(&events[0], ChainEvent::TxAccepted(tx) if tx.id == payment.id));
+    assert!(matches!(&events[1], ChainEvent::BlockConnected(connected) if connected.hash == block.hash));
+    assert!(matches!(&events[2], ChainEvent::BlockDisconnected(disconnected) if disconnected.hash == block.hash));
+    assert!(matches!(&events[3], ChainEvent::BlockConnected(connected) if connected.hash == fork.get_chain()[2].hash));
+    assert!(matches!(&events[4], ChainEvent::BlockConnected(connected) if connected.hash == fork.get_chain()[3].hash));
+    assert!(matches!(
+        &events[5],
+        ChainEvent::Reorganized { fork_height: 1, old_tip, disconnected, connected, .. }
+            if *old_tip == block.hash && disconnected.len() == 1 && connected.len() == 2
+    ));
+    assert!(matches!(&events[6], ChainEvent::TxAccepted(tx) if tx.id == payment.id));
+}
+
+#[test]
+fn forged_branch_is_refused_and_leaves_the_chain_untouched() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let victim = WalletKeyPair::new();
+    fund(&mut node, &params, victim.address());
+    let tip = node.get_history().get_last_block().unwrap().clone();
+
+    let theft = Transaction::new(victim.address(), "attacker".to_string(), 50, 0, 0, params.chain_id);
+    let mut forged = mine_on_top(node.get_history(), vec![theft]);
+    forged.hash = "zz-not-hex".to_string();
+    forged.difficulty = 0;
+    let mut next = forged.clone();
+    next.height += 1;
+    next.previous_hash = forged.hash.clone();
+    next.hash = "also-bogus".to_string();
+    let branch = [node.get_history().get_chain().to_vec(), vec![forged, next]].concat();
+
+    assert!(node.reorganize(&branch).is_err());
+    assert_eq!(params.block_reward(1), node.get_history().get_balance(&victim.address()));
+    assert_eq!(0, node.get_history().get_balance("attacker"));
+    assert_eq!(tip.hash, node.get_history().get_last_block().unwrap().hash);
+}
+
+#[test]
+fn replacement_drops_the_children_it_leaves_unfunded_so_blocks_still_connect() {
+    let params = test_params();
+    let mut node = new_node(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    fund(&mut node, &params, sender.address());
+
+    let first = signed_payment(&sender, &receiver, 0, &params);
+    let second = signed_payment(&sender, &receiver, 1, &params);
+    assert!(node.submit_tx(first).is_ok());
+    assert!(node.submit_tx(second.clone()).is_ok());
+
+    // Spends almost the whole reward, leaving nothing for the second payment
+    let mut replacement = Transaction::new(
+        sender.address(),
+        receiver.address(),
+        params.block_reward(1) - 3,
+        2,
+        0,
+        params.chain_id,
+    );
+    replacement.sign(&sender.secret_key);
+    assert!(node.submit_tx(replacement.clone()).is_ok());
+    assert!(node.get_mempool().get_tx(&second.id).is_none());
+
+    let txs = node.take_txs_for_block(10);
+    assert_eq!(vec![replacement.id], txs.iter().map(|tx| tx.id.clone()).collect::<Vec<_>>());
+    let block = mine_on_top(node.get_history(), txs);
+    assert!(node.submit_block(block).is_ok());
+}
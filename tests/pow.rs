@@ -1,18 +1,34 @@
+mod common;
+
 use chrono::Utc;
-use rust_chain::core::{mine_new_block, AppendToHistoryError, Block, History, NaiveReorgStrategy};
+use rust_chain::core::{
+    mine_new_block, AppendToHistoryError, Block, BlockValidationError, ConsensusParams, History,
+    NaiveReorgStrategy, Transaction, TransactionValidationError, WalletKeyPair,
+};
+
+use common::mine_on_top;
+
+fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        initial_difficulty: 8,
+        ..ConsensusParams::default()
+    }
+}
 
 #[test]
 fn create_chain_with_4_blocks() -> Result<(), AppendToHistoryError> {
-    let mut hs = History::new(Box::new(NaiveReorgStrategy {}));
+    let mut hs = History::new(test_params(), Box::new(NaiveReorgStrategy {}));
 
     for _ in 1..4 {
         let prev_block = hs.get_last_block().unwrap();
         let height = hs.get_height();
-        let timestamp = Utc::now().timestamp();
+        let timestamp = Utc::now().timestamp().max(hs.median_time_past() + 1);
+        let difficulty = hs.next_difficulty();
         let txs = Vec::new();
 
-        let (nonce, hash) = mine_new_block(height as u64, timestamp, &prev_block.hash, &txs);
-        let new_block = Block::new(prev_block, hash, timestamp, txs, nonce);
+        let (nonce, hash) =
+            mine_new_block(height as u64, timestamp, &prev_block.hash, &txs, difficulty);
+        let new_block = Block::new(prev_block, hash, timestamp, txs, difficulty, nonce);
 
         match hs.try_to_append(new_block) {
             Ok(_) => println!("Block appended successfully"),
@@ -26,15 +42,16 @@ fn create_chain_with_4_blocks() -> Result<(), AppendToHistoryError> {
 
 #[test]
 fn append_bad_block_to_history_throw_error() -> Result<(), AppendToHistoryError> {
-    let mut hs = History::new(Box::new(NaiveReorgStrategy {}));
+    let mut hs = History::new(test_params(), Box::new(NaiveReorgStrategy {}));
 
     let prev_block = hs.get_last_block().unwrap();
     let height = hs.get_height();
-    let timestamp = Utc::now().timestamp();
+    let timestamp = Utc::now().timestamp().max(hs.median_time_past() + 1);
+    let difficulty = hs.next_difficulty();
     let txs = Vec::new();
 
-    let (nonce, hash) = mine_new_block(height as u64, timestamp, &prev_block.hash, &txs);
-    let new_block = Block::new(prev_block, hash, timestamp, txs, nonce);
+    let (nonce, hash) = mine_new_block(height as u64, timestamp, &prev_block.hash, &txs, difficulty);
+    let new_block = Block::new(prev_block, hash, timestamp, txs, difficulty, nonce);
 
     match hs.try_to_append(new_block) {
         Ok(_) => println!("Block appended successfully"),
@@ -49,12 +66,95 @@ fn append_bad_block_to_history_throw_error() -> Result<(), AppendToHistoryError>
         "fake-hash".to_string(),
         Utc::now().timestamp(),
         Vec::new(),
+        hs.next_difficulty(),
         0u64,
     );
     match hs.try_to_append(bad_block) {
         Ok(_) => panic!("Block appended successfully"),
-        Err(e) => assert_eq!("Cannot append block to history", e.to_string()),
+        Err(e) => assert!(matches!(
+            e,
+            AppendToHistoryError::InvalidBlock(BlockValidationError::MalformedHash(_))
+        )),
     }
 
     Ok(())
 }
+
+#[test]
+fn append_tampered_block_to_history_is_rejected_with_hash_mismatch() {
+    let mut hs = History::new(test_params(), Box::new(NaiveReorgStrategy {}));
+
+    let prev_block = hs.get_last_block().unwrap();
+    let timestamp = hs.median_time_past() + 1;
+    let difficulty = hs.next_difficulty();
+    let txs = Vec::new();
+
+    let (nonce, hash) = mine_new_block(1, timestamp, &prev_block.hash, &txs, difficulty);
+    let mut tampered_block = Block::new(prev_block, hash, timestamp, txs, difficulty, nonce);
+    tampered_block.timestamp += 1;
+
+    let res = hs.try_to_append(tampered_block);
+
+    assert!(matches!(
+        res,
+        Err(AppendToHistoryError::InvalidBlock(BlockValidationError::HashMismatch { .. }))
+    ));
+    assert_eq!(1, hs.get_height());
+}
+
+#[test]
+fn block_spending_a_mined_reward_goes_through_every_validation_stage() {
+    let params = ConsensusParams {
+        coinbase_maturity: 0,
+        ..test_params()
+    };
+    let mut hs = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    let miner = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+
+    let reward = Transaction::coinbase(miner.address(), params.block_reward(1), 1, params.chain_id);
+    let first_block = mine_on_top(&hs, vec![reward]);
+    assert!(hs.try_to_append(first_block).is_ok());
+    assert_eq!(params.block_reward(1), hs.get_balance(&miner.address()));
+
+    let mut unsigned_tx =
+        Transaction::new(miner.address(), receiver.address(), 10, 1, 0, params.chain_id);
+    let unsigned_block = mine_on_top(&hs, vec![unsigned_tx.clone()]);
+    assert!(matches!(
+        unsigned_block.check_context_free(&params),
+        Err(BlockValidationError::InvalidTransaction {
+            source: TransactionValidationError::MissingSignature { .. },
+            ..
+        })
+    ));
+
+    unsigned_tx.sign(&miner.secret_key);
+    let signed_block = mine_on_top(&hs, vec![unsigned_tx]);
+    assert!(signed_block.check_context_free(&params).is_ok());
+    assert!(signed_block
+        .check_contextual(&hs.block_context().unwrap(), &params)
+        .is_ok());
+    assert!(hs.try_to_append(signed_block).is_ok());
+
+    assert_eq!(params.block_reward(1) - 11, hs.get_balance(&miner.address()));
+    assert_eq!(10, hs.get_balance(&receiver.address()));
+}
+
+#[test]
+fn genesis_hash_is_the_one_of_its_header_and_depends_on_the_params() {
+    let params = test_params();
+    let genesis = Block::genesis(&params);
+
+    assert_eq!(genesis.header().hash(), genesis.hash);
+    assert_eq!(genesis.hash, Block::genesis(&params).hash);
+    let other_chain = ConsensusParams {
+        chain_id: params.chain_id + 1,
+        ..params.clone()
+    };
+    let other_timestamp = ConsensusParams {
+        genesis_timestamp: params.genesis_timestamp + 1,
+        ..params.clone()
+    };
+    assert_ne!(genesis.hash, Block::genesis(&other_chain).hash);
+    assert_ne!(genesis.hash, Block::genesis(&other_timestamp).hash);
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+
+use rust_chain::{
+    core::{Block, ConsensusParams, Node, Transaction, WalletKeyPair},
+    network::P2pConfig,
+    rpc::{RpcConfig, RpcError, RpcServer, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, TX_REJECTED},
+};
+
+use common::{mine_reward_on_top, new_node, start_p2p, test_params, wait_until};
+
+const TOKEN: &str = "secret-token";
+
+fn config() -> RpcConfig {
+    RpcConfig {
+        auth_token: TOKEN.to_string(),
+        ..RpcConfig::default()
+    }
+}
+
+fn start_server(params: &ConsensusParams) -> (Arc<Mutex<Node>>, RpcServer) {
+    let node = Arc::new(Mutex::new(new_node(params)));
+    let server = RpcServer::start(Arc::clone(&node), "127.0.0.1:0", config()).unwrap();
+    (node, server)
+}
+
+fn reward_block(node: &Node, params: &ConsensusParams, address: String) -> Block {
+    mine_reward_on_top(node.get_history(), params, address, Vec::new())
+}
+
+/// Sends a raw HTTP request and returns the status and body of the response.
+fn http_post(addr: SocketAddr, token: Option<&str>, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let authorization = token.map_or(String::new(), |token| format!("Authorization: Bearer {}\r\n", token));
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        authorization,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+fn rpc(addr: SocketAddr, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let (status, body) = http_post(addr, Some(TOKEN), &request.to_string());
+    assert_eq!(200, status);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn requests_without_the_token_are_refused() {
+    let params = test_params();
+    let (_node, server) = start_server(&params);
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "getblockcount" }).to_string();
+
+    assert_eq!(401, http_post(server.local_addr(), None, &request).0);
+    assert_eq!(401, http_post(server.local_addr(), Some("wrong-token"), &request).0);
+    assert_eq!(200, http_post(server.local_addr(), Some(TOKEN), &request).0);
+}
+
+#[test]
+fn node_is_queried_and_fed_through_rpc() {
+    let params = test_params();
+    let (node, server) = start_server(&params);
+    let addr = server.local_addr();
+    let sender = WalletKeyPair::new();
+
+    let block = reward_block(&node.lock().unwrap(), &params, sender.address());
+    assert_eq!(json!(null), rpc(addr, "submitblock", json!([block]))["result"]);
+    assert_eq!(json!(1), rpc(addr, "getblockcount", json!([]))["result"]);
+    assert_eq!(json!(block.hash), rpc(addr, "getbestblockhash", json!([]))["result"]);
+    assert_eq!(
+        json!(block.hash),
+        rpc(addr, "getblock", json!([block.hash]))["result"]["hash"]
+    );
+    assert_eq!(
+        json!(params.block_reward(1)),
+        rpc(addr, "getbalance", json!({ "address": sender.address() }))["result"]
+    );
+
+    let mut tx = Transaction::new(
+        sender.address(),
+        WalletKeyPair::new().address(),
+        10,
+        1,
+        0,
+        params.chain_id,
+    );
+    tx.sign(&sender.secret_key);
+    assert_eq!(json!(tx.id), rpc(addr, "sendrawtransaction", json!([tx]))["result"]);
+    assert_eq!(json!(1), rpc(addr, "getmempoolinfo", json!([]))["result"]["tx_count"]);
+    assert!(node.lock().unwrap().get_mempool().get_tx(&tx.id).is_some());
+
+    let rejected = rpc(addr, "sendrawtransaction", json!([tx]));
+    assert_eq!(json!(TX_REJECTED), rejected["error"]["code"]);
+}
+
+#[test]
+fn batches_notifications_and_bad_requests_follow_json_rpc() {
+    let params = test_params();
+    let (_node, server) = start_server(&params);
+    let addr = server.local_addr();
+
+    let batch = json!([
+        { "jsonrpc": "2.0", "id": "a", "method": "getblockcount" },
+        { "jsonrpc": "2.0", "method": "getblockcount" },
+        { "jsonrpc": "2.0", "id": 2, "method": "unknown" },
+        { "id": 3, "method": "getblockcount" },
+    ]);
+    let (status, body) = http_post(addr, Some(TOKEN), &batch.to_string());
+    let responses: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(200, status);
+    assert_eq!(3, responses.as_array().unwrap().len());
+    assert_eq!(json!({ "jsonrpc": "2.0", "result": 0, "id": "a" }), responses[0]);
+    assert_eq!(json!(METHOD_NOT_FOUND), responses[1]["error"]["code"]);
+    assert_eq!(json!(INVALID_REQUEST), responses[2]["error"]["code"]);
+
+    let notification = json!({ "jsonrpc": "2.0", "method": "getblockcount" });
+    assert_eq!(204, http_post(addr, Some(TOKEN), &notification.to_string()).0);
+
+    let (_, body) = http_post(addr, Some(TOKEN), "{ not json");
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json!(PARSE_ERROR), response["error"]["code"]);
+    assert_eq!(Value::Null, response["id"]);
+}
+
+#[test]
+fn server_only_listens_on_localhost_and_hands_its_token_through_a_cookie() {
+    let params = test_params();
+    let node = Arc::new(Mutex::new(new_node(&params)));
+    let cookie_path = env::temp_dir().join(format!("rust-chain-rpc-cookie-{}", std::process::id()));
+
+    assert!(matches!(
+        RpcServer::start(Arc::clone(&node), "0.0.0.0:0", RpcConfig::default()),
+        Err(RpcError::NonLocalAddress { .. })
+    ));
+
+    let server = RpcServer::start(
+        node,
+        "127.0.0.1:0",
+        RpcConfig {
+            cookie_path: Some(cookie_path.clone()),
+            ..RpcConfig::default()
+        },
+    )
+    .unwrap();
+    let token = fs::read_to_string(&cookie_path).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(0o600, fs::metadata(&cookie_path).unwrap().permissions().mode() & 0o777);
+    }
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "getblockcount" }).to_string();
+    assert_eq!(200, http_post(server.local_addr(), Some(&token), &request).0);
+
+    server.shutdown();
+    assert!(!cookie_path.exists());
+}
+
+#[test]
+fn stop_method_shuts_the_server_down_once_answered() {
+    let params = test_params();
+    let node = Arc::new(Mutex::new(new_node(&params)));
+    let cookie_path = env::temp_dir().join(format!("rust-chain-rpc-stop-cookie-{}", std::process::id()));
+    let server = RpcServer::start(
+        node,
+        "127.0.0.1:0",
+        RpcConfig {
+            cookie_path: Some(cookie_path.clone()),
+            ..config()
+        },
+    )
+    .unwrap();
+
+    let response = rpc(server.local_addr(), "stop", json!([]));
+    server.wait();
+
+    assert_eq!(Value::Null, response["result"]);
+    assert!(!server.is_running());
+    assert!(!cookie_path.exists());
+}
+
+#[test]
+fn block_submitted_to_a_p2p_node_is_relayed_to_its_peers() {
+    let params = test_params();
+    let p2p = Arc::new(start_p2p(&params, P2pConfig::default()));
+    let peer = start_p2p(&params, P2pConfig::default());
+    assert!(p2p.connect(peer.local_addr()).is_ok());
+    let server = RpcServer::start(Arc::clone(&p2p), "127.0.0.1:0", config()).unwrap();
+
+    let block = p2p.with_node(|node| reward_block(node, &params, WalletKeyPair::new().address()));
+    assert_eq!(
+        json!(null),
+        rpc(server.local_addr(), "submitblock", json!([block]))["result"]
+    );
+
+    assert!(wait_until(
+        || peer.with_node(|node| node.get_history().get_block(&block.hash).is_some())
+    ));
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use std::time::Duration;
+
+use rust_chain::{
+    core::{Block, ConsensusParams, NaiveReorgStrategy, ReorgChainStrategy, ReorgChoice},
+    network::{SimulationReport, Simulator, SimulatorConfig},
+};
+
+fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        retarget_interval: 0,
+        ..common::test_params()
+    }
+}
+
+/// Keeps the first chain seen on ties, unlike `NaiveReorgStrategy`.
+#[derive(Clone)]
+struct FirstSeenStrategy;
+impl ReorgChainStrategy for FirstSeenStrategy {
+    fn choose_chain(&self, first_chain: &[Block], second_chain: &[Block]) -> ReorgChoice {
+        if second_chain.len() > first_chain.len() {
+            return ReorgChoice::Second;
+        }
+
+        ReorgChoice::First
+    }
+
+    fn clone_dyn(&self) -> Box<dyn ReorgChainStrategy> {
+        Box::new(self.clone())
+    }
+}
+
+fn simulate(
+    strategy: Box<dyn ReorgChainStrategy>,
+    hash_rates: &[f64],
+    config: SimulatorConfig,
+    scenario: impl FnOnce(&mut Simulator),
+    duration: Duration,
+) -> SimulationReport {
+    let mut simulator = Simulator::new(test_params(), strategy, config);
+    for hash_rate in hash_rates {
+        simulator.add_node(*hash_rate);
+    }
+    simulator.connect_all();
+    scenario(&mut simulator);
+    simulator.run(duration)
+}
+
+fn high_latency() -> SimulatorConfig {
+    SimulatorConfig {
+        seed: 3,
+        min_latency: Duration::from_secs(20),
+        max_latency: Duration::from_secs(40),
+        ..SimulatorConfig::default()
+    }
+}
+
+#[test]
+fn well_connected_nodes_reach_consensus_with_few_stale_blocks() {
+    let report = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[1.0; 5],
+        SimulatorConfig::default(),
+        |_| {},
+        Duration::from_secs(3 * 60 * 60),
+    );
+
+    assert!(report.consensus());
+    assert!(report.blocks_mined > 100);
+    assert!(report.orphan_rate() < 0.05);
+    assert_eq!(report.blocks_mined - report.stale_blocks, report.best_height as usize);
+}
+
+#[test]
+fn latency_close_to_the_block_time_makes_blocks_stale() {
+    let fast = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[1.0; 5],
+        SimulatorConfig {
+            seed: 3,
+            ..SimulatorConfig::default()
+        },
+        |_| {},
+        Duration::from_secs(3 * 60 * 60),
+    );
+    let slow = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[1.0; 5],
+        high_latency(),
+        |_| {},
+        Duration::from_secs(3 * 60 * 60),
+    );
+
+    assert!(slow.orphan_rate() > fast.orphan_rate());
+    assert!(slow.max_reorg_depth() >= 1);
+}
+
+#[test]
+fn minority_side_of_a_partition_is_reorganized_once_healed() {
+    let report = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[3.0, 3.0, 1.0, 1.0],
+        SimulatorConfig::default(),
+        |simulator| {
+            simulator.partition(Duration::ZERO, &[vec![0, 1], vec![2, 3]]);
+            simulator.heal(Duration::from_secs(60 * 60));
+        },
+        Duration::from_secs(2 * 60 * 60),
+    );
+
+    assert!(report.consensus());
+    assert!(report.max_reorg_depth() >= 5);
+    assert!(report.orphan_rate() > 0.1);
+}
+
+#[test]
+fn nodes_never_hearing_from_each_other_do_not_reach_consensus() {
+    let report = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[1.0; 3],
+        SimulatorConfig {
+            loss_rate: 1.0,
+            ..SimulatorConfig::default()
+        },
+        |_| {},
+        Duration::from_secs(30 * 60),
+    );
+
+    assert!(!report.consensus());
+    assert_eq!(report.messages_sent, report.messages_lost);
+    assert!(report.reorg_depths.is_empty());
+}
+
+#[test]
+fn keeping_the_first_chain_seen_avoids_switching_between_tied_branches() {
+    let naive = simulate(
+        Box::new(NaiveReorgStrategy {}),
+        &[1.0; 5],
+        high_latency(),
+        |_| {},
+        Duration::from_secs(3 * 60 * 60),
+    );
+    let first_seen = simulate(
+        Box::new(FirstSeenStrategy),
+        &[1.0; 5],
+        high_latency(),
+        |_| {},
+        Duration::from_secs(3 * 60 * 60),
+    );
+
+    assert!(first_seen.consensus());
+    assert!(first_seen.reorg_depths.len() < naive.reorg_depths.len());
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use rust_chain::{
+    core::{ConsensusParams, History, NaiveReorgStrategy, Node, Transaction, WalletKeyPair},
+    rpc::{accept_key, write_frame, Frame, FrameReader, RpcConfig, RpcServer},
+};
+
+use common::{mine_reward_on_top, new_node, test_params};
+
+const TOKEN: &str = "secret-token";
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn start_server(params: &ConsensusParams) -> (Arc<Mutex<Node>>, RpcServer) {
+    let node = Arc::new(Mutex::new(new_node(params)));
+    let config = RpcConfig {
+        auth_token: TOKEN.to_string(),
+        ..RpcConfig::default()
+    };
+    let server = RpcServer::start(Arc::clone(&node), "127.0.0.1:0", config).unwrap();
+    (node, server)
+}
+
+/// Rewards `sender` in a new block and returns a payment it signed.
+fn funded_payment(node: &Mutex<Node>, params: &ConsensusParams, sender: &WalletKeyPair, to: String) -> Transaction {
+    let mut node = node.lock().unwrap();
+    let block = mine_reward_on_top(node.get_history(), params, sender.address(), Vec::new());
+    assert!(node.submit_block(block).is_ok());
+
+    let mut tx = Transaction::new(sender.address(), to, 10, 1, 0, params.chain_id);
+    tx.sign(&sender.secret_key);
+    tx
+}
+
+/// Sends the upgrade request and returns the status of the response and the
+/// connection, ready for frames if it switched to WebSocket.
+fn websocket(addr: SocketAddr, token: Option<&str>) -> (u16, TcpStream) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let authorization = token.map_or(String::new(), |token| format!("Authorization: Bearer {}\r\n", token));
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\n{}Upgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        authorization, KEY
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    // Reads the headers byte by byte, so that no frame is read with them
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8(response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    if status == 101 {
+        assert!(response.contains(&format!("Sec-WebSocket-Accept: {}\r\n", accept_key(KEY))));
+    }
+    (status, stream)
+}
+
+fn send(stream: &mut TcpStream, method: &str, params: Value) {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    write_frame(stream, &Frame::Text(request.to_string()), Some([1, 2, 3, 4])).unwrap();
+}
+
+fn receive(stream: &mut TcpStream) -> Value {
+    match FrameReader::new(1_000_000, false).read(stream).unwrap() {
+        Frame::Text(text) => serde_json::from_str(&text).unwrap(),
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+}
+
+fn call(stream: &mut TcpStream, method: &str, params: Value) -> Value {
+    send(stream, method, params);
+    receive(stream)["result"].clone()
+}
+
+/// Subscription id, topic and result of the next notification.
+fn notification(stream: &mut TcpStream) -> (Value, Value, Value) {
+    let notification = receive(stream);
+    assert_eq!(json!("subscription"), notification["method"]);
+    let params = &notification["params"];
+    (
+        params["subscription"].clone(),
+        params["topic"].clone(),
+        params["result"].clone(),
+    )
+}
+
+#[test]
+fn upgrade_needs_the_auth_token() {
+    let params = test_params();
+    let (_node, server) = start_server(&params);
+
+    assert_eq!(401, websocket(server.local_addr(), None).0);
+    assert_eq!(401, websocket(server.local_addr(), Some("wrong-token")).0);
+    let (status, mut stream) = websocket(server.local_addr(), Some(TOKEN));
+    assert_eq!(101, status);
+
+    assert_eq!(json!(0), call(&mut stream, "getblockcount", json!([])));
+    write_frame(&mut stream, &Frame::Ping(b"ping".to_vec()), Some([4, 3, 2, 1])).unwrap();
+    let mut frames = FrameReader::new(1_000, false);
+    assert_eq!(Frame::Pong(b"ping".to_vec()), frames.read(&mut stream).unwrap());
+    write_frame(&mut stream, &Frame::Close, Some([4, 3, 2, 1])).unwrap();
+    assert_eq!(Frame::Close, frames.read(&mut stream).unwrap());
+}
+
+#[test]
+fn subscribers_are_notified_of_blocks_txs_and_address_activity() {
+    let params = test_params();
+    let (node, server) = start_server(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    let payment = funded_payment(&node, &params, &sender, receiver.address());
+    let (_, mut stream) = websocket(server.local_addr(), Some(TOKEN));
+
+    let blocks = call(&mut stream, "subscribe", json!(["blocks"]));
+    let txs = call(&mut stream, "subscribe", json!({ "topic": "txs" }));
+    let address = call(&mut stream, "subscribe", json!(["address", receiver.address()]));
+    let unused = call(&mut stream, "subscribe", json!(["txs"]));
+    assert_eq!(json!(true), call(&mut stream, "unsubscribe", json!([unused])));
+    assert_eq!(json!(false), call(&mut stream, "unsubscribe", json!([unused])));
+
+    assert!(node.lock().unwrap().submit_tx(payment.clone()).is_ok());
+    assert_eq!((txs.clone(), json!("txs"), json!(payment)), notification(&mut stream));
+    let (id, _, result) = notification(&mut stream);
+    assert_eq!(address, id);
+    assert_eq!(json!({ "status": "pending", "tx": payment }), result);
+
+    let block = {
+        let mut node = node.lock().unwrap();
+        let block = mine_reward_on_top(node.get_history(), &params, sender.address(), vec![payment.clone()]);
+        assert!(node.submit_block(block.clone()).is_ok());
+        block
+    };
+    let (id, topic, result) = notification(&mut stream);
+    assert_eq!((blocks, json!("blocks")), (id, topic));
+    assert_eq!(json!(block.hash), result["hash"]);
+    assert_eq!(json!(block.merkle_root), result["merkle_root"]);
+    let (id, _, result) = notification(&mut stream);
+    assert_eq!(address, id);
+    assert_eq!(json!("confirmed"), result["status"]);
+    assert_eq!(json!(block.hash), result["block_hash"]);
+}
+
+#[test]
+fn reorgs_are_notified_with_the_blocks_they_switch() {
+    let params = test_params();
+    let (node, server) = start_server(&params);
+    let sender = WalletKeyPair::new();
+    let receiver = WalletKeyPair::new();
+    let payment = funded_payment(&node, &params, &sender, receiver.address());
+
+    let mut fork = History::new(params.clone(), Box::new(NaiveReorgStrategy {}));
+    let block = {
+        let mut node = node.lock().unwrap();
+        assert!(fork
+            .try_to_append(node.get_history().get_last_block().unwrap().clone())
+            .is_ok());
+        assert!(node.submit_tx(payment.clone()).is_ok());
+        let block = mine_reward_on_top(node.get_history(), &params, sender.address(), vec![payment.clone()]);
+        assert!(node.submit_block(block.clone()).is_ok());
+        block
+    };
+    for _ in 0..2 {
+        let block = mine_reward_on_top(&fork, &params, WalletKeyPair::new().address(), Vec::new());
+        assert!(fork.try_to_append(block).is_ok());
+    }
+
+    let (_, mut stream) = websocket(server.local_addr(), Some(TOKEN));
+    let address = call(&mut stream, "subscribe", json!(["address", receiver.address()]));
+    let reorgs = call(&mut stream, "subscribe", json!(["reorgs"]));
+    assert!(node.lock().unwrap().reorganize(fork.get_chain()).unwrap());
+
+    let (id, _, result) = notification(&mut stream);
+    assert_eq!(address, id);
+    assert_eq!(json!("unconfirmed"), result["status"]);
+    assert_eq!(json!(block.hash), result["block_hash"]);
+    let (id, topic, result) = notification(&mut stream);
+    assert_eq!((reorgs, json!("reorgs")), (id, topic));
+    assert_eq!(
+        json!({
+            "fork_height": 1,
+            "old_tip": block.hash,
+            "new_tip": fork.get_last_block().unwrap().hash,
+            "disconnected": [block.hash],
+            "connected": [fork.get_chain()[2].hash, fork.get_chain()[3].hash],
+        }),
+        result
+    );
+    let (id, _, result) = notification(&mut stream);
+    assert_eq!(address, id);
+    assert_eq!(json!({ "status": "pending", "tx": payment }), result);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::{env, fs, net::TcpListener, thread, time::Duration};
+
+use rust_chain::{
+    core::{ConsensusParams, Node},
+    network::{headers_after, Message, P2pConfig, P2pNode, PeerConnection, SyncConfig, VersionMessage},
+};
+
+use common::{local_version, mine_on_top, new_node, test_params, wait_until};
+
+fn node_with_blocks(params: &ConsensusParams, count: usize) -> Node {
+    let mut node = new_node(params);
+    for _ in 0..count {
+        let block = mine_on_top(node.get_history(), Vec::new());
+        assert!(node.submit_block(block).is_ok());
+    }
+    node
+}
+
+fn sync_config() -> P2pConfig {
+    P2pConfig {
+        tick_interval: Duration::from_millis(50),
+        sync: SyncConfig {
+            max_headers_per_message: 10,
+            max_blocks_in_flight_per_peer: 4,
+            block_timeout: Duration::from_millis(300),
+            ..SyncConfig::default()
+        },
+        ..P2pConfig::default()
+    }
+}
+
+fn start(node: Node) -> P2pNode {
+    P2pNode::start(node, "127.0.0.1:0", sync_config()).unwrap()
+}
+
+fn tip_hash(p2p: &P2pNode) -> String {
+    p2p.with_node(|node| node.get_history().get_last_block().unwrap().hash.clone())
+}
+
+#[test]
+fn fresh_node_downloads_the_chain_from_several_peers() {
+    let params = test_params();
+    let a = start(node_with_blocks(&params, 25));
+    // Mining is deterministic, both peers have the same chain
+    let b = start(node_with_blocks(&params, 25));
+    assert_eq!(tip_hash(&a), tip_hash(&b));
+    let fresh = start(new_node(&params));
+
+    assert!(fresh.connect(a.local_addr()).is_ok());
+    assert!(fresh.connect(b.local_addr()).is_ok());
+
+    assert!(wait_until(|| tip_hash(&fresh) == tip_hash(&a)));
+    assert_eq!(26, fresh.with_node(|node| node.get_history().get_height()));
+    assert_eq!(25, fresh.sync_status().blocks_downloaded);
+}
+
+#[test]
+fn blocks_requested_from_a_stalled_peer_are_downloaded_from_another_one() {
+    let params = test_params();
+    let honest = start(node_with_blocks(&params, 25));
+    let source_headers = honest.with_node(|node| headers_after(node.get_history(), &[], 2000));
+
+    // Announces the chain of the honest node but never sends any block
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stalling_addr = listener.local_addr().unwrap();
+    let version = VersionMessage {
+        best_height: 25,
+        ..local_version(&params)
+    };
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let peer = PeerConnection::handshake(stream, &version, 4 * 1024 * 1024, Duration::from_secs(5)).unwrap();
+        while let Ok(message) = peer.receive() {
+            if let Message::GetHeaders(_) = message {
+                let _ = peer.send(&Message::Headers(source_headers[..9].to_vec()));
+            }
+        }
+    });
+
+    let fresh = start(new_node(&params));
+    assert!(fresh.connect(stalling_addr).is_ok());
+    assert!(wait_until(|| fresh.sync_status().blocks_in_flight > 0));
+    assert!(fresh.connect(honest.local_addr()).is_ok());
+
+    assert!(wait_until(|| tip_hash(&fresh) == tip_hash(&honest)));
+    assert!(wait_until(|| fresh.peer_count() == 1));
+}
+
+#[test]
+fn sync_resumes_from_the_chain_saved_before_restart() {
+    let params = test_params();
+    let path = env::temp_dir().join(format!("rust-chain-sync-{}.json", std::process::id()));
+    let source = start(node_with_blocks(&params, 20));
+
+    let first_run = start(new_node(&params));
+    assert!(first_run.connect(source.local_addr()).is_ok());
+    assert!(wait_until(|| tip_hash(&first_run) == tip_hash(&source)));
+    assert!(first_run.with_node(|node| node.dump_chain(&path)).is_ok());
+    drop(first_run);
+
+    for _ in 0..5 {
+        let block = source.with_node(|node| mine_on_top(node.get_history(), Vec::new()));
+        assert!(source.submit_block(block).is_ok());
+    }
+
+    let mut node = new_node(&params);
+    let loaded = node.load_chain(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(loaded, Ok(20)));
+    let second_run = start(node);
+    assert!(second_run.connect(source.local_addr()).is_ok());
+
+    assert!(wait_until(|| tip_hash(&second_run) == tip_hash(&source)));
+    assert_eq!(5, second_run.sync_status().blocks_downloaded);
+}
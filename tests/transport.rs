@@ -0,0 +1,86 @@
+mod common;
+
+use std::{net::TcpStream, time::Duration};
+
+use rust_chain::{
+    core::WalletKeyPair,
+    network::{NetworkError, NodeIdentity, P2pConfig, PeerConnection},
+};
+
+use common::{local_version, mine_reward_on_top, start_p2p, test_params, wait_until};
+
+fn encrypted_config() -> P2pConfig {
+    P2pConfig {
+        node_identity: Some(NodeIdentity::generate()),
+        ..P2pConfig::default()
+    }
+}
+
+#[test]
+fn encrypted_nodes_relay_blocks_and_know_the_key_of_each_other() {
+    let params = test_params();
+    let a = start_p2p(&params, encrypted_config());
+    let b = start_p2p(&params, encrypted_config());
+
+    assert!(a.connect_pinned(b.local_addr(), &b.node_key().unwrap()).is_ok());
+    assert!(wait_until(|| a.peer_count() == 1 && b.peer_count() == 1));
+    assert_eq!(b.node_key(), a.peer_keys().first().map(|(_, key)| *key));
+    assert_eq!(a.node_key(), b.peer_keys().first().map(|(_, key)| *key));
+
+    let miner = WalletKeyPair::new().address();
+    let block = a.with_node(|node| mine_reward_on_top(node.get_history(), &params, miner, Vec::new()));
+    let hash = block.hash.clone();
+    assert!(a.submit_block(block).is_ok());
+
+    assert!(wait_until(
+        || b.with_node(|node| node.get_history().get_block(&hash).is_some())
+    ));
+}
+
+#[test]
+fn peer_with_another_key_than_the_pinned_one_is_refused() {
+    let params = test_params();
+    let a = start_p2p(&params, encrypted_config());
+    let b = start_p2p(&params, encrypted_config());
+    let other_key = NodeIdentity::generate().public_key();
+
+    let res = a.connect_pinned(b.local_addr(), &other_key);
+
+    assert!(matches!(
+        res,
+        Err(NetworkError::UnexpectedPeerKey { expected, found })
+            if expected == other_key.to_string() && found == b.node_key().unwrap().to_string()
+    ));
+    assert_eq!(0, a.peer_count());
+    assert!(wait_until(|| b.peer_count() == 0));
+}
+
+#[test]
+fn plaintext_peers_cannot_connect_to_an_encrypted_node() {
+    let params = test_params();
+    let node = start_p2p(&params, encrypted_config());
+    let plaintext = start_p2p(&params, P2pConfig::default());
+
+    let stream = TcpStream::connect(node.local_addr()).unwrap();
+    let res = PeerConnection::handshake(stream, &local_version(&params), 4 * 1024 * 1024, Duration::from_secs(5));
+    assert!(res.is_err());
+    assert!(node.connect(plaintext.local_addr()).is_err());
+    assert!(matches!(
+        plaintext.connect_pinned(node.local_addr(), &node.node_key().unwrap()),
+        Err(NetworkError::EncryptionDisabled)
+    ));
+
+    let stream = TcpStream::connect(node.local_addr()).unwrap();
+    let identity = NodeIdentity::generate();
+    let peer = PeerConnection::handshake_encrypted(
+        stream,
+        &local_version(&params),
+        4 * 1024 * 1024,
+        Duration::from_secs(5),
+        &identity,
+        node.node_key().as_ref(),
+    )
+    .unwrap();
+    assert_eq!(node.node_key().as_ref(), peer.remote_key());
+    assert!(wait_until(|| node.peer_count() == 1 && plaintext.peer_count() == 0));
+}
@@ -1,4 +1,4 @@
-use rust_chain::core::{Transaction, WalletKeyPair};
+use rust_chain::core::{Transaction, TransactionValidationError, WalletKeyPair};
 
 #[test]
 fn verify_correct_tx_signature() {
@@ -9,6 +9,8 @@ fn verify_correct_tx_signature() {
         "to-address".to_string(),
         12345,
         100,
+        0,
+        1,
     );
     tx.sign(&key_pair.secret_key);
 
@@ -24,9 +26,33 @@ fn verify_empty_tx_signature_return_error() {
         "to-address".to_string(),
         12345,
         100,
+        0,
+        1,
     );
 
     assert!(tx.verify_signature(&key_pair.public_key).is_err_and(
-        |e| e.to_string() == format!("Transaction {} has an empty signature", tx.nonce)
+        |e| e.to_string() == format!("Transaction {} has an empty signature", tx.id)
+    ));
+}
+
+#[test]
+fn signed_tx_replayed_on_another_chain_is_rejected() {
+    let key_pair = WalletKeyPair::new();
+
+    let mut tx = Transaction::new(key_pair.address(), "to-address".to_string(), 12345, 100, 0, 1);
+    tx.sign(&key_pair.secret_key);
+    assert!(tx.verify_for_chain(1).is_ok());
+
+    assert!(matches!(
+        tx.verify_for_chain(2),
+        Err(TransactionValidationError::WrongChain { expected: 2, found: 1 })
+    ));
+
+    let mut replayed_tx = tx.clone();
+    replayed_tx.chain_id = 2;
+    replayed_tx.id = hex::encode(replayed_tx.to_hash());
+    assert!(matches!(
+        replayed_tx.verify_for_chain(2),
+        Err(TransactionValidationError::BadSignature(_))
     ));
 }